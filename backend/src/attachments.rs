@@ -0,0 +1,127 @@
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+use chrono::{Datelike, Utc};
+
+// 첨부 파일 올리기, 내려받기에 필요한 것들. 저장은 storage.rs
+
+// 저장소의 key. 한 디렉터리에 파일이 너무 많아지지 않도록 올린 달로 나눈다 (ex. 2024/01/3f2a…)
+pub fn storage_key() -> String {
+    let now = Utc::now();
+    format!("{:04}/{:02}/{}", now.year(), now.month(), uuid::Uuid::new_v4().simple())
+}
+
+// ATTACHMENTS.ALLOWED_TYPES 중 하나와 같거나, image/* 처럼 종류가 같으면 허용
+pub fn is_allowed(content_type: &str, allowed_types: &[String]) -> bool {
+    allowed_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+        Some(kind) => content_type.split('/').next() == Some(kind),
+        None => allowed == content_type
+    })
+}
+
+// 클라이언트가 보낸 파일 이름에서 경로와 제어 문자를 빼고 255자로 자른다
+pub fn clean_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars()
+        .filter(|c| !c.is_control())
+        .take(255)
+        .collect();
+
+    match name.trim() {
+        "" | "." | ".." => "file".to_string(),
+        name => name.to_string()
+    }
+}
+
+// 한글 같은 이름은 filename*(UTF-8)으로, 예전 클라이언트를 위해 filename에는 ASCII만 남긴 이름을 넣는다
+pub fn content_disposition(filename: &str) -> ContentDisposition {
+    let ascii: String = filename.chars()
+        .map(|c| if c.is_ascii() && c != '"' { c } else { '_' })
+        .collect();
+
+    let mut parameters = vec![DispositionParam::Filename(ascii)];
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec()
+        }));
+    }
+
+    ContentDisposition { disposition: DispositionType::Attachment, parameters }
+}
+
+pub enum ByteRange {
+    // 전체를 보낸다 (200)
+    Full,
+    // (처음, 끝) byte. 끝도 포함 (206)
+    Partial(u64, u64),
+    // 파일 크기를 벗어난 범위 (416)
+    Unsatisfiable
+}
+
+// Range: bytes=0-99, bytes=100-, bytes=-100 (마지막 100 byte)
+// 여러 범위(bytes=0-9,20-29)나 알 수 없는 형식은 무시하고 전체를 보낸다 (RFC 9110에서 허용)
+pub fn parse_range(header: Option<&str>, size: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(length) => (size.saturating_sub(length), size.saturating_sub(1)),
+            Err(_) => return ByteRange::Full
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, size.saturating_sub(1)),
+            Err(_) => return ByteRange::Full
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            _ => return ByteRange::Full
+        }
+    };
+
+    if size == 0 || start >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_header_or_unsupported_range_sends_everything() {
+        assert!(matches!(parse_range(None, 100), ByteRange::Full));
+        assert!(matches!(parse_range(Some("items=0-1"), 100), ByteRange::Full));
+        assert!(matches!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full));
+        assert!(matches!(parse_range(Some("bytes=9-3"), 100), ByteRange::Full));
+        assert!(matches!(parse_range(Some("bytes=a-b"), 100), ByteRange::Full));
+    }
+
+    #[test]
+    fn partial_ranges() {
+        assert!(matches!(parse_range(Some("bytes=0-9"), 100), ByteRange::Partial(0, 9)));
+        assert!(matches!(parse_range(Some("bytes=90-"), 100), ByteRange::Partial(90, 99)));
+        // 끝이 파일보다 크면 파일 끝까지
+        assert!(matches!(parse_range(Some("bytes=50-500"), 100), ByteRange::Partial(50, 99)));
+        // 마지막 N byte
+        assert!(matches!(parse_range(Some("bytes=-10"), 100), ByteRange::Partial(90, 99)));
+        assert!(matches!(parse_range(Some("bytes=-500"), 100), ByteRange::Partial(0, 99)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert!(matches!(parse_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable));
+        assert!(matches!(parse_range(Some("bytes=100-"), 100), ByteRange::Unsatisfiable));
+        assert!(matches!(parse_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable));
+    }
+}
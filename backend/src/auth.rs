@@ -0,0 +1,159 @@
+use crate::config::AuthConfig;
+use actix_web::{http::header, HttpRequest};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// 요청한 사용자가 누구인지 확인한다. 클라이언트가 보낸 이름을 그대로 믿지 않고, 아래 중 하나로 확인된 경우만 인정
+// 1. Authorization: Bearer <token>. token은 AUTH.SECRET으로 서명한 사용자 토큰 (issue_token)
+// 2. Authorization: Basic (이름:token). CalDAV 클라이언트처럼 Basic 인증만 보낼 수 있는 경우. token은 앱 비밀번호처럼 쓴다
+// 3. X-User + X-Proxy-Secret. 앞단의 인증 프록시가 확인한 이름을 넘겨줄 때. X-Proxy-Secret이 AUTH.PROXY_SECRET과 같아야 한다
+// 어느 것도 맞지 않으면 anonymous
+pub const USER_HEADER: &str = "x-user";
+pub const PROXY_SECRET_HEADER: &str = "x-proxy-secret";
+// 브라우저의 WebSocket은 헤더를 붙일 수 없으므로 new WebSocket(url, ["bearer", token])으로 보낸다
+pub const SOCKET_PROTOCOL: &str = "bearer";
+
+pub struct Auth {
+    // GET /audit 처럼 관리자만 볼 수 있는 기능을 사용할 수 있는 사용자 목록
+    pub admins: Vec<String>,
+    secret: String,
+    proxy_secret: String,
+    pub token_ttl_secs: i64
+}
+
+// 사용자를 확인한 방법. WebSocket은 응답에 고른 하위 프로토콜을 돌려줘야 한다
+#[derive(PartialEq)]
+pub enum Credential {
+    Header,
+    SocketProtocol
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Auth {
+        Auth {
+            admins: config.admins(),
+            secret: config.secret.clone(),
+            proxy_secret: config.proxy_secret.clone(),
+            token_ttl_secs: config.token_ttl_secs
+        }
+    }
+
+    // token은 {이름(hex)}.{만료 시각(unix)}.{서명(hex)}. 이름을 hex로 바꾸는 것은 . 이나 : 이 들어간 이름 때문
+    // AUTH.SECRET이 없으면 만들 수 없다
+    pub fn issue_token(&self, user: &str, ttl_secs: i64) -> Option<String> {
+        if self.secret.is_empty() {
+            return None;
+        }
+
+        let payload = format!("{}.{}", hex::encode(user), Utc::now().timestamp() + ttl_secs);
+        Some(format!("{}.{}", payload, hex::encode(self.mac(&payload).finalize().into_bytes())))
+    }
+
+    // 서명과 만료 시각이 맞으면 token의 사용자 이름
+    pub fn verify_token(&self, token: &str) -> Option<String> {
+        if self.secret.is_empty() {
+            return None;
+        }
+
+        let (payload, signature) = token.trim().rsplit_once('.')?;
+        let (user, expires_at) = payload.split_once('.')?;
+
+        let signature = hex::decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+
+        if expires_at.parse::<i64>().ok()? < Utc::now().timestamp() {
+            return None;
+        }
+
+        String::from_utf8(hex::decode(user).ok()?).ok().filter(|user| !user.is_empty())
+    }
+
+    // 확인된 사용자 이름. socket이면 WebSocket 하위 프로토콜과 ?access_token= 도 본다
+    pub fn authenticate(&self, req: &HttpRequest, socket: bool) -> Option<(String, Credential)> {
+        let authorization = req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+
+        if let Some(authorization) = authorization {
+            if let Some(token) = authorization.strip_prefix("Bearer ") {
+                return self.verify_token(token).map(|user| (user, Credential::Header));
+            }
+            if let Some(basic) = authorization.strip_prefix("Basic ") {
+                return self.verify_basic(basic).map(|user| (user, Credential::Header));
+            }
+        }
+
+        if let Some(user) = self.proxy_user(req) {
+            return Some((user, Credential::Header));
+        }
+
+        if socket {
+            if let Some(token) = socket_protocol_token(req) {
+                return self.verify_token(token).map(|user| (user, Credential::SocketProtocol));
+            }
+            if let Some(token) = query_token(req) {
+                return self.verify_token(&token).map(|user| (user, Credential::Header));
+            }
+        }
+
+        None
+    }
+
+    // Basic base64(이름:token). token의 사용자와 이름이 같아야 한다
+    fn verify_basic(&self, basic: &str) -> Option<String> {
+        let decoded = String::from_utf8(STANDARD.decode(basic.trim()).ok()?).ok()?;
+        let (user, token) = decoded.split_once(':')?;
+        self.verify_token(token).filter(|verified| verified == user)
+    }
+
+    fn proxy_user(&self, req: &HttpRequest) -> Option<String> {
+        if self.proxy_secret.is_empty() {
+            return None;
+        }
+
+        let secret = req.headers().get(PROXY_SECRET_HEADER)?.to_str().ok()?;
+        if !constant_time_eq(secret.as_bytes(), self.proxy_secret.as_bytes()) {
+            return None;
+        }
+
+        req.headers()
+            .get(USER_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+// Sec-WebSocket-Protocol: bearer, <token>
+fn socket_protocol_token(req: &HttpRequest) -> Option<&str> {
+    let protocols = req.headers().get(header::SEC_WEBSOCKET_PROTOCOL)?.to_str().ok()?;
+    let mut protocols = protocols.split(',').map(str::trim);
+
+    match (protocols.next(), protocols.next()) {
+        (Some(SOCKET_PROTOCOL), Some(token)) => Some(token),
+        _ => None
+    }
+}
+
+// ?access_token=<token>. 주소는 로그에 남을 수 있으므로 하위 프로토콜을 쓸 수 없을 때만
+fn query_token(req: &HttpRequest) -> Option<String> {
+    req.query_string()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == "access_token")
+        .map(|(_, token)| token.to_string())
+}
+
+// 비교에 걸린 시간으로 비밀 값을 알아낼 수 없도록 길이가 같으면 끝까지 비교한다
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
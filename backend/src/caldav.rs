@@ -0,0 +1,537 @@
+use crate::config::AppState;
+use crate::conditional::{optional_version, version_etag};
+use crate::context::RequestContext;
+use crate::db::{self, ListAccess};
+use crate::errors::AppError;
+use crate::handlers::{get_client, log_error};
+use crate::ical;
+use crate::models::{TodoItem, TodoList};
+use actix_web::http::header::{self, ETag, Header, IfNoneMatch};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, Route};
+use deadpool_postgres::Client;
+use slog::o;
+
+// CalDAV (RFC 4791) 중 할 일(VTODO) 동기화에 필요한 만큼만 구현한다
+// - /dav/                          PROPFIND: 현재 사용자(principal) 찾기
+// - /dav/principals/{user}/        PROPFIND: calendar-home-set
+// - /dav/calendars/                PROPFIND: list 하나가 캘린더 컬렉션 하나
+// - /dav/calendars/{list_id}/      PROPFIND, REPORT(calendar-query, calendar-multiget, sync-collection)
+// - /dav/calendars/{list_id}/{name} GET, PUT, DELETE: item 하나가 .ics 리소스 하나
+//
+// 인증은 다른 API와 같다 (auth.rs). CalDAV 클라이언트는 Basic 인증만 보낼 수 있으므로
+// 사용자 이름과, 비밀번호 자리에 POST /auth/token 으로 받은 token을 넣는다. 서명과 이름이 맞아야 인정
+// template list는 item을 만들 때 쓰는 틀이므로 캘린더로 보이지 않는다
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDAR_SERVER: &str = "http://calendarserver.org/ns/";
+
+const SYNC_TOKEN_PREFIX: &str = "http://actix-todo/sync/";
+
+pub fn propfind() -> Route {
+    web::method(Method::from_bytes(b"PROPFIND").unwrap())
+}
+
+pub fn report() -> Route {
+    web::method(Method::from_bytes(b"REPORT").unwrap())
+}
+
+// property 하나. value는 안에 들어갈 xml
+struct Prop {
+    ns: &'static str,
+    name: &'static str,
+    value: String
+}
+
+impl Prop {
+    fn new(ns: &'static str, name: &'static str, value: impl ToString) -> Self {
+        Prop { ns, name, value: value.to_string() }
+    }
+}
+
+// PROPFIND, REPORT에서 클라이언트가 요청한 property
+enum PropRequest {
+    // allprop, 또는 body가 없는 경우
+    All,
+    Names(Vec<(String, String)>)
+}
+
+impl PropRequest {
+    fn from_element(prop: Option<roxmltree::Node>) -> Self {
+        match prop {
+            Some(prop) => PropRequest::Names(prop.children()
+                .filter(|child| child.is_element())
+                .map(|child| (child.tag_name().namespace().unwrap_or_default().to_string(), child.tag_name().name().to_string()))
+                .collect()),
+            None => PropRequest::All
+        }
+    }
+}
+
+struct Resource {
+    href: String,
+    props: Vec<Prop>
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn prefix(ns: &str) -> &'static str {
+    match ns {
+        CALDAV => "c",
+        CALENDAR_SERVER => "cs",
+        _ => "d"
+    }
+}
+
+fn href(path: &str) -> String {
+    format!("<d:href>{}</d:href>", escape_xml(path))
+}
+
+// 리소스 이름은 클라이언트가 정하므로 주소에 쓸 수 없는 글자는 인코딩한다
+fn encode_segment(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'@' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+fn decode_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn item_name(item: &TodoItem) -> String {
+    item.dav_name.clone().unwrap_or_else(|| format!("{}.ics", item.id))
+}
+
+fn collection_href(list_id: i32) -> String {
+    format!("/dav/calendars/{}/", list_id)
+}
+
+fn item_href(list_id: i32, name: &str) -> String {
+    format!("{}{}", collection_href(list_id), encode_segment(name))
+}
+
+fn sync_token(token: i64) -> String {
+    format!("{}{}", SYNC_TOKEN_PREFIX, token)
+}
+
+// 요청한 property 중 있는 것은 200, 없는 것은 404로 나눠서 돌려준다
+fn response(resource: &Resource, request: &PropRequest) -> String {
+    let (found, missing): (Vec<&Prop>, Vec<(String, String)>) = match request {
+        // calendar-data는 크기 때문에 allprop에는 넣지 않는다
+        PropRequest::All => (resource.props.iter().filter(|prop| prop.name != "calendar-data").collect(), vec![]),
+        PropRequest::Names(names) => {
+            let found = resource.props.iter()
+                .filter(|prop| names.iter().any(|(ns, name)| ns == prop.ns && name == prop.name))
+                .collect();
+            let missing = names.iter()
+                .filter(|(ns, name)| !resource.props.iter().any(|prop| prop.ns == ns && prop.name == name))
+                .cloned()
+                .collect();
+            (found, missing)
+        }
+    };
+
+    let mut xml = format!("<d:response>{}", href(&resource.href));
+
+    if !found.is_empty() {
+        xml.push_str("<d:propstat><d:prop>");
+        for prop in found {
+            let tag = format!("{}:{}", prefix(prop.ns), prop.name);
+            if prop.value.is_empty() {
+                xml.push_str(&format!("<{}/>", tag));
+            } else {
+                xml.push_str(&format!("<{}>{}</{}>", tag, prop.value, tag));
+            }
+        }
+        xml.push_str("</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>");
+    }
+
+    if !missing.is_empty() {
+        xml.push_str("<d:propstat><d:prop>");
+        for (ns, name) in missing {
+            xml.push_str(&format!("<{} xmlns=\"{}\"/>", escape_xml(&name), escape_xml(&ns)));
+        }
+        xml.push_str("</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>");
+    }
+
+    xml.push_str("</d:response>");
+    xml
+}
+
+fn not_found_response(path: &str) -> String {
+    format!("<d:response>{}<d:status>HTTP/1.1 404 Not Found</d:status></d:response>", href(path))
+}
+
+fn multistatus(responses: Vec<String>, sync_token: Option<String>) -> HttpResponse {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"{}\" xmlns:c=\"{}\" xmlns:cs=\"{}\">",
+        DAV, CALDAV, CALENDAR_SERVER
+    );
+    for response in responses {
+        xml.push_str(&response);
+    }
+    if let Some(token) = sync_token {
+        xml.push_str(&format!("<d:sync-token>{}</d:sync-token>", escape_xml(&token)));
+    }
+    xml.push_str("</d:multistatus>");
+
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(xml)
+}
+
+// 캘린더 앱이 사용자 이름/비밀번호를 묻도록 WWW-Authenticate를 붙인다
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"todo\""))
+        .finish()
+}
+
+// Depth: 0 이면 자기 자신만, 1(또는 infinity)이면 바로 아래 리소스까지
+fn depth(req: &HttpRequest) -> u8 {
+    match req.headers().get("depth").and_then(|value| value.to_str().ok()) {
+        Some("0") => 0,
+        _ => 1
+    }
+}
+
+fn parse_xml(body: &str) -> Result<roxmltree::Document<'_>, AppError> {
+    roxmltree::Document::parse(body).map_err(|err| AppError::validation(format!("Invalid XML body: {}", err)))
+}
+
+fn parse_propfind(body: &str) -> Result<PropRequest, AppError> {
+    if body.trim().is_empty() {
+        return Ok(PropRequest::All);
+    }
+
+    let document = parse_xml(body)?;
+    let prop = document.root_element()
+        .children()
+        .find(|child| child.has_tag_name((DAV, "prop")));
+
+    Ok(PropRequest::from_element(prop))
+}
+
+fn principal_props(ctx: &RequestContext) -> Vec<Prop> {
+    let principal = format!("/dav/principals/{}/", encode_segment(&ctx.actor));
+
+    vec![
+        Prop::new(DAV, "current-user-principal", href(&principal)),
+        Prop::new(DAV, "principal-URL", href(&principal)),
+        Prop::new(DAV, "displayname", escape_xml(&ctx.actor)),
+        Prop::new(CALDAV, "calendar-home-set", href("/dav/calendars/"))
+    ]
+}
+
+fn collection_props(ctx: &RequestContext, list: &TodoList, token: i64) -> Vec<Prop> {
+    vec![
+        Prop::new(DAV, "resourcetype", "<d:collection/><c:calendar/>"),
+        Prop::new(DAV, "displayname", escape_xml(&list.title)),
+        Prop::new(DAV, "current-user-principal", href(&format!("/dav/principals/{}/", encode_segment(&ctx.actor)))),
+        Prop::new(DAV, "current-user-privilege-set", "<d:privilege><d:read/></d:privilege><d:privilege><d:write/></d:privilege>"),
+        Prop::new(DAV, "supported-report-set", [
+            "<d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>",
+            "<d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>",
+            "<d:supported-report><d:report><d:sync-collection/></d:report></d:supported-report>"
+        ].concat()),
+        Prop::new(CALDAV, "supported-calendar-component-set", "<c:comp name=\"VTODO\"/>"),
+        Prop::new(DAV, "sync-token", escape_xml(&sync_token(token))),
+        Prop::new(CALENDAR_SERVER, "getctag", escape_xml(&sync_token(token)))
+    ]
+}
+
+fn item_props(list: &TodoList, item: &TodoItem) -> Vec<Prop> {
+    vec![
+        Prop::new(DAV, "resourcetype", ""),
+        Prop::new(DAV, "getetag", escape_xml(&version_etag(item.version).to_string())),
+        Prop::new(DAV, "getcontenttype", "text/calendar; charset=utf-8; component=VTODO"),
+        Prop::new(CALDAV, "calendar-data", escape_xml(&ical::calendar(&list.title, &[(list, vec![item])])))
+    ]
+}
+
+pub async fn options() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("DAV", "1, 3, calendar-access"))
+        .insert_header((header::ALLOW, "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT"))
+        .finish()
+}
+
+// 클라이언트는 서버 주소만 입력받고 /.well-known/caldav 부터 찾아간다 (RFC 6764)
+pub async fn well_known() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, "/dav/"))
+        .finish()
+}
+
+// /dav/, /dav/principals/{user}/
+pub async fn propfind_principal(req: HttpRequest, ctx: RequestContext, body: String) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let request = parse_propfind(&body)?;
+    let mut props = principal_props(&ctx);
+    let resourcetype = if req.path().starts_with("/dav/principals/") { "<d:collection/><d:principal/>" } else { "<d:collection/>" };
+    props.push(Prop::new(DAV, "resourcetype", resourcetype));
+
+    Ok(multistatus(vec![response(&Resource { href: req.path().to_string(), props }, &request)], None))
+}
+
+// /dav/calendars/ 아래에 list들이 캘린더로 보인다
+pub async fn propfind_home(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, body: String) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_propfind_home"));
+    let request = parse_propfind(&body).map_err(log_error(log.clone()))?;
+
+    let mut props = principal_props(&ctx);
+    props.push(Prop::new(DAV, "resourcetype", "<d:collection/>"));
+    let mut responses = vec![response(&Resource { href: "/dav/calendars/".to_string(), props }, &request)];
+
+    if depth(&req) > 0 {
+        let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+        let token = db::get_sync_token(&client).await.map_err(log_error(log.clone()))?;
+        let lists = db::get_feed_lists(&client, None, ctx.reader()).await.map_err(log_error(log))?;
+
+        for list in lists.iter().filter(|list| !list.is_template) {
+            let resource = Resource { href: collection_href(list.id), props: collection_props(&ctx, list, token) };
+            responses.push(response(&resource, &request));
+        }
+    }
+
+    Ok(multistatus(responses, None))
+}
+
+pub async fn propfind_calendar(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, body: String) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_propfind_calendar"));
+    let request = parse_propfind(&body).map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+    let list = db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    let token = db::get_sync_token(&client).await.map_err(log_error(log.clone()))?;
+
+    let collection = Resource { href: collection_href(list.id), props: collection_props(&ctx, &list, token) };
+    let mut responses = vec![response(&collection, &request)];
+
+    if depth(&req) > 0 {
+        let items = db::get_itmes(&client, list.id).await.map_err(log_error(log))?;
+        for item in &items {
+            let resource = Resource { href: item_href(list.id, &item_name(item)), props: item_props(&list, item) };
+            responses.push(response(&resource, &request));
+        }
+    }
+
+    Ok(multistatus(responses, None))
+}
+
+// calendar-query: 필터는 무시하고 모든 VTODO를 돌려준다 (클라이언트가 다시 거른다)
+// calendar-multiget: 요청한 href들만
+// sync-collection: sync-token 이후에 바뀐 item들과, 사라진 item들(404)
+pub async fn report_calendar(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, body: String) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_report"));
+    let document = parse_xml(&body).map_err(log_error(log.clone()))?;
+    let root = document.root_element();
+    let request = PropRequest::from_element(root.children().find(|child| child.has_tag_name((DAV, "prop"))));
+
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+    let list = db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    // item을 읽는 사이에 바뀐 것이 다음 동기화에서 빠지지 않도록 token을 먼저 정한다
+    let token = db::get_sync_token(&client).await.map_err(log_error(log.clone()))?;
+    let items = db::get_itmes(&client, list.id).await.map_err(log_error(log.clone()))?;
+
+    let item_response = |item: &TodoItem| {
+        response(&Resource { href: item_href(list.id, &item_name(item)), props: item_props(&list, item) }, &request)
+    };
+
+    if root.has_tag_name((CALDAV, "calendar-query")) {
+        return Ok(multistatus(items.iter().map(item_response).collect(), None));
+    }
+
+    if root.has_tag_name((CALDAV, "calendar-multiget")) {
+        let prefix = collection_href(list.id);
+        let responses = root.children()
+            .filter(|child| child.has_tag_name((DAV, "href")))
+            .filter_map(|child| child.text())
+            .map(|path| {
+                // 전체 URL(http://host/dav/...)로 올 수도 있으므로 컬렉션 주소 뒤만 본다
+                let name = path.trim().rsplit_once(&prefix).map(|(_, name)| decode_segment(name));
+                match name.and_then(|name| items.iter().find(|item| item_name(item) == name)) {
+                    Some(item) => item_response(item),
+                    None => not_found_response(path.trim())
+                }
+            })
+            .collect();
+        return Ok(multistatus(responses, None));
+    }
+
+    if root.has_tag_name((DAV, "sync-collection")) {
+        let requested = root.children()
+            .find(|child| child.has_tag_name((DAV, "sync-token")))
+            .and_then(|child| child.text())
+            .map(|token| token.trim().to_string())
+            .unwrap_or_default();
+
+        // 처음 동기화(빈 token)이면 전부
+        if requested.is_empty() {
+            return Ok(multistatus(items.iter().map(item_response).collect(), Some(sync_token(token))));
+        }
+
+        let since = match requested.strip_prefix(SYNC_TOKEN_PREFIX).and_then(|since| since.parse::<i64>().ok()) {
+            Some(since) => since,
+            // RFC 6578: 알 수 없는 token이면 valid-sync-token 에러. 클라이언트는 처음부터 다시 동기화한다
+            None => return Ok(HttpResponse::Forbidden()
+                .content_type("application/xml; charset=utf-8")
+                .body(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:error xmlns:d=\"{}\"><d:valid-sync-token/></d:error>", DAV)))
+        };
+
+        let changes = db::get_dav_changes(&client, list.id, since).await.map_err(log_error(log))?;
+        let responses = changes.iter()
+            .map(|(id, name)| match items.iter().find(|item| item.id == *id) {
+                Some(item) => item_response(item),
+                None => not_found_response(&item_href(list.id, name))
+            })
+            .collect();
+
+        return Ok(multistatus(responses, Some(sync_token(token))));
+    }
+
+    Err(log_error(log)(AppError::validation("Unsupported REPORT")))
+}
+
+pub async fn get_resource(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, String)>) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_get"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+    let list = db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    let item = db::get_dav_item(&client, list.id, &path.1)
+        .await
+        .map_err(log_error(log.clone()))?
+        .ok_or_else(AppError::not_found)
+        .map_err(log_error(log))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .insert_header(ETag(version_etag(item.version)))
+        .body(ical::calendar(&list.title, &[(&list, vec![&item])])))
+}
+
+// If-None-Match: * 이면 새로 만들 때만, If-Match가 있으면 그 버전일 때만 바꾼다
+pub async fn put_resource(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, String)>, body: String) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_put"));
+    let expected_version = optional_version(&req).map_err(log_error(log.clone()))?;
+    let must_not_exist = matches!(IfNoneMatch::parse(&req), Ok(IfNoneMatch::Any));
+
+    let todo = ical::parse_single(&body)
+        .map_err(|errors| AppError::validation(errors.iter()
+            .map(|error| format!("line {}: {}", error.line, error.message))
+            .collect::<Vec<String>>()
+            .join(", ")))
+        .map_err(log_error(log.clone()))?;
+
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+    let (item, created) = db::put_dav_item(&mut client, &ctx, path.0, &path.1, &todo, expected_version, must_not_exist)
+        .await
+        .map_err(log_error(log))?;
+
+    let mut response = if created { HttpResponse::Created() } else { HttpResponse::NoContent() };
+    Ok(response.insert_header(ETag(version_etag(item.version))).finish())
+}
+
+pub async fn delete_resource(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, String)>) -> Result<HttpResponse, AppError> {
+    if !ctx.authenticated {
+        return Ok(unauthorized());
+    }
+
+    let log = state.log.new(o!("handler" => "caldav_delete"));
+    let expected_version = optional_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let deleted = db::delete_dav_item(&mut client, &ctx, path.0, &path.1, expected_version)
+        .await
+        .map_err(log_error(log.clone()))?;
+
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(log_error(log)(AppError::not_found()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_keeps_safe_characters() {
+        assert_eq!(encode_segment("a-Z_0.~@x"), "a-Z_0.~@x");
+        assert_eq!(encode_segment("a b/c%"), "a%20b%2Fc%25");
+        assert_eq!(encode_segment("장"), "%EC%9E%A5");
+    }
+
+    #[test]
+    fn decode_reverses_encode() {
+        for name in ["장보기 목록.ics", "100% done/", "a+b"] {
+            assert_eq!(decode_segment(&encode_segment(name)), name);
+        }
+        assert_eq!(decode_segment("%ec%9e%a5"), "장");
+    }
+
+    #[test]
+    fn decode_leaves_broken_escapes() {
+        assert_eq!(decode_segment("%zz%4"), "%zz%4");
+        assert_eq!(decode_segment("50%"), "50%");
+    }
+}
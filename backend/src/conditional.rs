@@ -0,0 +1,100 @@
+use crate::errors::AppError;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::{self, ETag, EntityTag, Header, HttpDate, IfMatch, IfNoneMatch, LastModified};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+// 하나의 item은 version 자체를 ETag로 사용한다. ex) ETag: "3"
+pub fn version_etag(version: i32) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+// 목록 응답은 여러 row로 만들어지기 때문에 응답 body 전체의 해시를 ETag로 사용한다.
+// item이 하나만 체크되어도 item_count 등이 바뀌므로 body가 달라짐
+fn body_etag(body: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    EntityTag::new_weak(format!("{:x}", hasher.finish()))
+}
+
+// If-None-Match 는 약한 비교(weak comparison)를 사용한다
+fn is_not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false
+    }
+}
+
+// GET 응답에 ETag / Last-Modified를 붙이고,
+// 클라이언트가 보낸 If-None-Match 가 같으면 body 없이 304를 돌려준다
+pub fn cached_json<T: Serialize>(req: &HttpRequest, body: &T, etag: Option<EntityTag>, last_modified: Option<DateTime<Utc>>) -> Result<HttpResponse, AppError> {
+    let body = serde_json::to_vec(body).map_err(AppError::db_error)?;
+    let etag = etag.unwrap_or_else(|| body_etag(&body));
+
+    let not_modified = is_not_modified(req, &etag);
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(HttpDate::from(SystemTime::from(last_modified))));
+    }
+
+    if not_modified {
+        return Ok(response.finish());
+    }
+
+    Ok(response
+        .content_type(header::ContentType::json())
+        .body(body))
+}
+
+// 수정 요청에는 If-Match 가 반드시 있어야 한다.
+// If-Match: * 이면 버전을 확인하지 않으므로 None, 아니면 기대하는 버전을 돌려준다
+pub fn required_version(req: &HttpRequest) -> Result<Option<i32>, AppError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Err(AppError::precondition_failed("If-Match header is required"));
+    }
+
+    optional_version(req)
+}
+
+// CalDAV처럼 If-Match 가 없어도 되는 경우. 없으면 None
+pub fn optional_version(req: &HttpRequest) -> Result<Option<i32>, AppError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(None);
+    }
+
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => Ok(None),
+        // If-Match 는 강한 비교(strong comparison)만 허용하므로 W/"3" 은 버전으로 인정하지 않음
+        Ok(IfMatch::Items(tags)) => tags.iter()
+            .filter(|tag| !tag.weak)
+            .find_map(|tag| tag.tag().parse::<i32>().ok())
+            .map(Some)
+            .ok_or_else(|| AppError::precondition_failed("If-Match must contain the item version ETag")),
+        Err(_) => Err(AppError::precondition_failed("If-Match header is malformed"))
+    }
+}
+
+// WebSocket, batch처럼 body로 버전을 받는 경우. If-Match와 같은 규칙을 따른다
+// 숫자이면 그 버전, "*"이면 확인하지 않음(None), 없으면 412
+pub fn body_version(version: Option<&Value>) -> Result<Option<i32>, AppError> {
+    match version {
+        None => Err(AppError::precondition_failed("version is required")),
+        Some(Value::String(any)) if any == "*" => Ok(None),
+        Some(Value::Number(number)) => number.as_i64()
+            .and_then(|number| i32::try_from(number).ok())
+            .map(Some)
+            .ok_or_else(|| AppError::precondition_failed("version must be an item version or \"*\"")),
+        Some(_) => Err(AppError::precondition_failed("version must be an item version or \"*\""))
+    }
+}
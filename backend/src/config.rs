@@ -1,11 +1,36 @@
+use crate::events::EventHub;
+use crate::presence::PresenceHub;
+use crate::storage::Storage;
+use crate::auth::Auth;
+use crate::webhooks::HostPolicy;
 use deadpool_postgres::Pool;
+use std::sync::Arc;
 use serde::Deserialize;
 use config::{ConfigError, Config, Environment};
 use slog::Logger;
 
 pub struct AppState {
     pub pool: Pool,
-    pub log: Logger
+    pub log: Logger,
+    // 사용자 확인 (토큰 서명 키, 인증 프록시, 관리자 목록)
+    pub auth: Arc<Auth>,
+    // LISTEN으로 받은 item 이벤트를 SSE 연결들에 나눠줌
+    pub events: Arc<EventHub>,
+    pub events_heartbeat: std::time::Duration,
+    // WebSocket으로 list를 보고 있는 사용자 목록
+    pub presence: Arc<PresenceHub>,
+    // Idempotency-Key로 저장한 응답을 보관하는 기간
+    pub idempotency_ttl: std::time::Duration,
+    // 첨부 파일을 저장하는 곳과 올릴 수 있는 파일의 제한
+    pub storage: Arc<dyn Storage>,
+    pub attachment_limits: Arc<AttachmentLimits>,
+    // 웹훅 주소로 쓸 수 있는 host (사설/loopback 주소 거부)
+    pub webhook_hosts: Arc<HostPolicy>
+}
+
+pub struct AttachmentLimits {
+    pub max_size_bytes: u64,
+    pub allowed_types: Vec<String>
 }
 
 
@@ -16,10 +41,373 @@ pub struct ServerConfig {
     pub host: String,
     pub port: i32
 }
+
+// DB.STATEMENT_CACHE=false 로 prepare 캐시를 끄고 부하 테스트 결과를 비교할 수 있다.
+// 값이 없으면 기본값(캐시 사용)을 사용
+#[derive(Deserialize)]
+pub struct DbConfig {
+    #[serde(default = "default_statement_cache")]
+    pub statement_cache: bool
+}
+
+fn default_statement_cache() -> bool {
+    true
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        DbConfig { statement_cache: default_statement_cache() }
+    }
+}
+
+// 휴지통 보관 기간과 정리 주기
+// TRASH.RETENTION_DAYS=30, TRASH.PURGE_INTERVAL_SECS=3600
+#[derive(Deserialize)]
+pub struct TrashConfig {
+    #[serde(default = "default_retention_days")]
+    pub retention_days: i64,
+    #[serde(default = "default_purge_interval_secs")]
+    pub purge_interval_secs: u64
+}
+
+fn default_retention_days() -> i64 {
+    30
+}
+
+fn default_purge_interval_secs() -> u64 {
+    3600
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        TrashConfig {
+            retention_days: default_retention_days(),
+            purge_interval_secs: default_purge_interval_secs()
+        }
+    }
+}
+
+// AUTH.ADMINS=alice,bob: 관리자. 기본값은 없다
+// AUTH.SECRET: 사용자 토큰을 서명하는 키. 없으면 토큰(Bearer, Basic)으로 인증할 수 없다
+// AUTH.PROXY_SECRET: 앞단의 인증 프록시가 X-User와 함께 X-Proxy-Secret으로 보내는 값. 없으면 X-User를 믿지 않는다
+// AUTH.TOKEN_TTL_SECS: POST /auth/token으로 만드는 토큰의 유효 기간 (기본 30일)
+#[derive(Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub admins: String,
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default)]
+    pub proxy_secret: String,
+    #[serde(default = "default_token_ttl_secs")]
+    pub token_ttl_secs: i64
+}
+
+fn default_token_ttl_secs() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            admins: String::new(),
+            secret: String::new(),
+            proxy_secret: String::new(),
+            token_ttl_secs: default_token_ttl_secs()
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn admins(&self) -> Vec<String> {
+        self.admins
+            .split(',')
+            .map(|admin| admin.trim().to_string())
+            .filter(|admin| !admin.is_empty())
+            .collect()
+    }
+}
+
+// EVENTS.REPLAY_BUFFER: Last-Event-ID로 이어받을 수 있도록 메모리에 보관하는 최근 이벤트 수
+// EVENTS.HEARTBEAT_SECS: SSE keep-alive, WebSocket ping 주기. WebSocket은 이 값의 3배 동안 응답이 없으면 끊는다
+#[derive(Deserialize)]
+pub struct EventsConfig {
+    #[serde(default = "default_replay_buffer")]
+    pub replay_buffer: usize,
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64
+}
+
+fn default_replay_buffer() -> usize {
+    1000
+}
+
+fn default_heartbeat_secs() -> u64 {
+    15
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            replay_buffer: default_replay_buffer(),
+            heartbeat_secs: default_heartbeat_secs()
+        }
+    }
+}
+
+// 웹훅 전송 설정
+// WEBHOOKS.POLL_INTERVAL_SECS: 보낼 웹훅을 확인하는 주기
+// WEBHOOKS.MAX_ATTEMPTS: 이 횟수만큼 실패하면 dead 상태가 됨
+// WEBHOOKS.BACKOFF_SECS: 첫 재시도까지의 대기 시간. 실패할 때마다 두 배
+// WEBHOOKS.TIMEOUT_SECS: 요청 하나의 제한 시간
+// WEBHOOKS.ALLOWED_HOSTS: 사설/loopback 주소여도 보낼 수 있는 host 목록 (쉼표로 구분, 로컬 개발용. 예: localhost,127.0.0.1)
+#[derive(Deserialize)]
+pub struct WebhooksConfig {
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i32,
+    #[serde(default = "default_backoff_secs")]
+    pub backoff_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: i64,
+    #[serde(default)]
+    pub allowed_hosts: String
+}
+
+fn default_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_max_attempts() -> i32 {
+    8
+}
+
+fn default_backoff_secs() -> u64 {
+    30
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+fn default_batch_size() -> i64 {
+    20
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            poll_interval_secs: default_poll_interval_secs(),
+            max_attempts: default_max_attempts(),
+            backoff_secs: default_backoff_secs(),
+            timeout_secs: default_timeout_secs(),
+            batch_size: default_batch_size(),
+            allowed_hosts: String::new()
+        }
+    }
+}
+
+impl WebhooksConfig {
+    pub fn allowed_hosts(&self) -> Vec<String> {
+        self.allowed_hosts
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect()
+    }
+}
+
+// Idempotency-Key 설정
+// IDEMPOTENCY.TTL_SECS: 같은 키로 재시도하면 저장된 응답을 돌려주는 기간 (기본 하루)
+// IDEMPOTENCY.CLEANUP_INTERVAL_SECS: 만료된 키를 지우는 주기
+#[derive(Deserialize)]
+pub struct IdempotencyConfig {
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub ttl_secs: u64,
+    #[serde(default = "default_cleanup_interval_secs")]
+    pub cleanup_interval_secs: u64
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_cleanup_interval_secs() -> u64 {
+    3600
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            ttl_secs: default_idempotency_ttl_secs(),
+            cleanup_interval_secs: default_cleanup_interval_secs()
+        }
+    }
+}
+
+// 알림 작업 설정
+// REMINDERS.POLL_INTERVAL_SECS: 알림 시간이 된 item을 확인하는 주기
+// REMINDERS.BATCH_SIZE: 한 번에 처리하는 알림 수
+#[derive(Deserialize)]
+pub struct RemindersConfig {
+    #[serde(default = "default_reminders_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    #[serde(default = "default_reminders_batch_size")]
+    pub batch_size: i64
+}
+
+fn default_reminders_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_reminders_batch_size() -> i64 {
+    100
+}
+
+impl Default for RemindersConfig {
+    fn default() -> Self {
+        RemindersConfig {
+            poll_interval_secs: default_reminders_poll_interval_secs(),
+            batch_size: default_reminders_batch_size()
+        }
+    }
+}
+
+// 하위 item 설정
+// ITEMS.MAX_DEPTH: 최상위 item을 1로 셀 때 최대 깊이
+// ITEMS.AUTO_COMPLETE_PARENTS: 하위 item이 모두 체크되면 상위 item도 체크하고, 하나라도 체크를 풀면 상위 item도 푼다
+// ITEMS.ALLOW_BLOCKED_CHECK: 체크되지 않은 blocker가 있어도 체크할 수 있게 한다 (응답에 Warning 헤더). 기본은 409
+#[derive(Deserialize)]
+pub struct ItemsConfig {
+    #[serde(default = "default_max_depth")]
+    pub max_depth: i32,
+    #[serde(default)]
+    pub auto_complete_parents: bool,
+    #[serde(default)]
+    pub allow_blocked_check: bool
+}
+
+fn default_max_depth() -> i32 {
+    5
+}
+
+impl Default for ItemsConfig {
+    fn default() -> Self {
+        ItemsConfig {
+            max_depth: default_max_depth(),
+            auto_complete_parents: false,
+            allow_blocked_check: false
+        }
+    }
+}
+
+// 첨부 파일 설정
+// ATTACHMENTS.STORAGE: local(기본) 또는 s3
+// ATTACHMENTS.DIR: local일 때 파일을 저장하는 디렉터리
+// ATTACHMENTS.MAX_SIZE_BYTES: 파일 하나의 최대 크기 (기본 10MiB)
+// ATTACHMENTS.ALLOWED_TYPES: 올릴 수 있는 Content-Type. image/* 처럼 종류 전체를 허용할 수도 있다
+// ATTACHMENTS.CLEANUP_INTERVAL_SECS: 지워진 첨부 파일의 blob을 저장소에서 지우는 주기
+// ATTACHMENTS.S3_ENDPOINT, S3_BUCKET, S3_REGION, S3_ACCESS_KEY, S3_SECRET_KEY: s3일 때 (ex. MinIO면 http://localhost:9000)
+#[derive(Deserialize)]
+pub struct AttachmentsConfig {
+    #[serde(default = "default_storage")]
+    pub storage: String,
+    #[serde(default = "default_attachments_dir")]
+    pub dir: String,
+    #[serde(default = "default_max_size_bytes")]
+    pub max_size_bytes: u64,
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: String,
+    #[serde(default = "default_attachments_cleanup_interval_secs")]
+    pub cleanup_interval_secs: u64,
+    #[serde(default)]
+    pub s3_endpoint: String,
+    #[serde(default)]
+    pub s3_bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+    #[serde(default)]
+    pub s3_access_key: String,
+    #[serde(default)]
+    pub s3_secret_key: String
+}
+
+fn default_storage() -> String {
+    "local".to_string()
+}
+
+fn default_attachments_dir() -> String {
+    "attachments".to_string()
+}
+
+fn default_max_size_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_allowed_types() -> String {
+    "image/png,image/jpeg,image/gif,image/webp,text/plain,text/csv,application/json,application/pdf,application/zip,application/gzip".to_string()
+}
+
+fn default_attachments_cleanup_interval_secs() -> u64 {
+    60
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl AttachmentsConfig {
+    pub fn allowed_types(&self) -> Vec<String> {
+        self.allowed_types
+            .split(',')
+            .map(|content_type| content_type.trim().to_lowercase())
+            .filter(|content_type| !content_type.is_empty())
+            .collect()
+    }
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        AttachmentsConfig {
+            storage: default_storage(),
+            dir: default_attachments_dir(),
+            max_size_bytes: default_max_size_bytes(),
+            allowed_types: default_allowed_types(),
+            cleanup_interval_secs: default_attachments_cleanup_interval_secs(),
+            s3_endpoint: String::new(),
+            s3_bucket: String::new(),
+            s3_region: default_s3_region(),
+            s3_access_key: String::new(),
+            s3_secret_key: String::new()
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ConfigSetting {
     pub server: ServerConfig,
-    pub pg: deadpool_postgres::Config
+    pub pg: deadpool_postgres::Config,
+    #[serde(default)]
+    pub db: DbConfig,
+    #[serde(default)]
+    pub trash: TrashConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub reminders: RemindersConfig,
+    #[serde(default)]
+    pub items: ItemsConfig,
+    #[serde(default)]
+    pub attachments: AttachmentsConfig
 }
 
 // 여기서 impl은 위의 구조체 configsetting이 가지고 있는 기능을 나타냄
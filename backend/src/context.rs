@@ -0,0 +1,115 @@
+use crate::auth::Credential;
+use crate::config::AppState;
+use crate::errors::AppError;
+use actix_web::{dev::{Payload, ServiceRequest}, web, FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Ready};
+
+// 사용자는 auth.rs에서 확인한다 (토큰, Basic, 인증 프록시)
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// 요청마다 하나씩 붙는 id. 미들웨어에서 만들어서 request extensions에 넣어둔다
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+// 클라이언트가 보낸 X-Request-Id가 있으면 그대로 쓰고, 없으면 새로 만든다
+// 만든 id는 응답 헤더에도 그대로 돌려줌 (main.rs의 wrap_fn 참고)
+pub fn assign_request_id(req: &ServiceRequest) -> String {
+    let request_id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 100)
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    request_id
+}
+
+// 누가(actor), 어떤 요청(request_id)으로 데이터를 바꾸었는지.
+// 감사 로그(audit_log)에 함께 기록된다
+pub struct RequestContext {
+    pub actor: String,
+    pub request_id: Option<String>,
+    pub is_admin: bool,
+    // 사용자가 확인되었는지. 아니면 actor는 anonymous
+    pub authenticated: bool
+}
+
+impl RequestContext {
+    // 백그라운드 작업처럼 사용자 요청이 아닌 경우
+    pub fn system() -> Self {
+        RequestContext { actor: "system".to_string(), request_id: None, is_admin: true, authenticated: true }
+    }
+
+    // WebSocket처럼 연결 하나에서 여러 요청이 오는 경우, 요청마다 id를 따로 붙인다
+    // ex) 연결의 request id가 abc이고 메시지 id가 c1이면 abc:c1
+    pub fn with_request_id(&self, id: &str) -> Self {
+        RequestContext {
+            actor: self.actor.clone(),
+            request_id: Some(match &self.request_id {
+                Some(request_id) => format!("{}:{}", request_id, id),
+                None => id.to_string()
+            }),
+            is_admin: self.is_admin,
+            authenticated: self.authenticated
+        }
+    }
+
+    // 요청에서 사용자를 확인한다. socket이면 WebSocket에서만 쓰는 방법(하위 프로토콜, ?access_token=)도 본다
+    pub fn authenticate(req: &HttpRequest, socket: bool) -> (Self, Option<Credential>) {
+        let state = req.app_data::<web::Data<AppState>>();
+        let user = state.and_then(|state| state.auth.authenticate(req, socket));
+
+        let authenticated = user.is_some();
+        let (actor, credential) = match user {
+            Some((actor, credential)) => (actor, Some(credential)),
+            None => ("anonymous".to_string(), None)
+        };
+
+        let is_admin = authenticated && state
+            .map(|state| state.auth.admins.contains(&actor))
+            .unwrap_or(false);
+
+        let request_id = req.extensions()
+            .get::<RequestId>()
+            .map(|request_id| request_id.0.clone());
+
+        (RequestContext { actor, request_id, is_admin, authenticated }, credential)
+    }
+
+    pub fn require_user(&self) -> Result<(), AppError> {
+        if self.authenticated {
+            Ok(())
+        } else {
+            Err(AppError::unauthorized())
+        }
+    }
+
+    // 여러 list를 한 번에 찾을 때 볼 수 있는 list를 거르는 기준 (init.pgsql의 list_readable)
+    // 관리자는 None(모든 list), anonymous는 빈 문자열이라 멤버가 있는 list는 볼 수 없다
+    pub fn reader(&self) -> Option<&str> {
+        match (self.is_admin, self.authenticated) {
+            (true, _) => None,
+            (false, true) => Some(&self.actor),
+            (false, false) => Some("")
+        }
+    }
+
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if self.is_admin {
+            Ok(())
+        } else {
+            Err(AppError::forbidden())
+        }
+    }
+}
+
+// 핸들러의 파라미터로 RequestContext를 적으면 actix가 이 함수를 호출해서 채워준다
+impl FromRequest for RequestContext {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(RequestContext::authenticate(req, false).0))
+    }
+}
@@ -1,20 +1,124 @@
-use crate::models::{TodoList, TodoItem};
+use crate::models::{TodoList, TodoItem, ItemDependencies, DependencyGraph, DependencyNode, DependencyEdge, Assignee, Attachment, NewAttachment, Comment, Notification, NotificationsQuery, ItemsQuery, ItemSchedule, ItemRecurrence, MoveRequest, TransferItems, DuplicateTodo, Tag, ListStatus, Board, BoardColumn, ItemCompletion, Agenda, AgendaDay, AgendaQuery, CalendarFeed, ListMember, TodoListWithItems, IdempotencyRecord, BatchMode, BatchOperation, BatchRequest, BatchResponse, BatchResult, StatementCacheStats, Trash, AuditEntry, AuditQuery, SearchQuery, SearchResponse, SearchResult, Webhook, CreateWebhook, WebhookDelivery, PendingDelivery, DeliveriesQuery};
 use crate::errors::{AppError, AppErrorType};
-use deadpool_postgres::Client;
+use crate::conditional::body_version;
+use crate::formats::ImportedItem;
+use crate::ical::CalendarTodo;
+use crate::recurrence::Recurrence;
+use crate::templates::Variables;
+use crate::mentions;
+use crate::context::RequestContext;
+use crate::presence::{self, PresenceSignal};
+use actix_web::ResponseError;
+use deadpool_postgres::{Client, GenericClient, StatementCache, Transaction};
 use tokio_pg_mapper::FromTokioPostgresRow;
-use std::io;
+use tokio_postgres::{error::SqlState, Statement};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 
-pub async fn get_todos(client: &Client) -> Result<Vec<TodoList>, AppError> {
+// 커넥션 풀의 각 커넥션은 자신만의 statement cache를 가지고 있다.
+// 아래 값들은 모든 커넥션에 걸친 적중/실패 횟수로, /metrics/statements 에서 확인 가능
+static STATEMENT_CACHE_ENABLED: AtomicBool = AtomicBool::new(true);
+static STATEMENT_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static STATEMENT_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+// 부하 테스트에서 캐시 유무를 비교하기 위해 설정(DB.STATEMENT_CACHE)으로 끌 수 있게 함
+pub fn set_statement_cache(enabled: bool) {
+    STATEMENT_CACHE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// 하위 item 규칙. 시작할 때 설정(ITEMS.MAX_DEPTH, ITEMS.AUTO_COMPLETE_PARENTS)에서 한 번 정한다
+static MAX_ITEM_DEPTH: AtomicI32 = AtomicI32::new(5);
+static AUTO_COMPLETE_PARENTS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_subtask_rules(max_depth: i32, auto_complete_parents: bool) {
+    MAX_ITEM_DEPTH.store(max_depth.max(1), Ordering::Relaxed);
+    AUTO_COMPLETE_PARENTS.store(auto_complete_parents, Ordering::Relaxed);
+}
+
+// 체크되지 않은 blocker가 있는 item을 체크할 때. false면 409, true면 체크하고 경고만 한다 (ITEMS.ALLOW_BLOCKED_CHECK)
+static ALLOW_BLOCKED_CHECK: AtomicBool = AtomicBool::new(false);
+
+pub fn set_allow_blocked_check(allow: bool) {
+    ALLOW_BLOCKED_CHECK.store(allow, Ordering::Relaxed);
+}
+
+pub fn allow_blocked_check() -> bool {
+    ALLOW_BLOCKED_CHECK.load(Ordering::Relaxed)
+}
+
+pub fn statement_cache_stats() -> StatementCacheStats {
+    StatementCacheStats {
+        enabled: STATEMENT_CACHE_ENABLED.load(Ordering::Relaxed),
+        hits: STATEMENT_CACHE_HITS.load(Ordering::Relaxed),
+        misses: STATEMENT_CACHE_MISSES.load(Ordering::Relaxed)
+    }
+}
+
+// 커넥션(Client)과 트랜잭션(Transaction) 모두에서 같은 prepare 캐시를 쓰기 위한 trait
+// 쿼리 실행 함수들(query, execute 등)은 GenericClient에 이미 정의되어 있다
+pub trait CachedClient: GenericClient {
+    fn statement_cache(&self) -> &StatementCache;
+}
+
+impl CachedClient for Client {
+    fn statement_cache(&self) -> &StatementCache {
+        &self.statement_cache
+    }
+}
+
+impl CachedClient for Transaction<'_> {
+    fn statement_cache(&self) -> &StatementCache {
+        &self.statement_cache
+    }
+}
+
+// client.prepare는 매 요청마다 postgres에 한 번 더 왕복을 하게 만든다.
+// prepare_cached는 같은 커넥션에서 이미 준비된 statement가 있으면 그것을 재사용한다.
+// 캐시에 새로 들어갔다면(size가 늘었다면) 실패, 아니면 적중으로 기록
+async fn prepare<C: CachedClient>(client: &C, query: &str) -> Result<Statement, AppError> {
+    if !STATEMENT_CACHE_ENABLED.load(Ordering::Relaxed) {
+        return client.prepare(query).await.map_err(AppError::db_error);
+    }
+
+    let before = client.statement_cache().size();
+    let statement = client.prepare_cached(query)
+        .await
+        .map_err(AppError::db_error)?;
+
+    if client.statement_cache().size() > before {
+        STATEMENT_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+    } else {
+        STATEMENT_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    Ok(statement)
+}
+
+// tag가 있으면 owner의 그 태그가 붙은 list만. reader가 볼 수 없는 list는 빠진다 (RequestContext::reader)
+pub async fn get_todos(client: &Client, templates: bool, tag: Option<&str>, owner: &str, reader: Option<&str>) -> Result<Vec<TodoList>, AppError> {
     // await를 써야하는지 아닌지는 타입을 체크해 보거나 직접 경험을 해 보는 수 밖에 없다.
     // statment: sql query를 준비하는데 사용하는 변수.
     // query를 최적화 시켜 주고 문제는 없는지 체크한다.
-    let statement = client.prepare("select * from todo_list order by id desc limit 10")
-        .await
-        // .map_err(|err| AppError{message: None, cause: Some(err.to_string()), error_type: AppErrorType::DbError})?;
-        .map_err(AppError::db_error)?;
+    // left join을 해야 item이 하나도 없는 list도 결과에 포함된다
+    let statement = prepare(client, "select l.id, l.title,
+                count(i.id) as item_count,
+                count(i.id) filter (where i.checked) as checked_count,
+                l.created_at, l.updated_at, l.version, l.position, l.is_template, l.deleted_at
+            from todo_list l
+            left join todo_item i on i.list_id = l.id and i.deleted_at is null
+            where l.deleted_at is null and l.is_template = $1 and list_readable(l.id, $4)
+                and ($2::text is null or exists (
+                    select 1 from list_tag lt
+                    join tag t on t.id = lt.tag_id
+                    where lt.list_id = l.id and t.owner = $3 and lower(t.name) = lower($2)))
+            group by l.id
+            order by l.position, l.id
+            limit 10").await?;
 
 
-    let todos = client.query(&statement, &[])
+    let todos = client.query(&statement, &[&templates, &tag, &owner, &reader])
                 .await
                 .expect("Error getting todo lists")
                 .iter()
@@ -30,10 +134,11 @@ pub async fn get_todos(client: &Client) -> Result<Vec<TodoList>, AppError> {
 
 pub async fn get_itmes(client: &Client, list_id: i32) -> Result<Vec<TodoItem>, AppError> {
 
-    let statement = client.prepare("select * from todo_item where list_id = $1 order by id")
-        .await
-        // .map_err(|err| AppError{message: None, cause: Some(err.to_string()), error_type: AppErrorType::DbError})?;
-        .map_err(AppError::db_error)?;
+    // 휴지통에 있는 item과, 휴지통에 있는 list의 item은 보여주지 않는다
+    let statement = prepare(client, "select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.deleted_at is null
+            order by i.position, i.id").await?;
 
     let itmes = client.query(&statement, &[&list_id])
                                         .await
@@ -45,52 +150,3267 @@ pub async fn get_itmes(client: &Client, list_id: i32) -> Result<Vec<TodoItem>, A
     Ok(itmes)
 }
 
-pub async fn create_todo(client: &Client, title: String) -> Result<TodoList, AppError> {
-    let statement = client.prepare("insert into todo_list (title) values ($1) returning id, title")
+// GET /todos/{list_id}/items 에 조건이 있을 때. 조건이 없는(null) 항목은 무시된다
+// list_id가 None이면 (GET /items) 템플릿이 아닌 모든 list에서 찾는다. 태그는 owner의 것
+// assignee가 있으면 그 사용자가 담당자인 item만 (GET /me/items는 요청한 사용자)
+pub async fn find_items(client: &Client, list_id: Option<i32>, owner: &str, query: &ItemsQuery, assignee: Option<&str>, reader: Option<&str>) -> Result<Vec<TodoItem>, AppError> {
+    let (all_tags, any_tags) = query.tag_filters();
+
+    // 태그 조건이 있으면 사용자의 태그에서 시작해서 item_tag의 (tag_id, item_id) 인덱스로 item을 찾는다
+    let statement = prepare(client, &format!("with wanted as (
+                select id, lower(name) as name from tag
+                where owner = $4 and lower(name) = any($5::text[] || $6::text[])
+            ),
+            tagged as (
+                select it.item_id from item_tag it
+                join wanted w on w.id = it.tag_id
+                group by it.item_id
+                having count(*) filter (where w.name = any($5)) = cardinality($5)
+                    and (cardinality($6) = 0 or count(*) filter (where w.name = any($6)) > 0)
+            )
+            select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where ($1::integer is null or i.list_id = $1) and i.deleted_at is null
+                and ($1::integer is not null or not l.is_template)
+                and ($2::timestamptz is null or i.due_at < $2)
+                and (not $3 or (i.due_at < now() and not i.checked))
+                and (cardinality($5) + cardinality($6) = 0 or i.id in (select item_id from tagged))
+                and ($7::text is null or i.status = $7)
+                and ($8::boolean is null or i.checked = $8)
+                and ($9::text is null or exists (select 1 from item_assignee a where a.item_id = i.id and a.assignee = $9))
+                and (not $10 or (not i.checked and not {HAS_UNCHECKED_BLOCKERS}))
+                and list_readable(l.id, $11)
+            order by l.position, l.id, i.position, i.id")).await?;
+
+    let items = client.query(&statement, &[&list_id, &query.due_before, &query.overdue, &owner, &all_tags, &any_tags, &query.status, &query.checked, &assignee, &query.ready, &reader])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| TodoItem::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoItem>>();
+
+    Ok(items)
+}
+
+// GET /agenda. 모든 list에서 마감 시간이 있는 item을 날짜별로 묶는다
+// 시간대 변환은 postgres가 한다 (at time zone). 모르는 시간대 이름이면 400
+pub async fn get_agenda(client: &Client, query: &AgendaQuery, reader: Option<&str>) -> Result<Agenda, AppError> {
+    let statement = prepare(client, "select coalesce($2::date, (now() at time zone tz.name)::date) as start
+            from pg_timezone_names tz where tz.name = $1").await?;
+
+    let from: NaiveDate = client.query_opt(&statement, &[&query.tz(), &query.from])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(|| AppError::validation(format!("Unknown time zone {}", query.tz())))?
+        .get("start");
+
+    // 범위 안에 마감인 item은 체크 여부와 상관없이, 범위 전에 마감인 item은 체크되지 않은 것만
+    let statement = prepare(client, "select i.*, (i.due_at at time zone $1)::date as due_date,
+                i.due_at < ($2::date)::timestamp at time zone $1 as overdue
+            from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.deleted_at is null and i.due_at is not null
+                and ($4::integer is null or i.list_id = $4) and list_readable(l.id, $5)
+                and i.due_at < ($2::date + $3::integer)::timestamp at time zone $1
+                and (i.due_at >= ($2::date)::timestamp at time zone $1 or not i.checked)
+            order by i.due_at, i.id").await?;
+
+    let rows = client.query(&statement, &[&query.tz(), &from, &query.days(), &query.list_id, &reader])
         .await
-        // .map_err(|err| AppError{message: None, cause: Some(err.to_string()), error_type: AppErrorType::DbError})?;
         .map_err(AppError::db_error)?;
 
+    let mut agenda = Agenda { timezone: query.tz().to_string(), from, days_count: query.days(), overdue: vec![], days: vec![] };
+
+    // 마감 시간 순으로 정렬되어 있으므로 날짜가 바뀔 때마다 새 묶음을 만든다
+    for row in &rows {
+        let item = TodoItem::from_row_ref(row).unwrap();
+        let date: NaiveDate = row.get("due_date");
+
+        if row.get("overdue") {
+            agenda.overdue.push(item);
+        } else {
+            match agenda.days.last_mut() {
+                Some(day) if day.date == date => day.items.push(item),
+                _ => agenda.days.push(AgendaDay { date, items: vec![item] })
+            }
+        }
+    }
+
+    Ok(agenda)
+}
+
+// 여러 list의 item을 한 번의 쿼리로 가져온다.
+// list마다 get_itmes를 부르면 list 수 만큼 쿼리가 나가기 때문 (N+1 문제)
+pub async fn get_items_for_lists(client: &Client, list_ids: &[i32]) -> Result<Vec<TodoItem>, AppError> {
 
-    client.query(&statement, &[&title])
+    let statement = prepare(client, "select * from todo_item where list_id = any($1) and deleted_at is null order by list_id, position, id").await?;
+
+    let items = client.query(&statement, &[&list_ids])
         .await
-        .expect("Error creating todo list")
+        .map_err(AppError::db_error)?
         .iter()
-        // 위의 값을 보면 하나의 값만을 반환한다. 하지만 아래에서는 map과 collection으로 억지로 배열로 만든다.
-        // 그 이유는 client.query는 무조건 vec 형태로 반환하기 때문
-        .map(|row| TodoList::from_row_ref(row).unwrap())
-        .collect::<Vec<TodoList>>()
-        .pop()
+        .map(|row| TodoItem::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoItem>>();
+
+    Ok(items)
+}
+
+// 데이터를 바꾸는 함수들은 모두 트랜잭션 안에서 감사 로그(audit_log)를 함께 남긴다.
+// 둘 중 하나라도 실패하면 둘 다 취소됨
+async fn record_audit<C: CachedClient>(client: &C, ctx: &RequestContext, action: &str, entity: &str, entity_id: Option<i32>, mut before: Option<Value>, mut after: Option<Value>) -> Result<(), AppError> {
+    // 검색용 컬럼(search_vector)은 title에서 계산되는 값이므로 기록하지 않는다 (웹훅, SSE로도 나가지 않음)
+    // record_audit을 거치지 않고 audit_log에 바로 쓰는 쿼리들(check_all, clear_completed, purge)은 쿼리에서 직접 뺀다
+    for snapshot in [&mut before, &mut after].into_iter().flatten() {
+        if let Some(fields) = snapshot.as_object_mut() {
+            fields.remove("search_vector");
+        }
+    }
+
+    let statement = prepare(client, "insert into audit_log (actor, action, entity, entity_id, before, after, request_id)
+            values ($1, $2, $3, $4, $5, $6, $7)").await?;
+
+    client.execute(&statement, &[&ctx.actor, &action, &entity, &entity_id, &before, &after, &ctx.request_id])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+pub async fn create_todo(client: &mut Client, ctx: &RequestContext, title: String) -> Result<TodoList, AppError> {
+    // 트랜잭션은 commit을 호출하지 않고 끝나면(drop) 자동으로 rollback 된다
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let todo = insert_todo(&tx, ctx, &title).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(todo)
+}
+
+async fn insert_todo<C: CachedClient>(client: &C, ctx: &RequestContext, title: &str) -> Result<TodoList, AppError> {
+    // to_jsonb(todo_list.*)는 방금 만들어진 row 전체를 json으로 바꾼 것. 감사 로그의 after에 기록
+    let statement = prepare(client, "insert into todo_list (title) values ($1)
+            returning id, title, 0::bigint as item_count, 0::bigint as checked_count, created_at, updated_at, version, position, is_template, deleted_at,
+                to_jsonb(todo_list.*) as snapshot").await?;
+
+    let row = client.query_opt(&statement, &[&title])
+        .await
+        .map_err(AppError::db_error)?
         .ok_or(AppError {
             message: Some("Error creating TODO list".to_string()),
             cause: Some("Unknown error".to_string()),
             error_type: AppErrorType::DbError
-        })
+        })?;
+
+    let todo = TodoList::from_row_ref(&row).unwrap();
+
+    record_audit(client, ctx, "create", "todo_list", Some(todo.id), None, row.get("snapshot")).await?;
+
+    // 만든 사용자가 owner. 멤버가 없는 list는 누구나 볼 수 있으므로 처음부터 멤버를 둔다
+    if ctx.authenticated {
+        let statement = prepare(client, "insert into list_member (list_id, member, role, added_by) values ($1, $2, 'owner', $2)").await?;
+        client.execute(&statement, &[&todo.id, &ctx.actor]).await.map_err(AppError::db_error)?;
+
+        let members = json!({ "members": [{ "member": ctx.actor, "role": "owner" }] });
+        record_audit(client, ctx, "create", "list_member", Some(todo.id), None, Some(members)).await?;
+    }
+
+    Ok(todo)
+}
+
+// POST /todos/import. list와 item을 하나의 트랜잭션에서 만들어서, 중간에 실패하면 아무것도 남지 않는다
+pub async fn import_todo(client: &mut Client, ctx: &RequestContext, title: &str, items: &[ImportedItem]) -> Result<TodoListWithItems, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let todo = insert_todo(&tx, ctx, title).await?;
+
+    let mut created = Vec::with_capacity(items.len());
+    for item in items {
+        created.push(insert_item(&tx, ctx, todo.id, &item.title, item.checked, None, &ItemSchedule::default()).await?);
+    }
+
+    // item_count, checked_count를 다시 계산
+    let list = get_todo(&tx, todo.id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoListWithItems { list, items: created })
+}
+
+// expected_version은 If-Match 로 받은 버전. None이면 (If-Match: *) 버전을 확인하지 않는다.
+// 성공하면 증가된 새 버전을 반환하고, 이미 체크되어 있거나 item이 없으면 None
+pub async fn check_item(cleint: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, expected_version: Option<i32>) -> Result<Option<i32>, AppError> {
+
+    let tx = cleint.transaction().await.map_err(AppError::db_error)?;
+
+    let updated = match set_item_checked(&tx, ctx, list_id, item_id, true, expected_version).await {
+        Ok(updated) => updated,
+        // 이 API는 item이 없을 때 404 대신 success: false 를 돌려준다
+        Err(AppError { error_type: AppErrorType::NotFoundError, .. }) => None,
+        Err(err) => return Err(err)
+    };
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(updated.map(|item| item.version))
+}
+
+// check_item과 batch가 함께 사용한다. 트랜잭션은 호출하는 쪽에서 관리
+// item이 없으면 404, 이미 checked 상태이면 None
+async fn set_item_checked<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32, checked: bool, expected_version: Option<i32>) -> Result<Option<TodoItem>, AppError> {
+    let updated = update_item_checked(client, ctx, list_id, item_id, checked, expected_version).await?;
+
+    if let Some(item) = &updated {
+        roll_up_parents(client, ctx, item).await?;
+    }
+
+    Ok(updated)
+}
+
+// ITEMS.AUTO_COMPLETE_PARENTS 일 때, 체크한 item의 상위 item들에 반영한다 (같은 트랜잭션)
+// - 체크: 상위 item의 하위 item이 모두 체크되었으면 상위 item도 체크. 그 위로도 같은 방법으로 올라간다
+// - 체크 해제: 체크된 상위 item들의 체크를 푼다
+async fn roll_up_parents<C: CachedClient>(client: &C, ctx: &RequestContext, item: &TodoItem) -> Result<(), AppError> {
+    if !AUTO_COMPLETE_PARENTS.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let statement = prepare(client, "select p.parent_id, p.checked, bool_and(c.checked) as all_checked
+            from todo_item p
+            left join todo_item c on c.parent_id = p.id and c.deleted_at is null
+            where p.id = $1 and p.deleted_at is null
+            group by p.id").await?;
+
+    let mut parent_id = item.parent_id;
+    while let Some(id) = parent_id {
+        let parent = match client.query_opt(&statement, &[&id]).await.map_err(AppError::db_error)? {
+            Some(parent) => parent,
+            None => break
+        };
+
+        let should_change = parent.get::<_, bool>("checked") != item.checked
+            && (!item.checked || parent.get::<_, Option<bool>>("all_checked").unwrap_or(false));
+        if !should_change {
+            break;
+        }
+        // 막혀 있는 상위 item은 자동으로 체크하지 않는다 (하위 item의 체크까지 실패하지 않도록)
+        if item.checked && !allow_blocked_check() && !unchecked_blockers(client, id).await?.is_empty() {
+            break;
+        }
+
+        update_item_checked(client, ctx, item.list_id, id, item.checked, None).await?;
+        parent_id = parent.get("parent_id");
+    }
+
+    Ok(())
+}
+
+// 체크 상태 하나만 바꾼다. 반복 item이면 다음 item을 만든다
+async fn update_item_checked<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32, checked: bool, expected_version: Option<i32>) -> Result<Option<TodoItem>, AppError> {
+    // 체크하면 상태도 바뀌므로 WIP 제한을 확인해야 한다
+    lock_wip_limits(client, list_id).await?;
+
+    // for update로 row를 잠궈서, 동시에 두 요청이 들어와도 버전 비교와 수정 사이에 끼어들 수 없게 한다
+    let statement = prepare(client, "select i.*, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+    let item = TodoItem::from_row_ref(&current).unwrap();
+
+    // 버전이 다르면 이미 체크된 item이라도 412를 돌려준다 (조건 검사가 먼저)
+    check_version(item.version, expected_version)?;
+
+    if item.checked == checked {
+        return Ok(None);
+    }
+
+    if checked && !allow_blocked_check() {
+        let blockers = unchecked_blockers(client, item_id).await?;
+        if !blockers.is_empty() {
+            return Err(AppError::blocked(&blockers));
+        }
+    }
+
+    // set checked = $2 라는 소리는 checked 항목을 $2로 바꾸겠다는 소리
+    // 반복 item을 체크하면 반복 규칙은 다음 item으로 옮겨가므로 여기서는 지운다
+    let statement = prepare(client, "update todo_item
+            set checked = $2,
+                recurrence = case when $2 then null else recurrence end,
+                recurrence_tz = case when $2 then null else recurrence_tz end,
+                version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = client.query_one(&statement, &[&item_id, &checked])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let action = if checked { "check" } else { "uncheck" };
+    record_audit(client, ctx, action, "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    check_wip_limit(client, list_id, updated.get("status")).await?;
+
+    if checked && item.recurrence.is_some() {
+        create_next_occurrence(client, ctx, &item).await?;
+    }
+
+    Ok(Some(TodoItem::from_row_ref(&updated).unwrap()))
+}
+
+// 반복 item이 완료되었을 때 (체크하는 트랜잭션 안에서)
+// 1. 완료 기록을 남기고  2. 다음 발생 시간을 마감으로 하는 새 item을 만든다 (반복 규칙은 새 item으로)
+// item은 체크되기 전의 값
+async fn create_next_occurrence<C: CachedClient>(client: &C, ctx: &RequestContext, item: &TodoItem) -> Result<TodoItem, AppError> {
+    let rule = Recurrence::parse(item.recurrence.as_deref().unwrap_or_default())?;
+    let tz = item.recurrence_tz.as_deref().unwrap_or("UTC");
+    let series_id = item.series_id.unwrap_or(item.id);
+
+    let statement = prepare(client, "insert into item_completion (series_id, item_id, list_id, title, due_at, completed_by)
+            values ($1, $2, $3, $4, $5, $6)").await?;
+
+    client.execute(&statement, &[&series_id, &item.id, &item.list_id, &item.title, &item.due_at, &ctx.actor])
+        .await
+        .map_err(AppError::db_error)?;
+
+    // 요일과 날짜는 item의 시간대로 계산해야 하므로(ex. 한국 시간 월요일 오전 8시는 UTC로 일요일)
+    // 시간대 시각으로 바꿔서 계산하고 다시 UTC로 돌린다. 마감이 없으면 지금을 기준으로 한다
+    let statement = prepare(client, "select coalesce($1, now()) at time zone $2 as base, now() at time zone $2 as now").await?;
+    let row = client.query_one(&statement, &[&item.due_at, &tz])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let base: NaiveDateTime = row.get("base");
+    let next = rule.next_after_now(base, row.get("now"));
+
+    let statement = prepare(client, "select $1::timestamp at time zone $2 as next").await?;
+    let due_at: DateTime<Utc> = client.query_one(&statement, &[&next, &tz])
+        .await
+        .map_err(AppError::db_error)?
+        .get("next");
+
+    // 알림은 마감과의 간격을 그대로 유지한다
+    let remind_at = item.remind_at.map(|remind_at| remind_at + (next - base));
+
+    // 상태, 우선순위, notes는 insert_copies처럼 그대로 가져간다. item은 체크되기 전의 값이므로 상태도 체크되지 않은 상태
+    let statement = prepare(client, "insert into todo_item (title, list_id, parent_id, due_at, remind_at, recurrence, recurrence_tz, series_id, status, priority, notes)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let row = client.query_one(&statement, &[&item.title, &item.list_id, &item.parent_id, &due_at, &remind_at, &item.recurrence, &item.recurrence_tz, &series_id,
+            &item.status, &item.priority, &item.notes])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let next_item = TodoItem::from_row_ref(&row).unwrap();
+
+    // 같은 일을 반복하는 것이므로 담당자와 태그도 이어진다. 이미 담당자였으므로 assigned 알림은 보내지 않는다
+    let statement = prepare(client, "insert into item_assignee (item_id, assignee, assigned_by, assigned_at)
+            select $2, assignee, assigned_by, assigned_at from item_assignee where item_id = $1").await?;
+    client.execute(&statement, &[&item.id, &next_item.id]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(client, "insert into item_tag (item_id, tag_id)
+            select $2, tag_id from item_tag where item_id = $1").await?;
+    client.execute(&statement, &[&item.id, &next_item.id]).await.map_err(AppError::db_error)?;
+
+    record_audit(client, ctx, "create", "todo_item", Some(next_item.id), None, row.get("snapshot")).await?;
+
+    Ok(next_item)
+}
+
+// PUT /todos/{list_id}/items/{item_id}/parent. parent_id가 None이면 최상위로
+pub async fn set_item_parent(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, parent_id: Option<i32>, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select i.version, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = tx.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    if let Some(parent_id) = parent_id {
+        check_parent(&tx, list_id, parent_id, Some(item_id)).await?;
+    }
+
+    let statement = prepare(&tx, "update todo_item
+            set parent_id = $2, version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&item_id, &parent_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "move", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+// 같은 list의 item 구조(순서, 상위 item)를 바꾸는 트랜잭션을 list 단위로 차례대로 실행한다 (트랜잭션이 끝나면 풀림)
+async fn lock_list_items<C: CachedClient>(client: &C, list_id: i32) -> Result<(), AppError> {
+    let statement = prepare(client, "select pg_advisory_xact_lock('todo_item'::regclass::integer, $1)").await?;
+    client.execute(&statement, &[&list_id]).await.map_err(AppError::db_error)?;
+    Ok(())
+}
+
+// 다시 번호를 매길 때의 간격. init.pgsql의 기본값과 같다
+const POSITION_GAP: i64 = 65536;
+
+// {"before": id} 또는 {"after": id} 중 하나. (기준 id, 뒤로 옮기는지)
+fn move_anchor(request: &MoveRequest) -> Result<(i32, bool), AppError> {
+    match (request.before, request.after) {
+        (Some(before), None) => Ok((before, false)),
+        (None, Some(after)) => Ok((after, true)),
+        _ => Err(AppError::validation("Either before or after is required, but not both"))
+    }
+}
+
+// 이웃한 두 위치 사이의 새 위치. 사이에 남은 값이 없으면 None (다시 번호를 매겨야 함)
+fn position_between(previous: Option<i64>, next: Option<i64>) -> Option<i64> {
+    match (previous, next) {
+        (Some(previous), Some(next)) if next - previous >= 2 => Some(previous + (next - previous) / 2),
+        (Some(_), Some(_)) => None,
+        (Some(previous), None) => Some(previous + POSITION_GAP),
+        (None, Some(next)) => Some(next - POSITION_GAP),
+        (None, None) => Some(0)
+    }
+}
+
+// 기준 item의 바로 앞/뒤에 올 위치. 옮기는 item(moving)은 이웃에서 뺀다
+async fn item_position<C: CachedClient>(client: &C, list_id: i32, moving: i32, anchor: i32, after: bool) -> Result<Option<i64>, AppError> {
+    if anchor == moving {
+        return Err(AppError::validation("An item cannot be moved next to itself"));
+    }
+
+    // 위치가 같은 item이 있을 수 있으므로 (position, id) 순서로 이웃을 찾는다
+    let statement = prepare(client, "select a.position,
+                (select n.position from todo_item n
+                    where n.list_id = a.list_id and n.deleted_at is null and n.id <> $3
+                        and (n.position, n.id) > (a.position, a.id)
+                    order by n.position, n.id limit 1) as next,
+                (select p.position from todo_item p
+                    where p.list_id = a.list_id and p.deleted_at is null and p.id <> $3
+                        and (p.position, p.id) < (a.position, a.id)
+                    order by p.position desc, p.id desc limit 1) as previous
+            from todo_item a
+            where a.list_id = $1 and a.id = $2 and a.deleted_at is null").await?;
+
+    let row = client.query_opt(&statement, &[&list_id, &anchor, &moving])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(|| AppError::validation(format!("Item {} was not found in this list", anchor)))?;
+
+    Ok(if after {
+        position_between(Some(row.get("position")), row.get("next"))
+    } else {
+        position_between(row.get("previous"), Some(row.get("position")))
+    })
+}
+
+// 사이에 남은 값이 없으면 list 전체의 순서는 그대로 두고 간격만 다시 벌린다
+// 위치가 바뀐 item은 version과 updated_at도 바뀌므로(ETag) 감사 로그에 한 번에 남긴다 (action reorder_items)
+// item마다 남기지 않고 하나의 reordered 이벤트로 SSE, WebSocket에 전달된다
+// 휴지통에 있는 item은 보이지 않으므로 그대로 둔다
+async fn rebalance_items<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32) -> Result<(), AppError> {
+    let statement = prepare(client, "with ranked as (
+                select id, row_number() over (order by position, id) * $2 as position
+                from todo_item where list_id = $1 and deleted_at is null
+            ),
+            changed as (
+                update todo_item i set position = r.position, version = i.version + 1, updated_at = now()
+                from ranked r
+                where r.id = i.id and i.position <> r.position
+                returning i.id, i.position, i.version, i.dav_name
+            )
+            select coalesce(jsonb_agg(to_jsonb(changed) order by position), '[]'::jsonb) as items from changed").await?;
+
+    let items: Value = client.query_one(&statement, &[&list_id, &POSITION_GAP])
+        .await
+        .map_err(AppError::db_error)?
+        .get("items");
+
+    let after = json!({ "list_id": list_id, "items": items });
+    record_audit(client, ctx, "reorder_items", "todo_list", Some(list_id), None, Some(after)).await
+}
+
+// POST /todos/{list_id}/items/{item_id}/move
+pub async fn move_item(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, request: &MoveRequest, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let (anchor, after) = move_anchor(request)?;
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // 같은 list에서 동시에 옮기면 같은 빈자리를 고를 수 있으므로 차례대로 처리
+    lock_list_items(&tx, list_id).await?;
+
+    let statement = prepare(&tx, "select i.version, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = tx.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    let position = match item_position(&tx, list_id, item_id, anchor, after).await? {
+        Some(position) => position,
+        None => {
+            rebalance_items(&tx, ctx, list_id).await?;
+            item_position(&tx, list_id, item_id, anchor, after).await?
+                .ok_or_else(|| AppError::db_error("Could not find a position after rebalancing"))?
+        }
+    };
+
+    let statement = prepare(&tx, "update todo_item
+            set position = $2, version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&item_id, &position])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "move", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
 }
 
-pub async fn check_item(cleint: &Client, list_id: i32, item_id: i32) -> Result<bool, AppError> {
+// 기준 list의 바로 앞/뒤에 올 위치. item_position과 같은 방법
+async fn list_position<C: CachedClient>(client: &C, moving: i32, anchor: i32, after: bool) -> Result<Option<i64>, AppError> {
+    if anchor == moving {
+        return Err(AppError::validation("A list cannot be moved next to itself"));
+    }
+
+    let statement = prepare(client, "select a.position,
+                (select n.position from todo_list n
+                    where n.deleted_at is null and n.id <> $2
+                        and (n.position, n.id) > (a.position, a.id)
+                    order by n.position, n.id limit 1) as next,
+                (select p.position from todo_list p
+                    where p.deleted_at is null and p.id <> $2
+                        and (p.position, p.id) < (a.position, a.id)
+                    order by p.position desc, p.id desc limit 1) as previous
+            from todo_list a
+            where a.id = $1 and a.deleted_at is null").await?;
+
+    let row = client.query_opt(&statement, &[&anchor, &moving])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(|| AppError::validation(format!("List {} was not found", anchor)))?;
+
+    Ok(if after {
+        position_between(Some(row.get("position")), row.get("next"))
+    } else {
+        position_between(row.get("previous"), Some(row.get("position")))
+    })
+}
+
+// 새 list는 맨 앞(더 작은 값)에 오므로 음수 쪽으로 다시 번호를 매긴다
+// rebalance_items와 같이 바뀐 list의 version을 올리고 감사 로그는 한 번만 (list 하나의 변경이 아니므로 entity_id 없음)
+async fn rebalance_lists<C: CachedClient>(client: &C, ctx: &RequestContext) -> Result<(), AppError> {
+    let statement = prepare(client, "with ranked as (
+                select id, (row_number() over (order by position, id) - count(*) over () - 1) * $1 as position
+                from todo_list where deleted_at is null
+            ),
+            changed as (
+                update todo_list l set position = r.position, version = l.version + 1, updated_at = now()
+                from ranked r
+                where r.id = l.id and l.position <> r.position
+                returning l.id, l.position, l.version
+            )
+            select coalesce(jsonb_agg(to_jsonb(changed) order by position), '[]'::jsonb) as lists from changed").await?;
+
+    let lists: Value = client.query_one(&statement, &[&POSITION_GAP])
+        .await
+        .map_err(AppError::db_error)?
+        .get("lists");
+
+    record_audit(client, ctx, "reorder", "todo_list", None, None, Some(json!({ "lists": lists }))).await
+}
+
+// POST /todos/{list_id}/move
+pub async fn move_todo(client: &mut Client, ctx: &RequestContext, list_id: i32, request: &MoveRequest, expected_version: Option<i32>) -> Result<TodoList, AppError> {
+    let (anchor, after) = move_anchor(request)?;
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // list 순서는 하나뿐이므로 list를 옮기는 트랜잭션은 모두 차례대로
+    let statement = prepare(&tx, "select pg_advisory_xact_lock('todo_list'::regclass::integer, 0)").await?;
+    tx.execute(&statement, &[]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select version, to_jsonb(l) as snapshot from todo_list l
+            where id = $1 and deleted_at is null
+            for update").await?;
+
+    let current = tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
 
-    // set chcked = true 라는 소리는 checked 항목을 true로 바꾸겠다는 소리
-    let statement = cleint.prepare("update todo_item set checked = true where list_id = $1 and id = $2 and checked = false")
+    check_version(current.get("version"), expected_version)?;
+
+    let position = match list_position(&tx, list_id, anchor, after).await? {
+        Some(position) => position,
+        None => {
+            rebalance_lists(&tx, ctx).await?;
+            list_position(&tx, list_id, anchor, after).await?
+                .ok_or_else(|| AppError::db_error("Could not find a position after rebalancing"))?
+        }
+    };
+
+    let statement = prepare(&tx, "update todo_list
+            set position = $2, version = version + 1, updated_at = now()
+            where id = $1
+            returning to_jsonb(todo_list.*) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&list_id, &position])
         .await
         .map_err(AppError::db_error)?;
 
-    // 결과물은 업데이트 된 todo의 수
-    // 1개가 없데이터 되었다면 결과 값은 1
-    let result = cleint.execute(&statement, &[&list_id, &item_id])
-                                                        .await
-                                                        .expect("Error checking todo item");
+    record_audit(&tx, ctx, "move", "todo_list", Some(list_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    let todo = get_todo(&tx, list_id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(todo)
+}
+
+// 옮기거나 복사할 item들. item_ids(None이면 list 전체)와 그 하위 item 전부를, 상위 item이 먼저 오는 순서로
+// 함께 돌려주는 숫자는 원래 list에서의 순서 (1부터)
+async fn select_subtrees<C: CachedClient>(client: &C, list_id: i32, item_ids: Option<&[i32]>) -> Result<Vec<(TodoItem, i64)>, AppError> {
+    // 상위 item과 하위 item을 함께 고른 경우 하위 item은 두 번 나오므로, 가장 깊은 쪽을 기준으로 정렬한다
+    let statement = prepare(client, "with recursive subtree as (
+                select id, 0 as depth from todo_item
+                where list_id = $1 and deleted_at is null and ($2::integer[] is null or id = any($2))
+                union
+                select i.id, s.depth + 1 from todo_item i
+                join subtree s on i.parent_id = s.id
+                where i.deleted_at is null and s.depth < 100
+            )
+            select i.*, row_number() over (order by i.position, i.id) as rank
+            from (select id, max(depth) as depth from subtree group by id) s
+            join todo_item i on i.id = s.id
+            order by s.depth, i.position, i.id").await?;
+
+    let items = client.query(&statement, &[&list_id, &item_ids])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| (TodoItem::from_row_ref(row).unwrap(), row.get::<_, i64>("rank")))
+        .collect::<Vec<(TodoItem, i64)>>();
+
+    if let Some(missing) = item_ids.unwrap_or_default().iter().find(|id| !items.iter().any(|(item, _)| item.id == **id)) {
+        return Err(AppError::validation(format!("Item {} was not found in this list", missing)));
+    }
+
+    Ok(items)
+}
+
+// 옮기기/복사는 두 list가 모두 있어야 하고, 그 사이에 list가 삭제되지 않도록 잡아둔다
+// 두 list의 item 순서도 함께 잠근다 (교착을 피하려고 id 순서로)
+async fn lock_transfer_lists<C: CachedClient>(client: &C, from_list_id: i32, to_list_id: i32) -> Result<(), AppError> {
+    let statement = prepare(client, "select id from todo_list where id = any($1) and deleted_at is null order by id for share").await?;
+
+    let found = client.query(&statement, &[&vec![from_list_id, to_list_id]])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| row.get::<_, i32>("id"))
+        .collect::<Vec<i32>>();
+
+    if !found.contains(&from_list_id) {
+        return Err(AppError::not_found());
+    }
+    if !found.contains(&to_list_id) {
+        return Err(AppError::validation(format!("List {} was not found", to_list_id)));
+    }
+
+    for list_id in found {
+        lock_list_items(client, list_id).await?;
+    }
+
+    Ok(())
+}
+
+// list 맨 뒤의 위치. 여기에 POSITION_GAP * 순서를 더해서 붙인다
+async fn last_position<C: CachedClient>(client: &C, list_id: i32) -> Result<i64, AppError> {
+    let statement = prepare(client, "select coalesce(max(position), 0) as position from todo_item where list_id = $1").await?;
+
+    client.query_one(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)
+        .map(|row| row.get("position"))
+}
+
+fn transfer_ids(request: &TransferItems) -> Result<Vec<i32>, AppError> {
+    let mut item_ids = request.item_ids.clone();
+    item_ids.sort_unstable();
+    item_ids.dedup();
+
+    if item_ids.is_empty() {
+        return Err(AppError::validation("item_ids must not be empty"));
+    }
+    Ok(item_ids)
+}
+
+// POST /todos/{list_id}/items:move
+// 하위 item도 함께 옮긴다. 상위 item이 함께 옮겨지지 않으면 새 list에서 최상위 item이 된다
+pub async fn move_items(client: &mut Client, ctx: &RequestContext, list_id: i32, request: &TransferItems) -> Result<Vec<TodoItem>, AppError> {
+    let item_ids = transfer_ids(request)?;
+    if request.to_list_id == list_id {
+        return Err(AppError::validation("Items are already in this list, use /move with before or after to reorder"));
+    }
+
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+    lock_transfer_lists(&tx, list_id, request.to_list_id).await?;
+
+    let items = select_subtrees(&tx, list_id, Some(&item_ids)).await?;
+    let base = last_position(&tx, request.to_list_id).await?;
+
+    let ids: Vec<i32> = items.iter().map(|(item, _)| item.id).collect();
+    let positions: Vec<i64> = items.iter().map(|(_, rank)| base + rank * POSITION_GAP).collect();
+
+    // CalDAV 리소스 이름은 list 안에서만 유일하므로 옮기면서 지운다 ({id}.ics 가 됨)
+    let statement = prepare(&tx, "update todo_item i
+            set list_id = $1, position = m.position, version = i.version + 1, updated_at = now(), dav_name = null,
+                parent_id = case when i.parent_id = any($2) then i.parent_id end
+            from todo_item old, unnest($2::integer[], $3::bigint[]) as m(id, position)
+            where i.id = m.id and old.id = i.id
+            returning i.*, to_jsonb(old) as before, to_jsonb(i) as snapshot").await?;
+
+    let rows = tx.query(&statement, &[&request.to_list_id, &ids, &positions])
+        .await
+        .map_err(AppError::db_error)?;
 
-    match result {
-        // ref는 참조를 발생. 즉 result의 값을 updated에 참조 시키는 것
-        // * 는 역참조를 뜻함. 즉 *updated는 updated의 실제 값을 가져오는 것
-        // 언뜻 보기에는 참조를 한 뒤, 역참조를 하는것 처럼 보여, 의미 없는 작업처럼 보일 수 있으나
-        // result의 값을 그대로 사용할 시, result의 소유권이 check_item이 아니라 match쪽으로 넘어간다.
-        // 그렇게 되면 result는 match 안에서는 사용할 수 있으나, 더이상 check_item에서는 사용할 수 없게 된다.
-        // 그렇기에 result의 값만 가져와 새로운 변수에 저장하기 위해서는
-        // 우선 참조를 한 뒤, 역 참조를 해 그 값만 가져올 수 밖에 없다.
-        ref updated if *updated == 1 => Ok(true),
-        // _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list"))
-        _ => Ok(false)
+    let mut moved = Vec::with_capacity(rows.len());
+    for row in rows {
+        let item = TodoItem::from_row_ref(&row).unwrap();
+        record_audit(&tx, ctx, "move", "todo_item", Some(item.id), row.get("before"), row.get("snapshot")).await?;
+        moved.push(item);
     }
-}
\ No newline at end of file
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    moved.sort_by_key(|item| item.position);
+    Ok(moved)
+}
+
+// POST /todos/{list_id}/items:copy. 같은 list에 복사해도 된다
+pub async fn copy_items(client: &mut Client, ctx: &RequestContext, list_id: i32, request: &TransferItems) -> Result<Vec<TodoItem>, AppError> {
+    let item_ids = transfer_ids(request)?;
+
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+    lock_transfer_lists(&tx, list_id, request.to_list_id).await?;
+
+    let items = select_subtrees(&tx, list_id, Some(&item_ids)).await?;
+    let base = last_position(&tx, request.to_list_id).await?;
+    let copied = insert_copies(&tx, ctx, items, request.to_list_id, base, request.reset_checked).await?;
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(copied)
+}
+
+// select_subtrees의 item들을 list_id에 새로 만든다. 상위 item이 먼저 오므로 새 id로 바꿔서 연결할 수 있다
+// 마감, 알림, 반복 규칙은 그대로 복사하고, 반복 묶음(series_id)과 CalDAV 정보는 새로
+async fn insert_copies<C: CachedClient>(client: &C, ctx: &RequestContext, items: Vec<(TodoItem, i64)>, list_id: i32, base: i64, reset_checked: bool) -> Result<Vec<TodoItem>, AppError> {
+    // 상태가 복사할 list에 없거나 체크를 풀면 체크 여부에 맞는 첫 상태가 된다 (todo_item_sync_status)
+    let statement = prepare(client, "insert into todo_item (title, list_id, checked, parent_id, position, due_at, remind_at, recurrence, recurrence_tz, status, priority, notes)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let mut new_ids: HashMap<i32, i32> = HashMap::new();
+    let mut copied = Vec::with_capacity(items.len());
+
+    for (item, rank) in items {
+        let parent_id = item.parent_id.and_then(|parent_id| new_ids.get(&parent_id).copied());
+        let checked = item.checked && !reset_checked;
+        let position = base + rank * POSITION_GAP;
+
+        let row = client.query_one(&statement, &[&item.title, &list_id, &checked, &parent_id, &position,
+                &item.due_at, &item.remind_at, &item.recurrence, &item.recurrence_tz, &item.status, &item.priority, &item.notes])
+            .await
+            .map_err(AppError::db_error)?;
+
+        let created = TodoItem::from_row_ref(&row).unwrap();
+        record_audit(client, ctx, "create", "todo_item", Some(created.id), None, row.get("snapshot")).await?;
+
+        new_ids.insert(item.id, created.id);
+        copied.push(created);
+    }
+
+    copied.sort_by_key(|item| item.position);
+    Ok(copied)
+}
+
+// POST /todos/{list_id}/duplicate
+// 템플릿 list는 title의 모든 변수에 값이 있어야 한다 (없으면 422). 만들어진 list는 일반 list
+pub async fn duplicate_todo(client: &mut Client, ctx: &RequestContext, list_id: i32, request: &DuplicateTodo) -> Result<TodoListWithItems, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select title, is_template from todo_list where id = $1 and deleted_at is null for share").await?;
+    let source = tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let mut variables = Variables::new(request.variables.clone());
+    let title = variables.render(request.title.as_deref().unwrap_or(source.get("title")));
+
+    let mut items = select_subtrees(&tx, list_id, None).await?;
+    for (item, _) in items.iter_mut() {
+        item.title = variables.render(&item.title);
+    }
+
+    if source.get("is_template") && !variables.missing.is_empty() {
+        return Err(AppError::unprocessable(format!("Missing template variables: {}", variables.missing.join(", "))));
+    }
+    // 변수를 채우면서 길어졌을 수 있다
+    for (item, _) in items.iter() {
+        valid_title(&item.title)?;
+    }
+
+    let todo = insert_todo(&tx, ctx, valid_title(&title)?).await?;
+
+    // 상태 흐름도 함께 복사한다. item보다 먼저 있어야 item의 상태가 유지된다
+    let statement = prepare(&tx, "insert into list_status (list_id, key, name, position, done, wip_limit)
+            select $2, key, name, position, done, wip_limit from list_status where list_id = $1").await?;
+    tx.execute(&statement, &[&list_id, &todo.id]).await.map_err(AppError::db_error)?;
+
+    let items = insert_copies(&tx, ctx, items, todo.id, 0, request.reset_checked).await?;
+
+    let list = get_todo(&tx, todo.id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoListWithItems { list, items })
+}
+
+// PUT /todos/{list_id}/template. If-Match 필요
+pub async fn set_todo_template(client: &mut Client, ctx: &RequestContext, list_id: i32, is_template: bool, expected_version: Option<i32>) -> Result<TodoList, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select version, to_jsonb(l) as snapshot from todo_list l
+            where id = $1 and deleted_at is null
+            for update").await?;
+
+    let current = tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    let statement = prepare(&tx, "update todo_list
+            set is_template = $2, version = version + 1, updated_at = now()
+            where id = $1
+            returning to_jsonb(todo_list.*) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&list_id, &is_template])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "template", "todo_list", Some(list_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    let todo = get_todo(&tx, list_id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(todo)
+}
+
+// list의 상태 흐름. 정하지 않은 list는 todo, done
+pub async fn get_workflow<C: CachedClient>(client: &C, list_id: i32) -> Result<Vec<ListStatus>, AppError> {
+    let statement = prepare(client, "select * from list_status where list_id = $1 order by position").await?;
+
+    let statuses = client.query(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| ListStatus::from_row_ref(row).unwrap())
+        .collect::<Vec<ListStatus>>();
+
+    Ok(if statuses.is_empty() { ListStatus::defaults() } else { statuses })
+}
+
+// PUT /todos/{list_id}/workflow. 없어진 상태의 item은 완료 여부가 같은 첫 상태로 옮긴다
+// WIP 제한은 이후의 상태 변경부터 적용된다 (이미 넘은 열의 item은 그대로)
+pub async fn set_workflow(client: &mut Client, ctx: &RequestContext, list_id: i32, statuses: &[ListStatus]) -> Result<Vec<ListStatus>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select id from todo_list where id = $1 and deleted_at is null for update").await?;
+    tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    lock_list_items(&tx, list_id).await?;
+    let before = get_workflow(&tx, list_id).await?;
+
+    let keys: Vec<&str> = statuses.iter().map(|status| status.key.as_str()).collect();
+    let names: Vec<&str> = statuses.iter().map(|status| status.name.trim()).collect();
+    let positions: Vec<i32> = (1..=statuses.len() as i32).collect();
+    let done: Vec<bool> = statuses.iter().map(|status| status.done).collect();
+    let wip_limits: Vec<Option<i32>> = statuses.iter().map(|status| status.wip_limit).collect();
+
+    let statement = prepare(&tx, "delete from list_status where list_id = $1").await?;
+    tx.execute(&statement, &[&list_id]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "insert into list_status (list_id, key, name, position, done, wip_limit)
+            select $1, s.key, s.name, s.position, s.done, s.wip_limit
+            from unnest($2::text[], $3::text[], $4::integer[], $5::boolean[], $6::integer[]) as s(key, name, position, done, wip_limit)").await?;
+    tx.execute(&statement, &[&list_id, &keys, &names, &positions, &done, &wip_limits]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "with changed as (
+                update todo_item i
+                set status = (select s.key from list_status s where s.list_id = i.list_id and s.done = i.checked order by s.position limit 1),
+                    version = i.version + 1, updated_at = now()
+                from todo_item old
+                where old.id = i.id and i.list_id = $1 and i.status <> all($2)
+                returning i.id, to_jsonb(old) - 'search_vector' as before, to_jsonb(i) - 'search_vector' as after
+            )
+            insert into audit_log (actor, action, entity, entity_id, before, after, request_id)
+            select $3, 'status', 'todo_item', id, before, after, $4 from changed").await?;
+    tx.execute(&statement, &[&list_id, &keys, &ctx.actor, &ctx.request_id]).await.map_err(AppError::db_error)?;
+
+    let after = get_workflow(&tx, list_id).await?;
+    record_audit(&tx, ctx, "update", "list_status", Some(list_id), Some(json!({ "statuses": before })), Some(json!({ "statuses": after }))).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(after)
+}
+
+// list에 WIP 제한이 있으면, 상태를 바꾸기 전에 list 단위로 잠근다
+// item row보다 먼저 잠궈야 순서를 바꾸는 요청(move_item)과 서로 기다리지 않는다
+async fn lock_wip_limits<C: CachedClient>(client: &C, list_id: i32) -> Result<(), AppError> {
+    let statement = prepare(client, "select exists (select 1 from list_status where list_id = $1 and wip_limit is not null) as limited").await?;
+
+    let limited: bool = client.query_one(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .get("limited");
+
+    if limited {
+        lock_list_items(client, list_id).await?;
+    }
+    Ok(())
+}
+
+// 상태를 바꾼 뒤에 확인한다. 넘으면 409이고, 트랜잭션이 rollback 되므로 바뀐 것도 취소된다
+async fn check_wip_limit<C: CachedClient>(client: &C, list_id: i32, status: &str) -> Result<(), AppError> {
+    let statement = prepare(client, "select s.name, s.wip_limit,
+                (select count(*) from todo_item i where i.list_id = s.list_id and i.status = s.key and i.deleted_at is null) as count
+            from list_status s
+            where s.list_id = $1 and s.key = $2 and s.wip_limit is not null").await?;
+
+    if let Some(row) = client.query_opt(&statement, &[&list_id, &status]).await.map_err(AppError::db_error)? {
+        let limit: i32 = row.get("wip_limit");
+        if row.get::<_, i64>("count") > limit as i64 {
+            return Err(AppError::conflict(format!("{} already has {} items, which is its WIP limit", row.get::<_, String>("name"), limit)));
+        }
+    }
+    Ok(())
+}
+
+// PUT /todos/{list_id}/items/{item_id}/status. If-Match 필요
+// 완료 여부가 바뀌면 체크/체크 해제와 같이 처리한다 (반복 item, 상위 item 완료)
+pub async fn set_item_status(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, status: &str, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let workflow = get_workflow(&tx, list_id).await?;
+    let target = workflow.iter()
+        .find(|column| column.key == status)
+        .ok_or_else(|| AppError::validation(format!("Unknown status {}, this list has {}", status,
+            workflow.iter().map(|column| column.key.as_str()).collect::<Vec<&str>>().join(", "))))?;
+
+    lock_wip_limits(&tx, list_id).await?;
+
+    let item = get_item_for_update(&tx, list_id, item_id).await?;
+    check_version(item.version, expected_version)?;
+
+    if item.checked != target.done {
+        set_item_checked(&tx, ctx, list_id, item_id, target.done, None).await?;
+    }
+
+    let statement = prepare(&tx, "select i.*, to_jsonb(i) as snapshot from todo_item i where id = $1 for update").await?;
+    let current = tx.query_one(&statement, &[&item_id]).await.map_err(AppError::db_error)?;
+
+    let item = if current.get::<_, String>("status") == status {
+        TodoItem::from_row_ref(&current).unwrap()
+    } else {
+        let statement = prepare(&tx, "update todo_item
+                set status = $2, version = version + 1, updated_at = now()
+                where id = $1
+                returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+        let updated = tx.query_one(&statement, &[&item_id, &status])
+            .await
+            .map_err(AppError::db_error)?;
+
+        record_audit(&tx, ctx, "status", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+        TodoItem::from_row_ref(&updated).unwrap()
+    };
+
+    check_wip_limit(&tx, list_id, status).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(item)
+}
+
+// PUT /todos/{list_id}/items/{item_id}/priority. If-Match 필요
+pub async fn set_item_priority(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, priority: i16, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    if !(0..=4).contains(&priority) {
+        return Err(AppError::validation("priority must be 0 (none) to 4 (urgent)"));
+    }
+
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let item = get_item_for_update(&tx, list_id, item_id).await?;
+    check_version(item.version, expected_version)?;
+
+    let statement = prepare(&tx, "update todo_item i
+            set priority = $2, version = i.version + 1, updated_at = now()
+            from todo_item old
+            where i.id = $1 and old.id = i.id
+            returning i.*, to_jsonb(old) as before, to_jsonb(i) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&item_id, &priority])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "priority", "todo_item", Some(item_id), updated.get("before"), updated.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+// PUT /todos/{list_id}/items/{item_id}/notes
+// 새로 @이름 으로 부른 사용자에게만 알림을 보낸다 (원래 notes에 있던 이름은 다시 알리지 않음)
+pub async fn set_item_notes(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, notes: Option<&str>, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let item = get_item_for_update(&tx, list_id, item_id).await?;
+    check_version(item.version, expected_version)?;
+
+    let statement = prepare(&tx, "update todo_item i
+            set notes = $2, version = i.version + 1, updated_at = now()
+            from todo_item old
+            where i.id = $1 and old.id = i.id
+            returning i.*, to_jsonb(old) as before, to_jsonb(i) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&item_id, &notes])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "notes", "todo_item", Some(item_id), updated.get("before"), updated.get("snapshot")).await?;
+
+    let mentioned = new_mentions(item.notes.as_deref(), notes);
+    notify(&tx, ctx, "mention", &mentioned, list_id, Some(item_id), None).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+async fn get_item_for_update<C: CachedClient>(client: &C, list_id: i32, item_id: i32) -> Result<TodoItem, AppError> {
+    let statement = prepare(client, "select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| TodoItem::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// GET /todos/{list_id}/board. 상태 흐름의 순서대로, 열 안에서는 item 순서대로
+pub async fn get_board(client: &Client, list_id: i32) -> Result<Board, AppError> {
+    get_todo(client, list_id).await?;
+
+    let workflow = get_workflow(client, list_id).await?;
+    let mut items = get_itmes(client, list_id).await?;
+
+    let columns = workflow.into_iter()
+        .map(|status| {
+            let (column, rest): (Vec<TodoItem>, Vec<TodoItem>) = std::mem::take(&mut items).into_iter().partition(|item| item.status == status.key);
+            items = rest;
+            BoardColumn { status, count: column.len(), items: column }
+        })
+        .collect();
+
+    Ok(Board { list_id, columns })
+}
+
+// item(새로 만드는 경우 None)을 parent_id 아래에 둘 수 있는지 확인한다
+// - 상위 item은 같은 list에 있고 휴지통에 있지 않아야 한다 (400)
+// - item을 자신의 하위 item 아래로 옮기면 순환이 생긴다 (409)
+// - 상위 item의 깊이 + item 아래 단계 수가 ITEMS.MAX_DEPTH를 넘으면 안 된다 (400)
+async fn check_parent<C: CachedClient>(client: &C, list_id: i32, parent_id: i32, item_id: Option<i32>) -> Result<(), AppError> {
+    // 같은 list의 구조를 동시에 바꾸면 둘 다 확인을 통과하고 순환이 생길 수 있으므로, list 단위로 차례대로 처리한다
+    lock_list_items(client, list_id).await?;
+
+    // 상위 item부터 최상위까지 올라가면서 깊이를 센다
+    let statement = prepare(client, "with recursive ancestors as (
+                select id, parent_id, 1 as depth from todo_item
+                where id = $1 and list_id = $2 and deleted_at is null
+                union all
+                select i.id, i.parent_id, a.depth + 1 from todo_item i
+                join ancestors a on i.id = a.parent_id
+                where a.depth < 100
+            )
+            select count(*)::integer as depth, coalesce(bool_or(id = $3), false) as cycle from ancestors").await?;
+
+    let row = client.query_one(&statement, &[&parent_id, &list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let depth: i32 = row.get("depth");
+    if depth == 0 {
+        return Err(AppError::validation(format!("Parent item {} was not found in this list", parent_id)));
+    }
+    if row.get("cycle") {
+        return Err(AppError::conflict("An item cannot be moved under itself or one of its subtasks"));
+    }
+
+    // 옮기는 item 아래에 몇 단계가 있는지 (item 자신이 1)
+    let height = match item_id {
+        Some(item_id) => {
+            let statement = prepare(client, "with recursive subtree as (
+                        select id, 1 as height from todo_item where id = $1
+                        union
+                        select i.id, s.height + 1 from todo_item i
+                        join subtree s on i.parent_id = s.id
+                        where i.deleted_at is null and s.height < 100
+                    )
+                    select max(height) as height from subtree").await?;
+
+            client.query_one(&statement, &[&item_id])
+                .await
+                .map_err(AppError::db_error)?
+                .get::<_, i32>("height")
+        },
+        None => 1
+    };
+
+    let max_depth = MAX_ITEM_DEPTH.load(Ordering::Relaxed);
+    if depth + height > max_depth {
+        return Err(AppError::validation(format!("Items can be nested at most {} levels deep", max_depth)));
+    }
+
+    Ok(())
+}
+
+// PUT /todos/{list_id}/items/{item_id}/recurrence
+// 규칙은 정리된 형태(ex. FREQ=WEEKLY;BYDAY=MO,WE)로 저장한다. 모르는 시간대 이름이면 400
+pub async fn set_item_recurrence(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, recurrence: &ItemRecurrence, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let rule = match recurrence.rule.as_deref() {
+        Some(rule) => Some(Recurrence::parse(rule)?.to_rule()),
+        None => None
+    };
+    let tz = rule.as_ref().map(|_| recurrence.tz.clone().unwrap_or_else(|| "UTC".to_string()));
+
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    if let Some(tz) = &tz {
+        let statement = prepare(&tx, "select 1 from pg_timezone_names where name = $1").await?;
+        tx.query_opt(&statement, &[tz])
+            .await
+            .map_err(AppError::db_error)?
+            .ok_or_else(|| AppError::validation(format!("Unknown time zone {}", tz)))?;
+    }
+
+    let statement = prepare(&tx, "select i.version, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = tx.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    // 처음 반복 규칙을 정한 item이 묶음(series)의 시작
+    let statement = prepare(&tx, "update todo_item
+            set recurrence = $2, recurrence_tz = $3,
+                series_id = case when $2::varchar is null then series_id else coalesce(series_id, id) end,
+                version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = tx.query_one(&statement, &[&item_id, &rule, &tz])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "recurrence", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+// GET /todos/{list_id}/items/{item_id}/history. 같은 묶음(series)의 완료 기록, 최근 것부터
+// 이미 체크되어 휴지통으로 간 item으로도 조회할 수 있다
+pub async fn get_item_history(client: &Client, list_id: i32, item_id: i32) -> Result<Vec<ItemCompletion>, AppError> {
+    let statement = prepare(client, "select coalesce(series_id, id) as series_id from todo_item
+            where list_id = $1 and id = $2").await?;
+
+    let series_id: i32 = client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?
+        .get("series_id");
+
+    let statement = prepare(client, "select * from item_completion
+            where series_id = $1
+            order by completed_at desc, id desc").await?;
+
+    let completions = client.query(&statement, &[&series_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| ItemCompletion::from_row_ref(row).unwrap())
+        .collect::<Vec<ItemCompletion>>();
+
+    Ok(completions)
+}
+
+fn check_version(version: i32, expected_version: Option<i32>) -> Result<(), AppError> {
+    match expected_version {
+        Some(expected) if expected != version => Err(AppError::precondition_failed(
+            format!("Item version is {}, but If-Match was {}", version, expected)
+        )),
+        _ => Ok(())
+    }
+}
+
+async fn insert_item<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, title: &str, checked: bool, parent_id: Option<i32>, schedule: &ItemSchedule) -> Result<TodoItem, AppError> {
+    if let Some(parent_id) = parent_id {
+        check_parent(client, list_id, parent_id, None).await?;
+    }
+
+    let statement = prepare(client, "insert into todo_item (title, list_id, checked, parent_id, due_at, remind_at) values ($1, $2, $3, $4, $5, $6)
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let row = client.query_one(&statement, &[&title, &list_id, &checked, &parent_id, &schedule.due_at, &schedule.remind_at])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let item = TodoItem::from_row_ref(&row).unwrap();
+    record_audit(client, ctx, "create", "todo_item", Some(item.id), None, row.get("snapshot")).await?;
+
+    Ok(item)
+}
+
+async fn rename_item<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32, title: &str, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let statement = prepare(client, "select i.version, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    let statement = prepare(client, "update todo_item
+            set title = $2, version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = client.query_one(&statement, &[&item_id, &title])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(client, ctx, "update", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+// PUT /todos/{list_id}/items/{item_id}/schedule
+pub async fn set_item_schedule(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, schedule: &ItemSchedule, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let item = schedule_item(&tx, ctx, list_id, item_id, schedule, expected_version).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(item)
+}
+
+// 알림 시간이 바뀌면 reminded_at을 지워서 새 시간에 다시 알림이 가도록 한다
+async fn schedule_item<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32, schedule: &ItemSchedule, expected_version: Option<i32>) -> Result<TodoItem, AppError> {
+    let statement = prepare(client, "select i.version, to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    let current = client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    check_version(current.get("version"), expected_version)?;
+
+    let statement = prepare(client, "update todo_item
+            set due_at = $2, remind_at = $3,
+                reminded_at = case when remind_at is distinct from $3 then null else reminded_at end,
+                version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let updated = client.query_one(&statement, &[&item_id, &schedule.due_at, &schedule.remind_at])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(client, ctx, "schedule", "todo_item", Some(item_id), current.get("snapshot"), updated.get("snapshot")).await?;
+
+    Ok(TodoItem::from_row_ref(&updated).unwrap())
+}
+
+// list의 체크되지 않은 item을 하나씩 set_item_checked로 체크한다
+// 하나씩 체크할 때와 같이 blocker, WIP 제한, 상위 item 반영, 반복 item의 다음 item 만들기가 모두 적용된다
+// 같은 list의 blocker가 먼저 체크되도록 의존 관계 순서대로 체크하고, 다른 list의 체크되지 않은 blocker가 있으면 409
+// 반환값은 체크된 item 수
+async fn check_all_items<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32) -> Result<u64, AppError> {
+    // update_item_checked와 같은 순서로 잠근다 (WIP 제한 -> item)
+    lock_wip_limits(client, list_id).await?;
+
+    // 체크할 item을 먼저 잠궈서, 도중에 다른 요청이 체크하거나 풀 수 없게 한다
+    let statement = prepare(client, "select id from todo_item
+            where list_id = $1 and deleted_at is null and not checked
+            order by id
+            for update").await?;
+
+    let ids = client.query(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| row.get("id"))
+        .collect::<Vec<i32>>();
+
+    let statement = prepare(client, "select item_id, blocked_by from item_dependency
+            where item_id = any($1) and blocked_by = any($1)").await?;
+
+    let mut blockers: HashMap<i32, Vec<i32>> = HashMap::new();
+    for row in client.query(&statement, &[&ids]).await.map_err(AppError::db_error)? {
+        blockers.entry(row.get("item_id")).or_default().push(row.get("blocked_by"));
+    }
+
+    // blocker가 모두 앞에 오도록 정렬한다. 순환은 만들 때 막으므로 남는 것이 있으면 그대로 뒤에 붙인다
+    let mut ordered: Vec<i32> = Vec::with_capacity(ids.len());
+    let mut pending = ids;
+    while !pending.is_empty() {
+        let (ready, rest): (Vec<i32>, Vec<i32>) = pending.into_iter().partition(|id| {
+            blockers.get(id).is_none_or(|blocked_by| blocked_by.iter().all(|blocker| ordered.contains(blocker)))
+        });
+        if ready.is_empty() {
+            ordered.extend(rest);
+            break;
+        }
+        ordered.extend(ready);
+        pending = rest;
+    }
+
+    // 상위 item 반영으로 이미 체크된 item은 None이지만, 이 요청에서 체크된 것이므로 센다
+    for id in &ordered {
+        set_item_checked(client, ctx, list_id, *id, true, None).await?;
+    }
+
+    Ok(ordered.len() as u64)
+}
+
+async fn clear_completed_items<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32) -> Result<u64, AppError> {
+    let statement = prepare(client, "with changed as (
+                update todo_item i
+                set deleted_at = now(), version = i.version + 1, updated_at = now()
+                from todo_item old
+                where old.id = i.id and i.list_id = $1 and i.deleted_at is null and i.checked
+                returning i.id, to_jsonb(old) - 'search_vector' as before, to_jsonb(i) - 'search_vector' as after
+            )
+            insert into audit_log (actor, action, entity, entity_id, before, after, request_id)
+            select $2, 'delete', 'todo_item', id, before, after, $3 from changed").await?;
+
+    client.execute(&statement, &[&list_id, &ctx.actor, &ctx.request_id])
+        .await
+        .map_err(AppError::db_error)
+}
+
+// POST /todos/{list_id}/items:batch
+// 모든 연산이 하나의 커넥션, 하나의 트랜잭션에서 실행되므로 요청마다 get_client/prepare를 반복하지 않는다.
+// partial 모드에서는 연산마다 savepoint를 만들어서 실패한 연산만 되돌린다
+pub async fn apply_batch(client: &mut Client, ctx: &RequestContext, list_id: i32, batch: &BatchRequest) -> Result<BatchResponse, AppError> {
+    let mut tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // batch 도중에 list가 삭제되지 않도록 잠가둔다
+    let statement = prepare(&tx, "select id from todo_list where id = $1 and deleted_at is null for share").await?;
+    tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let mut results = Vec::with_capacity(batch.operations.len());
+
+    for (index, operation) in batch.operations.iter().enumerate() {
+        let result = match batch.mode {
+            BatchMode::Atomic => apply_operation(&tx, ctx, list_id, index, operation).await,
+            BatchMode::Partial => {
+                let savepoint = tx.transaction().await.map_err(AppError::db_error)?;
+                let result = apply_operation(&savepoint, ctx, list_id, index, operation).await;
+                match result {
+                    Ok(_) => savepoint.commit().await.map_err(AppError::db_error)?,
+                    Err(_) => savepoint.rollback().await.map_err(AppError::db_error)?
+                }
+                result
+            }
+        };
+
+        match result {
+            Ok(result) => results.push(result),
+            Err(err) if batch.mode == BatchMode::Atomic => {
+                // 앞에서 성공한 연산도 함께 취소되었음을 알려준다 (424 Failed Dependency)
+                for result in results.iter_mut() {
+                    *result = BatchResult {
+                        index: result.index,
+                        status: 424,
+                        success: false,
+                        item: None,
+                        count: None,
+                        error: Some(format!("Rolled back because operation {} failed", index)),
+                        cause: None
+                    };
+                }
+                results.push(failed_operation(index, err));
+
+                return Ok(BatchResponse { committed: false, results });
+            },
+            Err(err) => results.push(failed_operation(index, err))
+        }
+    }
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(BatchResponse { committed: true, results })
+}
+
+async fn apply_operation<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, index: usize, operation: &BatchOperation) -> Result<BatchResult, AppError> {
+    let result = BatchResult { index, status: 200, success: true, item: None, count: None, error: None, cause: None };
+
+    match operation {
+        BatchOperation::Create { title, parent_id, due_at, remind_at } => {
+            let schedule = ItemSchedule { due_at: *due_at, remind_at: *remind_at };
+            let item = insert_item(client, ctx, list_id, valid_title(title)?, false, *parent_id, &schedule).await?;
+            Ok(BatchResult { status: 201, item: Some(item), ..result })
+        },
+        BatchOperation::Update { item_id, title, version } => {
+            let expected_version = body_version(version.as_ref())?;
+            let item = rename_item(client, ctx, list_id, *item_id, valid_title(title)?, expected_version).await?;
+            Ok(BatchResult { item: Some(item), ..result })
+        },
+        BatchOperation::Check { item_id, version } | BatchOperation::Uncheck { item_id, version } => {
+            let checked = matches!(operation, BatchOperation::Check { .. });
+            let expected_version = body_version(version.as_ref())?;
+            let item = set_item_checked(client, ctx, list_id, *item_id, checked, expected_version).await?;
+            Ok(BatchResult { success: item.is_some(), item, ..result })
+        },
+        BatchOperation::Delete { item_id } => {
+            if !soft_delete_item(client, ctx, list_id, *item_id).await? {
+                return Err(AppError::not_found());
+            }
+            Ok(result)
+        },
+        BatchOperation::Schedule { item_id, due_at, remind_at, version } => {
+            let expected_version = body_version(version.as_ref())?;
+            let schedule = ItemSchedule { due_at: *due_at, remind_at: *remind_at };
+            let item = schedule_item(client, ctx, list_id, *item_id, &schedule, expected_version).await?;
+            Ok(BatchResult { item: Some(item), ..result })
+        },
+        BatchOperation::CheckAll => {
+            let count = check_all_items(client, ctx, list_id).await?;
+            Ok(BatchResult { success: count > 0, count: Some(count), ..result })
+        },
+        BatchOperation::ClearCompleted => {
+            let count = clear_completed_items(client, ctx, list_id).await?;
+            Ok(BatchResult { success: count > 0, count: Some(count), ..result })
+        }
+    }
+}
+
+// title은 varchar(150). 길이를 넘기면 DB 에러(500) 대신 400
+pub fn valid_title(title: &str) -> Result<&str, AppError> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(AppError::validation("title must not be empty"));
+    }
+    if title.chars().count() > 150 {
+        return Err(AppError::validation("title must be at most 150 characters"));
+    }
+    Ok(title)
+}
+
+fn failed_operation(index: usize, err: AppError) -> BatchResult {
+    BatchResult {
+        index,
+        status: err.status_code().as_u16(),
+        success: false,
+        item: None,
+        count: None,
+        error: Some(err.message()),
+        cause: err.cause
+    }
+}
+
+pub async fn get_item<C: CachedClient>(client: &C, list_id: i32, item_id: i32) -> Result<TodoItem, AppError> {
+    let statement = prepare(client, "select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null").await?;
+
+    client.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| TodoItem::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// 삭제는 실제로 row를 지우지 않고 deleted_at만 기록한다 (soft delete)
+// 실제 삭제는 보관 기간이 지난 뒤 purge_trash에서 일어남
+pub async fn delete_todo(client: &mut Client, ctx: &RequestContext, list_id: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select to_jsonb(l) as snapshot from todo_list l
+            where id = $1 and deleted_at is null
+            for update").await?;
+
+    let before = match tx.query_opt(&statement, &[&list_id]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get::<_, Value>("snapshot"),
+        None => return Ok(false)
+    };
+
+    let statement = prepare(&tx, "update todo_list
+            set deleted_at = now(), version = version + 1, updated_at = now()
+            where id = $1
+            returning to_jsonb(todo_list.*) as snapshot").await?;
+
+    let after = tx.query_one(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "delete", "todo_list", Some(list_id), Some(before), after.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+pub async fn delete_item(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let deleted = soft_delete_item(&tx, ctx, list_id, item_id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(deleted)
+}
+
+// delete_item과 batch가 함께 사용한다. item이 없으면 false
+async fn soft_delete_item<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32) -> Result<bool, AppError> {
+    let statement = prepare(client, "select to_jsonb(i) as snapshot from todo_item i
+            where list_id = $1 and id = $2 and deleted_at is null
+            for update").await?;
+
+    let before = match client.query_opt(&statement, &[&list_id, &item_id]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get::<_, Value>("snapshot"),
+        None => return Ok(false)
+    };
+
+    let statement = prepare(client, "update todo_item
+            set deleted_at = now(), version = version + 1, updated_at = now()
+            where id = $1
+            returning to_jsonb(todo_item.*) as snapshot").await?;
+
+    let after = client.query_one(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(client, ctx, "delete", "todo_item", Some(item_id), Some(before), after.get("snapshot")).await?;
+
+    // 하위 item들도 모두 휴지통으로
+    let statement = prepare(client, "with recursive subtree as (
+                select id from todo_item where parent_id = $1 and deleted_at is null
+                union
+                select i.id from todo_item i
+                join subtree s on i.parent_id = s.id
+                where i.deleted_at is null
+            ),
+            changed as (
+                update todo_item i
+                set deleted_at = now(), version = i.version + 1, updated_at = now()
+                from todo_item old, subtree
+                where subtree.id = i.id and old.id = i.id
+                returning i.id, to_jsonb(old) - 'search_vector' as before, to_jsonb(i) - 'search_vector' as after
+            )
+            insert into audit_log (actor, action, entity, entity_id, before, after, request_id)
+            select $2, 'delete', 'todo_item', id, before, after, $3 from changed").await?;
+
+    client.execute(&statement, &[&item_id, &ctx.actor, &ctx.request_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+pub async fn get_todo<C: CachedClient>(client: &C, list_id: i32) -> Result<TodoList, AppError> {
+    let statement = prepare(client, "select l.id, l.title,
+                count(i.id) as item_count,
+                count(i.id) filter (where i.checked) as checked_count,
+                l.created_at, l.updated_at, l.version, l.position, l.is_template, l.deleted_at
+            from todo_list l
+            left join todo_item i on i.list_id = l.id and i.deleted_at is null
+            where l.id = $1 and l.deleted_at is null
+            group by l.id").await?;
+
+    client.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| TodoList::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// 휴지통: 삭제된 list와, list는 살아있는데 따로 삭제된 item
+// 삭제된 list 안의 item은 list를 복구하면 함께 돌아오므로 따로 보여주지 않는다
+pub async fn get_trash(client: &Client, reader: Option<&str>) -> Result<Trash, AppError> {
+    let lists_statement = prepare(client, "select l.id, l.title,
+                count(i.id) as item_count,
+                count(i.id) filter (where i.checked) as checked_count,
+                l.created_at, l.updated_at, l.version, l.position, l.is_template, l.deleted_at
+            from todo_list l
+            left join todo_item i on i.list_id = l.id and i.deleted_at is null
+            where l.deleted_at is not null and list_readable(l.id, $1)
+            group by l.id
+            order by l.deleted_at desc").await?;
+
+    let items_statement = prepare(client, "select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.deleted_at is not null and list_readable(l.id, $1)
+            order by i.deleted_at desc").await?;
+
+    let lists = client.query(&lists_statement, &[&reader])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| TodoList::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoList>>();
+
+    let items = client.query(&items_statement, &[&reader])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| TodoItem::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoItem>>();
+
+    Ok(Trash { lists, items })
+}
+
+pub async fn restore_todo(client: &mut Client, ctx: &RequestContext, list_id: i32) -> Result<TodoList, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select to_jsonb(l) as snapshot from todo_list l
+            where id = $1 and deleted_at is not null
+            for update").await?;
+
+    let before = tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| row.get::<_, Value>("snapshot"))
+        .ok_or_else(AppError::not_found)?;
+
+    // list를 휴지통에서 꺼내는 것은 지우는 것과 같은 권한 (delete_todo)
+    check_list_access(&tx, ctx, list_id, ListAccess::Manage).await?;
+
+    let statement = prepare(&tx, "update todo_list
+            set deleted_at = null, version = version + 1, updated_at = now()
+            where id = $1
+            returning to_jsonb(todo_list.*) as snapshot").await?;
+
+    let after = tx.query_one(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "restore", "todo_list", Some(list_id), Some(before), after.get("snapshot")).await?;
+    let todo = get_todo(&tx, list_id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(todo)
+}
+
+// list가 휴지통에 있으면 item만 복구해도 보이지 않으므로 list를 먼저 복구하도록 한다
+pub async fn restore_item(client: &mut Client, ctx: &RequestContext, item_id: i32) -> Result<TodoItem, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select to_jsonb(i) as snapshot from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.id = $1 and i.deleted_at is not null
+            for update of i").await?;
+
+    let before = tx.query_opt(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| row.get::<_, Value>("snapshot"))
+        .ok_or(AppError {
+            message: Some("The item is not in the trash, or its list must be restored first".to_string()),
+            cause: None,
+            error_type: AppErrorType::NotFoundError
+        })?;
+
+    // 휴지통의 item은 list_id로 찾지 않으므로 여기서 list 권한을 확인한다
+    let list_id = before["list_id"].as_i64().unwrap_or_default() as i32;
+    check_list_access(&tx, ctx, list_id, ListAccess::Write).await?;
+
+    let statement = prepare(&tx, "update todo_item
+            set deleted_at = null, version = version + 1, updated_at = now()
+            where id = $1
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let row = tx.query_one(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "restore", "todo_item", Some(item_id), Some(before), row.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(TodoItem::from_row_ref(&row).unwrap())
+}
+
+// deleted_at이 before 보다 오래된 것들을 실제로 삭제한다.
+// todo_item이 todo_list를 참조하고 있으므로 item을 먼저 지워야 함
+// 지워진 row는 하나씩 감사 로그의 before에 남긴다 (delete ... returning 결과를 그대로 insert)
+// 반환값은 (삭제된 list 수, 삭제된 item 수)
+pub async fn purge_trash(client: &mut Client, ctx: &RequestContext, before: DateTime<Utc>) -> Result<(u64, u64), AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let items_statement = prepare(&tx, "with purged as (
+                delete from todo_item i
+                using todo_list l
+                where l.id = i.list_id
+                    and (i.deleted_at < $1 or l.deleted_at < $1)
+                returning i.id, to_jsonb(i) - 'search_vector' as snapshot
+            )
+            insert into audit_log (actor, action, entity, entity_id, before, request_id)
+            select $2, 'purge', 'todo_item', id, snapshot, $3 from purged").await?;
+
+    let lists_statement = prepare(&tx, "with purged as (
+                delete from todo_list l
+                where deleted_at < $1
+                returning l.id, to_jsonb(l) - 'search_vector' as snapshot
+            )
+            insert into audit_log (actor, action, entity, entity_id, before, request_id)
+            select $2, 'purge', 'todo_list', id, snapshot, $3 from purged").await?;
+
+    let items = tx.execute(&items_statement, &[&before, &ctx.actor, &ctx.request_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let lists = tx.execute(&lists_statement, &[&before, &ctx.actor, &ctx.request_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok((lists, items))
+}
+
+// 알림 시간이 지난 item들에 알림을 보낸 것으로 표시하고 감사 로그(remind)를 남긴다.
+// 감사 로그의 트리거가 SSE(reminded)와 웹훅(item.reminded)으로 알림을 내보낸다
+// 서버가 여러 대이면 skip locked로 서로 다른 item을 가져가므로 알림이 두 번 가지 않는다
+pub async fn send_reminders(client: &mut Client, ctx: &RequestContext, limit: i64) -> Result<Vec<TodoItem>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "with due as (
+                select i.id from todo_item i
+                join todo_list l on l.id = i.list_id and l.deleted_at is null
+                where i.reminded_at is null and i.remind_at <= now() and i.deleted_at is null and not i.checked
+                order by i.remind_at
+                limit $1
+                for update of i skip locked
+            ),
+            changed as (
+                update todo_item i
+                set reminded_at = now()
+                from todo_item old, due
+                where due.id = i.id and old.id = i.id
+                returning i.*, to_jsonb(old) - 'search_vector' as before, to_jsonb(i) - 'search_vector' as after
+            ),
+            logged as (
+                insert into audit_log (actor, action, entity, entity_id, before, after, request_id)
+                select $2, 'remind', 'todo_item', id, before, after, $3 from changed
+            )
+            select * from changed order by remind_at, id").await?;
+
+    let items = tx.query(&statement, &[&limit, &ctx.actor, &ctx.request_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| TodoItem::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoItem>>();
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(items)
+}
+
+// GET /audit. 조건이 없는(null) 항목은 무시된다
+pub async fn get_audit_log(client: &Client, query: &AuditQuery) -> Result<Vec<AuditEntry>, AppError> {
+    let statement = prepare(client, "select * from audit_log
+            where ($1::text is null or actor = $1)
+                and ($2::text is null or action = $2)
+                and ($3::text is null or entity = $3)
+                and ($4::integer is null or entity_id = $4)
+                and ($5::text is null or request_id = $5)
+                and ($6::timestamptz is null or created_at >= $6)
+                and ($7::timestamptz is null or created_at < $7)
+                and ($8::bigint is null or id < $8)
+            order by id desc
+            limit $9").await?;
+
+    let entries = client.query(&statement, &[
+            &query.actor, &query.action, &query.entity, &query.entity_id, &query.request_id,
+            &query.since, &query.until, &query.before_id, &query.limit()
+        ])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| AuditEntry::from_row_ref(row).unwrap())
+        .collect::<Vec<AuditEntry>>();
+
+    Ok(entries)
+}
+
+// list와 item을 함께 검색. 휴지통에 있는 것(또는 휴지통에 있는 list의 item)은 제외한다
+// $1: 검색어, $2: type, $3: checked, $4: list_id, $5: reader (볼 수 없는 list와 그 item은 빠진다)
+const SEARCH_MATCHES: &str = "with query as (select todo_search_query($1) as q),
+    matches as (
+        select 'list' as kind, l.id, l.id as list_id, l.title, null::boolean as checked, l.updated_at,
+            ts_rank(l.search_vector, query.q) as rank
+        from todo_list l, query
+        where l.deleted_at is null and l.search_vector @@ query.q
+            and ($2::text is null or $2 = 'list') and $3::boolean is null and ($4::integer is null or l.id = $4)
+            and list_readable(l.id, $5)
+        union all
+        select 'item', i.id, i.list_id, i.title, i.checked, i.updated_at,
+            ts_rank(i.search_vector, query.q)
+        from todo_item i
+        join todo_list l on l.id = i.list_id and l.deleted_at is null, query
+        where i.deleted_at is null and i.search_vector @@ query.q
+            and ($2::text is null or $2 = 'item') and ($3::boolean is null or i.checked = $3) and ($4::integer is null or i.list_id = $4)
+            and list_readable(l.id, $5)
+    )";
+
+pub async fn search(client: &Client, query: &SearchQuery, reader: Option<&str>) -> Result<SearchResponse, AppError> {
+    let terms = query.terms()?;
+    let kind = query.kind.map(|kind| kind.as_str());
+
+    // 점수가 같으면 최근에 바뀐 것부터. snippet은 아래에서 title에 표시를 붙여서 만든다
+    let statement = prepare(client, &format!("{}
+        select *, title as snippet from matches
+        order by rank desc, updated_at desc, kind, id
+        limit $6 offset $7", SEARCH_MATCHES)).await?;
+
+    let results = client.query(&statement, &[&query.q, &kind, &query.checked, &query.list_id, &reader, &query.limit(), &query.offset()])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| SearchResult::from_row_ref(row).unwrap())
+        .map(|result| SearchResult { snippet: highlight(&result.title, &terms), ..result })
+        .collect::<Vec<SearchResult>>();
+
+    let statement = prepare(client, &format!("{} select count(*) as total from matches", SEARCH_MATCHES)).await?;
+    let total: i64 = client.query_one(&statement, &[&query.q, &kind, &query.checked, &query.list_id, &reader])
+        .await
+        .map_err(AppError::db_error)?
+        .get("total");
+
+    Ok(SearchResponse { total, limit: query.limit(), offset: query.offset(), results })
+}
+
+// 검색어로 시작하는 단어를 <mark>로 감싼다. ts_headline은 DB의 parser를 쓰므로 한글을 표시하지 못할 수 있어서 직접 만든다
+fn highlight(title: &str, terms: &[String]) -> String {
+    let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut snippet = String::with_capacity(title.len() + 16);
+    let mut word = String::new();
+
+    let flush = |word: &mut String, snippet: &mut String| {
+        let lower = word.to_lowercase();
+        if !word.is_empty() && terms.iter().any(|term| lower.starts_with(term.as_str())) {
+            snippet.push_str(&format!("<mark>{}</mark>", escape(word)));
+        } else {
+            snippet.push_str(&escape(word));
+        }
+        word.clear();
+    };
+
+    for c in title.chars() {
+        if c.is_whitespace() || c.is_ascii_punctuation() {
+            flush(&mut word, &mut snippet);
+            snippet.push_str(&escape(&c.to_string()));
+        } else {
+            word.push(c);
+        }
+    }
+    flush(&mut word, &mut snippet);
+
+    snippet
+}
+
+// 접속 중인 사용자 정보는 테이블에 저장하지 않고 NOTIFY로 다른 서버들에 알리기만 한다
+pub async fn notify_presence(client: &Client, signal: &PresenceSignal) -> Result<(), AppError> {
+    let statement = prepare(client, "select pg_notify($1, $2)").await?;
+    let payload = serde_json::to_string(signal).map_err(AppError::db_error)?;
+
+    client.execute(&statement, &[&presence::CHANNEL, &payload])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+// 아래 owner 인자는 구독을 만든 사용자. None이면(관리자) 모든 구독에 접근 가능
+pub async fn create_webhook(client: &mut Client, ctx: &RequestContext, webhook: &CreateWebhook, secret: &str) -> Result<Webhook, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // secret은 감사 로그에도 남기지 않는다
+    let statement = prepare(&tx, "insert into webhook_subscription (owner, list_id, url, secret, events)
+            values ($1, $2, $3, $4, $5)
+            returning *, to_jsonb(webhook_subscription.*) - 'secret' as snapshot").await?;
+
+    let row = tx.query_one(&statement, &[&ctx.actor, &webhook.list_id, &webhook.url, &secret, &webhook.events])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let created = Webhook::from_row_ref(&row).unwrap();
+
+    record_audit(&tx, ctx, "create", "webhook_subscription", Some(created.id), None, row.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(created)
+}
+
+pub async fn get_webhooks(client: &Client, owner: Option<&str>) -> Result<Vec<Webhook>, AppError> {
+    let statement = prepare(client, "select * from webhook_subscription
+            where ($1::text is null or owner = $1)
+            order by id").await?;
+
+    let webhooks = client.query(&statement, &[&owner])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Webhook::from_row_ref(row).unwrap())
+        .collect::<Vec<Webhook>>();
+
+    Ok(webhooks)
+}
+
+// 구독을 지우면 전송 기록도 함께 지워진다 (on delete cascade)
+pub async fn delete_webhook(client: &mut Client, ctx: &RequestContext, webhook_id: i32, owner: Option<&str>) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "delete from webhook_subscription
+            where id = $1 and ($2::text is null or owner = $2)
+            returning to_jsonb(webhook_subscription.*) - 'secret' as snapshot").await?;
+
+    let before = match tx.query_opt(&statement, &[&webhook_id, &owner]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get::<_, Value>("snapshot"),
+        None => return Ok(false)
+    };
+
+    record_audit(&tx, ctx, "delete", "webhook_subscription", Some(webhook_id), Some(before), None).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+pub async fn get_webhook_deliveries(client: &Client, webhook_id: i32, owner: Option<&str>, query: &DeliveriesQuery) -> Result<Vec<WebhookDelivery>, AppError> {
+    // 다른 사람의 구독이면 빈 목록이 아니라 404
+    let statement = prepare(client, "select 1 from webhook_subscription where id = $1 and ($2::text is null or owner = $2)").await?;
+    client.query_opt(&statement, &[&webhook_id, &owner])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let statement = prepare(client, "select d.* from webhook_delivery d
+            join webhook_subscription s on s.id = d.subscription_id
+            where d.subscription_id = $1 and ($2::text is null or s.owner = $2)
+                and ($3::text is null or d.status = $3)
+                and ($4::bigint is null or d.id < $4)
+            order by d.id desc
+            limit $5").await?;
+
+    let deliveries = client.query(&statement, &[&webhook_id, &owner, &query.status, &query.before_id, &query.limit()])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| WebhookDelivery::from_row_ref(row).unwrap())
+        .collect::<Vec<WebhookDelivery>>();
+
+    Ok(deliveries)
+}
+
+// dead 상태가 된 전송을 다시 보내도록 대기열에 넣는다
+pub async fn retry_webhook_delivery(client: &Client, webhook_id: i32, delivery_id: i64, owner: Option<&str>) -> Result<WebhookDelivery, AppError> {
+    let statement = prepare(client, "update webhook_delivery d
+            set status = 'pending', attempts = 0, next_attempt_at = now()
+            from webhook_subscription s
+            where d.id = $1 and d.subscription_id = $2 and s.id = d.subscription_id
+                and ($3::text is null or s.owner = $3)
+                and d.status <> 'delivered'
+            returning d.*").await?;
+
+    client.query_opt(&statement, &[&delivery_id, &webhook_id, &owner])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| WebhookDelivery::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// 보낼 차례가 된 전송을 가져온다.
+// skip locked로 다른 서버가 가져간 것은 건너뛰고, next_attempt_at을 lease만큼 미뤄서
+// 보내는 도중에 다른 서버가 다시 가져가지 않게 한다
+pub async fn claim_webhook_deliveries(client: &Client, limit: i64, lease_secs: f64) -> Result<Vec<PendingDelivery>, AppError> {
+    let statement = prepare(client, "update webhook_delivery d
+            set attempts = d.attempts + 1, next_attempt_at = now() + make_interval(secs => $2)
+            from webhook_subscription s
+            where s.id = d.subscription_id
+                and d.id in (
+                    select id from webhook_delivery
+                    where status = 'pending' and next_attempt_at <= now()
+                    order by next_attempt_at
+                    limit $1
+                    for update skip locked
+                )
+            returning d.id, d.event, d.payload, d.attempts, s.url, s.secret").await?;
+
+    let deliveries = client.query(&statement, &[&limit, &lease_secs])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| PendingDelivery::from_row_ref(row).unwrap())
+        .collect::<Vec<PendingDelivery>>();
+
+    Ok(deliveries)
+}
+
+pub async fn complete_webhook_delivery(client: &Client, delivery_id: i64, status_code: i32) -> Result<(), AppError> {
+    let statement = prepare(client, "update webhook_delivery
+            set status = 'delivered', delivered_at = now(), last_status_code = $2, last_error = null
+            where id = $1").await?;
+
+    client.execute(&statement, &[&delivery_id, &status_code])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+// 재시도 횟수를 넘기면 dead(dead letter)가 되어 더 이상 보내지 않는다
+pub async fn fail_webhook_delivery(client: &Client, delivery_id: i64, status_code: Option<i32>, error: &str, max_attempts: i32, retry_after_secs: f64) -> Result<(), AppError> {
+    let statement = prepare(client, "update webhook_delivery
+            set status = case when attempts >= $4 then 'dead' else 'pending' end,
+                next_attempt_at = now() + make_interval(secs => $5),
+                last_status_code = $2,
+                last_error = $3
+            where id = $1").await?;
+
+    client.execute(&statement, &[&delivery_id, &status_code, &error, &max_attempts, &retry_after_secs])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+// Idempotency-Key를 먼저 차지한다. 차지했으면 None, 이미 살아있는 키가 있으면 그 기록을 돌려준다.
+// 만료된 키나, 처리 중인 채로 5분이 지난 키(서버가 죽은 경우)는 새 요청이 다시 차지할 수 있다
+pub async fn claim_idempotency_key(client: &Client, actor: &str, key: &str, fingerprint: &str, ttl_secs: f64) -> Result<Option<IdempotencyRecord>, AppError> {
+    let statement = prepare(client, "insert into idempotency_key (actor, key, fingerprint, expires_at)
+            values ($1, $2, $3, now() + make_interval(secs => $4))
+            on conflict (actor, key) do update
+                set fingerprint = excluded.fingerprint, status_code = null, content_type = null, response = null,
+                    created_at = now(), expires_at = excluded.expires_at
+                where idempotency_key.expires_at < now()
+                    or (idempotency_key.status_code is null and idempotency_key.created_at < now() - interval '5 minutes')
+            returning key").await?;
+
+    let claimed = client.query_opt(&statement, &[&actor, &key, &fingerprint, &ttl_secs])
+        .await
+        .map_err(AppError::db_error)?;
+
+    if claimed.is_some() {
+        return Ok(None);
+    }
+
+    let statement = prepare(client, "select fingerprint, status_code, content_type, response from idempotency_key
+            where actor = $1 and key = $2").await?;
+
+    client.query_opt(&statement, &[&actor, &key])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| Some(IdempotencyRecord::from_row_ref(&row).unwrap()))
+        // insert와 select 사이에 만료되어 지워진 경우
+        .ok_or_else(|| AppError::conflict("Idempotency-Key expired while being checked, please retry"))
+}
+
+pub async fn complete_idempotency_key(client: &Client, actor: &str, key: &str, status_code: i32, content_type: Option<&str>, response: &[u8]) -> Result<(), AppError> {
+    let statement = prepare(client, "update idempotency_key
+            set status_code = $3, content_type = $4, response = $5
+            where actor = $1 and key = $2").await?;
+
+    client.execute(&statement, &[&actor, &key, &status_code, &content_type, &response])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+// 요청이 서버 오류로 끝났을 때. 같은 키로 다시 시도할 수 있도록 지운다
+pub async fn release_idempotency_key(client: &Client, actor: &str, key: &str) -> Result<(), AppError> {
+    let statement = prepare(client, "delete from idempotency_key where actor = $1 and key = $2 and status_code is null").await?;
+
+    client.execute(&statement, &[&actor, &key])
+        .await
+        .map(|_| ())
+        .map_err(AppError::db_error)
+}
+
+pub async fn purge_idempotency_keys(client: &Client) -> Result<u64, AppError> {
+    let statement = prepare(client, "delete from idempotency_key where expires_at < now()").await?;
+
+    client.execute(&statement, &[])
+        .await
+        .map_err(AppError::db_error)
+}
+
+// token은 감사 로그에 남기지 않는다 (피드 주소 자체가 권한이므로)
+pub async fn create_calendar_feed(client: &mut Client, ctx: &RequestContext, list_id: Option<i32>, token: &str) -> Result<CalendarFeed, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "insert into calendar_feed (token, owner, list_id) values ($1, $2, $3)
+            returning *, to_jsonb(calendar_feed.*) - 'token' as snapshot").await?;
+
+    let row = tx.query_one(&statement, &[&token, &ctx.actor, &list_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let feed = CalendarFeed::from_row_ref(&row).unwrap();
+
+    record_audit(&tx, ctx, "create", "calendar_feed", Some(feed.id), None, row.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(feed)
+}
+
+pub async fn get_calendar_feeds(client: &Client, owner: Option<&str>) -> Result<Vec<CalendarFeed>, AppError> {
+    let statement = prepare(client, "select * from calendar_feed where $1::text is null or owner = $1 order by id").await?;
+
+    let feeds = client.query(&statement, &[&owner])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| CalendarFeed::from_row_ref(row).unwrap())
+        .collect::<Vec<CalendarFeed>>();
+
+    Ok(feeds)
+}
+
+pub async fn get_calendar_feed(client: &Client, token: &str) -> Result<CalendarFeed, AppError> {
+    let statement = prepare(client, "select * from calendar_feed where token = $1").await?;
+
+    client.query_opt(&statement, &[&token])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| CalendarFeed::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// 피드를 지우면 그 주소로는 더 이상 구독할 수 없다
+pub async fn delete_calendar_feed(client: &mut Client, ctx: &RequestContext, feed_id: i32, owner: Option<&str>) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "delete from calendar_feed
+            where id = $1 and ($2::text is null or owner = $2)
+            returning to_jsonb(calendar_feed.*) - 'token' as snapshot").await?;
+
+    let before = match tx.query_opt(&statement, &[&feed_id, &owner]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get::<_, Value>("snapshot"),
+        None => return Ok(false)
+    };
+
+    record_audit(&tx, ctx, "delete", "calendar_feed", Some(feed_id), Some(before), None).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+// 피드에 들어갈 list. get_todos와 달리 개수 제한이 없다
+pub async fn get_feed_lists(client: &Client, list_id: Option<i32>, reader: Option<&str>) -> Result<Vec<TodoList>, AppError> {
+    let statement = prepare(client, "select l.id, l.title,
+                count(i.id) as item_count,
+                count(i.id) filter (where i.checked) as checked_count,
+                l.created_at, l.updated_at, l.version, l.position, l.is_template, l.deleted_at
+            from todo_list l
+            left join todo_item i on i.list_id = l.id and i.deleted_at is null
+            where l.deleted_at is null and ($1::integer is null or l.id = $1) and list_readable(l.id, $2)
+            group by l.id
+            order by l.position, l.id").await?;
+
+    let lists = client.query(&statement, &[&list_id, &reader])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| TodoList::from_row_ref(row).unwrap())
+        .collect::<Vec<TodoList>>();
+
+    Ok(lists)
+}
+
+// CalDAV의 sync-token. item 이벤트의 마지막 seq를 사용한다 (변경이 있을 때마다 커짐)
+// audit_log의 id는 커밋 순서와 다를 수 있어서, token보다 작은 id의 변경이 나중에 커밋되면 다음 동기화에서 빠진다
+// 아직 seq가 없는 이벤트는 나중에 붙을 때 token보다 큰 seq를 받으므로 다음 동기화에 나온다
+// seq는 jobs.rs의 event_listener가 붙인다. 읽기만 하는 PROPFIND, REPORT에서 outbox를 잠그지 않도록 여기서는 붙이지 않는다
+pub async fn get_sync_token(client: &Client) -> Result<i64, AppError> {
+    let statement = prepare(client, "select coalesce(max(seq), 0) as token from item_event").await?;
+
+    client.query_one(&statement, &[])
+        .await
+        .map(|row| row.get("token"))
+        .map_err(AppError::db_error)
+}
+
+// 리소스 이름은 CalDAV로 만들어진 item이면 dav_name, 아니면 {id}.ics
+pub async fn get_dav_item<C: CachedClient>(client: &C, list_id: i32, name: &str) -> Result<Option<TodoItem>, AppError> {
+    let statement = prepare(client, "select i.* from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.deleted_at is null
+                and (i.dav_name = $2 or (i.dav_name is null and i.id::text || '.ics' = $2))").await?;
+
+    client.query_opt(&statement, &[&list_id, &name])
+        .await
+        .map(|row| row.map(|row| TodoItem::from_row_ref(&row).unwrap()))
+        .map_err(AppError::db_error)
+}
+
+// sync-token 이후에 이 list에서 바뀐 item의 (id, 리소스 이름).
+// 다른 list로 옮겨졌거나 삭제된 item도 포함되므로, 지금 list에 없는 것은 호출하는 쪽에서 삭제로 처리한다
+pub async fn get_dav_changes(client: &Client, list_id: i32, since: i64) -> Result<Vec<(i32, String)>, AppError> {
+    let statement = prepare(client, "with changes as (
+            select e.item_id as id, e.seq, coalesce(coalesce(a.after, a.before)->>'dav_name', e.item_id || '.ics') as name
+            from item_event e
+            join audit_log a on a.id = e.audit_id
+            where e.seq > $2 and e.item_id is not null
+                and $1 in (e.list_id, (a.before->>'list_id')::integer)
+            union all
+            -- 간격을 다시 벌리면서 위치(와 version)가 바뀐 item들
+            select (r->>'id')::integer, e.seq, coalesce(r->>'dav_name', (r->>'id') || '.ics')
+            from item_event e
+            join audit_log a on a.id = e.audit_id
+            cross join jsonb_array_elements(a.after->'items') r
+            where e.seq > $2 and e.type = 'reordered' and e.list_id = $1
+        )
+        select distinct on (id) id, name from changes order by id, seq desc").await?;
+
+    let changes = client.query(&statement, &[&list_id, &since])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| (row.get("id"), row.get("name")))
+        .collect::<Vec<(i32, String)>>();
+
+    Ok(changes)
+}
+
+// CalDAV PUT. 리소스가 있으면 제목과 checked를 바꾸고, 없으면 새로 만든다
+// expected_version은 If-Match, must_not_exist는 If-None-Match: * 
+// 반환값은 (item, 새로 만들었는지)
+pub async fn put_dav_item(client: &mut Client, ctx: &RequestContext, list_id: i32, name: &str, todo: &CalendarTodo, expected_version: Option<i32>, must_not_exist: bool) -> Result<(TodoItem, bool), AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // list가 휴지통에 있거나 없으면 404
+    get_todo(&tx, list_id).await?;
+
+    let existing = get_dav_item(&tx, list_id, name).await?;
+    let due_at = dav_due_at(&tx, todo).await?;
+
+    let (item, created) = match existing {
+        Some(_) if must_not_exist => return Err(AppError::precondition_failed("The resource already exists")),
+        Some(current) => {
+            check_version(current.version, expected_version)?;
+
+            if current.title != todo.title {
+                rename_item(&tx, ctx, list_id, current.id, &todo.title, None).await?;
+            }
+            if current.checked != todo.checked {
+                set_item_checked(&tx, ctx, list_id, current.id, todo.checked, None).await?;
+            }
+            // 알림 시간은 VTODO로 주고받지 않으므로 그대로 둔다
+            if current.due_at != due_at {
+                schedule_item(&tx, ctx, list_id, current.id, &ItemSchedule { due_at, remind_at: current.remind_at }, None).await?;
+            }
+
+            (get_item(&tx, list_id, current.id).await?, false)
+        },
+        None if expected_version.is_some() => return Err(AppError::precondition_failed("The resource does not exist")),
+        None => (insert_dav_item(&tx, ctx, list_id, name, todo, due_at).await?, true)
+    };
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok((item, created))
+}
+
+// 클라이언트가 정한 UID와 이름을 기억해둬야 같은 주소, 같은 UID로 다시 돌려줄 수 있다
+// 감사 로그와 이벤트의 after에도 남도록 insert할 때 함께 넣는다
+async fn insert_dav_item<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, name: &str, todo: &CalendarTodo, due_at: Option<DateTime<Utc>>) -> Result<TodoItem, AppError> {
+    let statement = prepare(client, "insert into todo_item (title, list_id, checked, ical_uid, dav_name, due_at) values ($1, $2, $3, $4, $5, $6)
+            returning *, to_jsonb(todo_item.*) as snapshot").await?;
+
+    let row = client.query_one(&statement, &[&todo.title, &list_id, &todo.checked, &todo.uid, &name, &due_at])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let item = TodoItem::from_row_ref(&row).unwrap();
+    record_audit(client, ctx, "create", "todo_item", Some(item.id), None, row.get("snapshot")).await?;
+
+    Ok(item)
+}
+
+// VTODO의 DUE를 마감 시간으로. TZID가 있으면 시간대 변환은 postgres가 한다 (at time zone)
+// 모르는 시간대 이름이면 400
+async fn dav_due_at<C: CachedClient>(client: &C, todo: &CalendarTodo) -> Result<Option<DateTime<Utc>>, AppError> {
+    let (due_at, due_tz) = match (todo.due_at, todo.due_tz.as_deref()) {
+        (Some(due_at), Some(due_tz)) => (due_at, due_tz),
+        (due_at, _) => return Ok(due_at.map(|due_at| due_at.and_utc()))
+    };
+
+    let statement = prepare(client, "select $1::timestamp at time zone tz.name as due_at
+            from pg_timezone_names tz where tz.name = $2").await?;
+
+    let row = client.query_opt(&statement, &[&due_at, &due_tz])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(|| AppError::validation(format!("Unknown time zone {}", due_tz)))?;
+
+    Ok(Some(row.get("due_at")))
+}
+
+// CalDAV DELETE. 다른 삭제와 마찬가지로 휴지통으로 간다
+pub async fn delete_dav_item(client: &mut Client, ctx: &RequestContext, list_id: i32, name: &str, expected_version: Option<i32>) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let item = match get_dav_item(&tx, list_id, name).await? {
+        Some(item) => item,
+        None => return Ok(false)
+    };
+
+    check_version(item.version, expected_version)?;
+    let deleted = soft_delete_item(&tx, ctx, list_id, item.id).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(deleted)
+}
+
+pub async fn get_tags(client: &Client, owner: &str) -> Result<Vec<Tag>, AppError> {
+    let statement = prepare(client, "select * from tag where owner = $1 order by lower(name)").await?;
+
+    let tags = client.query(&statement, &[&owner])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Tag::from_row_ref(row).unwrap())
+        .collect::<Vec<Tag>>();
+
+    Ok(tags)
+}
+
+// 같은 사용자의 태그 이름은 대소문자를 무시하고 겹칠 수 없다 (409)
+fn tag_conflict(err: tokio_postgres::Error) -> AppError {
+    if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        AppError::conflict("A tag with this name already exists")
+    } else {
+        AppError::db_error(err)
+    }
+}
+
+pub async fn create_tag(client: &mut Client, ctx: &RequestContext, name: &str, color: Option<&str>) -> Result<Tag, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "insert into tag (owner, name, color) values ($1, $2, coalesce($3, '#808080'))
+            returning *, to_jsonb(tag.*) as snapshot").await?;
+
+    let row = tx.query_one(&statement, &[&ctx.actor, &name, &color])
+        .await
+        .map_err(tag_conflict)?;
+
+    let tag = Tag::from_row_ref(&row).unwrap();
+
+    record_audit(&tx, ctx, "create", "tag", Some(tag.id), None, row.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(tag)
+}
+
+// 다른 사용자의 태그면 404
+pub async fn update_tag(client: &mut Client, ctx: &RequestContext, tag_id: i32, name: Option<&str>, color: Option<&str>) -> Result<Tag, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "update tag t
+            set name = coalesce($3, t.name), color = coalesce($4, t.color)
+            from tag old
+            where t.id = $1 and t.owner = $2 and old.id = t.id
+            returning t.*, to_jsonb(old) as before, to_jsonb(t) as snapshot").await?;
+
+    let row = tx.query_opt(&statement, &[&tag_id, &ctx.actor, &name, &color])
+        .await
+        .map_err(tag_conflict)?
+        .ok_or_else(AppError::not_found)?;
+
+    record_audit(&tx, ctx, "update", "tag", Some(tag_id), row.get("before"), row.get("snapshot")).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(Tag::from_row_ref(&row).unwrap())
+}
+
+// 태그를 지우면 item, list에 붙은 것도 함께 지워진다 (on delete cascade)
+pub async fn delete_tag(client: &mut Client, ctx: &RequestContext, tag_id: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "delete from tag where id = $1 and owner = $2
+            returning to_jsonb(tag.*) as snapshot").await?;
+
+    let before = match tx.query_opt(&statement, &[&tag_id, &ctx.actor]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get::<_, Value>("snapshot"),
+        None => return Ok(false)
+    };
+
+    record_audit(&tx, ctx, "delete", "tag", Some(tag_id), Some(before), None).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+// 태그를 붙이는 대상. 연결 테이블만 다르다
+#[derive(Clone, Copy)]
+pub enum TagTarget {
+    Item,
+    List
+}
+
+impl TagTarget {
+    // (연결 테이블, 대상 id 컬럼). 감사 로그의 entity로도 연결 테이블 이름을 쓴다
+    fn link(self) -> (&'static str, &'static str) {
+        match self {
+            TagTarget::Item => ("item_tag", "item_id"),
+            TagTarget::List => ("list_tag", "list_id")
+        }
+    }
+}
+
+// item/list에 붙은 태그 중 owner의 것만
+pub async fn get_target_tags<C: CachedClient>(client: &C, target: TagTarget, id: i32, owner: &str) -> Result<Vec<Tag>, AppError> {
+    let (link, column) = target.link();
+    let statement = prepare(client, &format!("select t.* from {link} x
+            join tag t on t.id = x.tag_id
+            where x.{column} = $1 and t.owner = $2
+            order by lower(t.name)")).await?;
+
+    let tags = client.query(&statement, &[&id, &owner])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Tag::from_row_ref(row).unwrap())
+        .collect::<Vec<Tag>>();
+
+    Ok(tags)
+}
+
+// PUT /todos/{list_id}/items/{item_id}/tags
+pub async fn set_item_tags(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, names: &[String]) -> Result<Vec<Tag>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // 같은 item의 태그를 동시에 바꾸면 차례대로
+    let statement = prepare(&tx, "select i.id from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for update of i").await?;
+
+    tx.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let tags = replace_tags(&tx, ctx, TagTarget::Item, item_id, names).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(tags)
+}
+
+// PUT /todos/{list_id}/tags
+pub async fn set_list_tags(client: &mut Client, ctx: &RequestContext, list_id: i32, names: &[String]) -> Result<Vec<Tag>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select id from todo_list where id = $1 and deleted_at is null for update").await?;
+
+    tx.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let tags = replace_tags(&tx, ctx, TagTarget::List, list_id, names).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(tags)
+}
+
+// 요청한 사용자의 태그만 names로 바꾼다. 다른 사용자가 붙인 태그는 그대로
+async fn replace_tags<C: CachedClient>(client: &C, ctx: &RequestContext, target: TagTarget, id: i32, names: &[String]) -> Result<Vec<Tag>, AppError> {
+    let (link, column) = target.link();
+    let before = get_target_tags(client, target, id, &ctx.actor).await?;
+    let lower_names: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+
+    let statement = prepare(client, "insert into tag (owner, name) select $1, unnest($2::text[])
+            on conflict (owner, lower(name)) do nothing").await?;
+    client.execute(&statement, &[&ctx.actor, &names]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(client, &format!("delete from {link} x using tag t
+            where t.id = x.tag_id and x.{column} = $1 and t.owner = $2 and lower(t.name) <> all($3)")).await?;
+    client.execute(&statement, &[&id, &ctx.actor, &lower_names]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(client, &format!("insert into {link} ({column}, tag_id)
+            select $1, t.id from tag t where t.owner = $2 and lower(t.name) = any($3)
+            on conflict do nothing")).await?;
+    client.execute(&statement, &[&id, &ctx.actor, &lower_names]).await.map_err(AppError::db_error)?;
+
+    let after = get_target_tags(client, target, id, &ctx.actor).await?;
+
+    // item/list 자체는 바뀌지 않았으므로 entity는 연결 테이블. 이벤트와 웹훅은 보내지 않는다
+    let snapshot = |tags: &[Tag]| json!({ "tags": tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<&str>>() });
+    record_audit(client, ctx, "update", link, Some(id), Some(snapshot(&before)), Some(snapshot(&after))).await?;
+
+    Ok(after)
+}
+
+// list에 대한 권한. 뒤로 갈수록 큰 권한 (Manage는 멤버 관리와 list 삭제)
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum ListAccess {
+    Read,
+    Write,
+    Manage
+}
+
+impl ListAccess {
+    fn allowed_for(self, role: &str) -> bool {
+        match role {
+            "owner" => true,
+            "editor" => self <= ListAccess::Write,
+            _ => self == ListAccess::Read
+        }
+    }
+}
+
+// 멤버가 없는 list(공유 list)는 읽기는 누구나, 쓰기와 관리는 확인된 사용자
+// 멤버가 있는 list는 멤버의 role만큼. 관리자는 항상 된다
+// 멤버가 아니면 anonymous는 401, 사용자는 403
+pub async fn check_list_access<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, access: ListAccess) -> Result<(), AppError> {
+    if ctx.is_admin {
+        return Ok(());
+    }
+
+    let statement = prepare(client, "select exists (select 1 from list_member where list_id = $1) as restricted,
+            (select role from list_member where list_id = $1 and member = $2) as role").await?;
+
+    let row = client.query_one(&statement, &[&list_id, &ctx.actor])
+        .await
+        .map_err(AppError::db_error)?;
+    let restricted: bool = row.get("restricted");
+    let role: Option<String> = row.get("role");
+
+    match (restricted, role) {
+        (false, _) if access == ListAccess::Read => Ok(()),
+        (false, _) => ctx.require_user(),
+        (true, Some(role)) if ctx.authenticated && access.allowed_for(&role) => Ok(()),
+        (true, _) if !ctx.authenticated => Err(AppError::unauthorized()),
+        _ => Err(AppError::forbidden())
+    }
+}
+
+// GET /todos/{list_id}/members. owner, editor, viewer 순서
+pub async fn get_members<C: CachedClient>(client: &C, list_id: i32) -> Result<Vec<ListMember>, AppError> {
+    get_todo(client, list_id).await?;
+
+    let statement = prepare(client, "select * from list_member where list_id = $1
+            order by array_position(array['owner', 'editor', 'viewer']::varchar[], role), added_at, member").await?;
+
+    let members = client.query(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| ListMember::from_row_ref(row).unwrap())
+        .collect::<Vec<ListMember>>();
+
+    Ok(members)
+}
+
+// 멤버를 바꾸는 동안 다른 요청이 owner를 모두 빼지 못하도록 list를 잠근다
+async fn lock_members<C: CachedClient>(client: &C, list_id: i32) -> Result<Vec<ListMember>, AppError> {
+    let statement = prepare(client, "select id from todo_list where id = $1 and deleted_at is null for update").await?;
+
+    client.query_opt(&statement, &[&list_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    get_members(client, list_id).await
+}
+
+// 멤버가 남아있으면 owner가 한 명은 있어야 한다 (멤버를 모두 빼면 다시 공유 list)
+async fn finish_members<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, before: &[ListMember]) -> Result<Vec<ListMember>, AppError> {
+    let after = get_members(client, list_id).await?;
+
+    if !after.is_empty() && !after.iter().any(|member| member.role == "owner") {
+        return Err(AppError::conflict("A list with members needs at least one owner"));
+    }
+
+    let snapshot = |members: &[ListMember]| json!({ "members": members.iter()
+        .map(|member| json!({ "member": member.member, "role": member.role }))
+        .collect::<Vec<Value>>() });
+    record_audit(client, ctx, "update", "list_member", Some(list_id), Some(snapshot(before)), Some(snapshot(&after))).await?;
+
+    Ok(after)
+}
+
+// PUT /todos/{list_id}/members/{member}. 이미 멤버면 role만 바꾼다
+// 공유 list에 첫 멤버를 넣으면 list가 잠기므로, 요청한 사용자를 먼저 owner로 넣는다
+pub async fn set_member(client: &mut Client, ctx: &RequestContext, list_id: i32, member: &str, role: &str) -> Result<Vec<ListMember>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let before = lock_members(&tx, list_id).await?;
+
+    let statement = prepare(&tx, "insert into list_member (list_id, member, role, added_by) values ($1, $2, $3, $4)
+            on conflict (list_id, member) do update set role = excluded.role").await?;
+
+    if before.is_empty() && member != ctx.actor {
+        tx.execute(&statement, &[&list_id, &ctx.actor, &"owner", &ctx.actor]).await.map_err(AppError::db_error)?;
+    }
+    tx.execute(&statement, &[&list_id, &member, &role, &ctx.actor]).await.map_err(AppError::db_error)?;
+
+    let after = finish_members(&tx, ctx, list_id, &before).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(after)
+}
+
+// DELETE /todos/{list_id}/members/{member}. 멤버가 아니었으면 false
+pub async fn remove_member(client: &mut Client, ctx: &RequestContext, list_id: i32, member: &str) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let before = lock_members(&tx, list_id).await?;
+
+    let statement = prepare(&tx, "delete from list_member where list_id = $1 and member = $2").await?;
+    let deleted = tx.execute(&statement, &[&list_id, &member]).await.map_err(AppError::db_error)?;
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    finish_members(&tx, ctx, list_id, &before).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+// 댓글 조회, 수정에 쓰는 컬럼. 삭제된 댓글의 내용은 돌려주지 않는다
+const COMMENT_COLUMNS: &str = "c.id, c.item_id, c.parent_id, c.author,
+        case when c.deleted_at is null then c.body end as body, c.created_at, c.updated_at, c.deleted_at";
+
+// GET /todos/{list_id}/items/{item_id}/comments. 작성 순서대로 (스레드는 CommentNode::threads가 만든다)
+pub async fn get_comments(client: &Client, list_id: i32, item_id: i32) -> Result<Vec<Comment>, AppError> {
+    get_item(client, list_id, item_id).await?;
+
+    let statement = prepare(client, &format!("select {COMMENT_COLUMNS} from item_comment c where c.item_id = $1 order by c.id")).await?;
+
+    let comments = client.query(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Comment::from_row_ref(row).unwrap())
+        .collect::<Vec<Comment>>();
+
+    Ok(comments)
+}
+
+// POST /todos/{list_id}/items/{item_id}/comments
+// 답글은 같은 item의 삭제되지 않은 댓글에만 달 수 있다. 댓글에서 부른 사용자에게 알림을 보낸다
+pub async fn create_comment(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, body: &str, parent_id: Option<i32>) -> Result<Comment, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    get_item(&tx, list_id, item_id).await?;
+
+    if let Some(parent_id) = parent_id {
+        let statement = prepare(&tx, "select item_id, deleted_at is not null as deleted from item_comment where id = $1 for share").await?;
+        let parent = tx.query_opt(&statement, &[&parent_id]).await.map_err(AppError::db_error)?;
+
+        match parent {
+            Some(row) if row.get::<_, i32>("item_id") == item_id && !row.get::<_, bool>("deleted") => (),
+            Some(row) if row.get::<_, i32>("item_id") == item_id => return Err(AppError::validation(format!("Comment {} has been deleted", parent_id))),
+            _ => return Err(AppError::validation(format!("Comment {} is not on this item", parent_id)))
+        }
+    }
+
+    let statement = prepare(&tx, &format!("insert into item_comment as c (item_id, parent_id, author, body) values ($1, $2, $3, $4)
+            returning {COMMENT_COLUMNS}, to_jsonb(c) as snapshot")).await?;
+
+    let row = tx.query_one(&statement, &[&item_id, &parent_id, &ctx.actor, &body])
+        .await
+        .map_err(AppError::db_error)?;
+
+    let comment = Comment::from_row_ref(&row).unwrap();
+    record_audit(&tx, ctx, "create", "item_comment", Some(comment.id), None, row.get("snapshot")).await?;
+
+    let mentioned = new_mentions(None, Some(body));
+    notify(&tx, ctx, "mention", &mentioned, list_id, Some(item_id), Some(comment.id)).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(comment)
+}
+
+// 수정, 삭제할 댓글. 쓴 사람이나 관리자만 바꿀 수 있다 (403)
+async fn get_comment_for_update<C: CachedClient>(client: &C, ctx: &RequestContext, list_id: i32, item_id: i32, comment_id: i32) -> Result<(String, String), AppError> {
+    let statement = prepare(client, "select c.author, c.body from item_comment c
+            join todo_item i on i.id = c.item_id and i.list_id = $1 and i.deleted_at is null
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where c.id = $3 and c.item_id = $2 and c.deleted_at is null
+            for update of c").await?;
+
+    let row = client.query_opt(&statement, &[&list_id, &item_id, &comment_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let author: String = row.get("author");
+    if author != ctx.actor && !ctx.is_admin {
+        return Err(AppError::forbidden());
+    }
+
+    Ok((author, row.get("body")))
+}
+
+// PUT /todos/{list_id}/items/{item_id}/comments/{comment_id}
+// 새로 부른 사용자에게만 알림을 보낸다
+pub async fn update_comment(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, comment_id: i32, body: &str) -> Result<Comment, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let (_, old_body) = get_comment_for_update(&tx, ctx, list_id, item_id, comment_id).await?;
+
+    let statement = prepare(&tx, &format!("update item_comment c
+            set body = $2, updated_at = now()
+            from item_comment old
+            where c.id = $1 and old.id = c.id
+            returning {COMMENT_COLUMNS}, to_jsonb(old) as before, to_jsonb(c) as snapshot")).await?;
+
+    let row = tx.query_one(&statement, &[&comment_id, &body])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "update", "item_comment", Some(comment_id), row.get("before"), row.get("snapshot")).await?;
+
+    let mentioned = new_mentions(Some(&old_body), Some(body));
+    notify(&tx, ctx, "mention", &mentioned, list_id, Some(item_id), Some(comment_id)).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(Comment::from_row_ref(&row).unwrap())
+}
+
+// DELETE /todos/{list_id}/items/{item_id}/comments/{comment_id}
+// deleted_at만 기록한다. 답글이 있으면 "삭제된 댓글"로 스레드에 남고, 이 댓글로 생긴 알림은 지운다
+pub async fn delete_comment(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, comment_id: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    match get_comment_for_update(&tx, ctx, list_id, item_id, comment_id).await {
+        Err(AppError { error_type: AppErrorType::NotFoundError, .. }) => return Ok(false),
+        result => result?
+    };
+
+    let statement = prepare(&tx, "update item_comment c
+            set deleted_at = now()
+            from item_comment old
+            where c.id = $1 and old.id = c.id
+            returning to_jsonb(old) as before, to_jsonb(c) as snapshot").await?;
+
+    let row = tx.query_one(&statement, &[&comment_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "delete", "item_comment", Some(comment_id), row.get("before"), row.get("snapshot")).await?;
+
+    let statement = prepare(&tx, "delete from notification where comment_id = $1").await?;
+    tx.execute(&statement, &[&comment_id]).await.map_err(AppError::db_error)?;
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+// after에는 있고 before에는 없는 @이름
+fn new_mentions(before: Option<&str>, after: Option<&str>) -> Vec<String> {
+    let before = before.map(mentions::parse).unwrap_or_default();
+
+    after.map(mentions::parse)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|name| !before.contains(name))
+        .collect()
+}
+
+// recipients에게 알림을 쌓는다. 자기 자신은 부르더라도 알리지 않는다
+// 사용자 목록이 따로 없으므로 받는 사람은 사용자 이름 그대로
+async fn notify<C: CachedClient>(client: &C, ctx: &RequestContext, kind: &str, recipients: &[String], list_id: i32, item_id: Option<i32>, comment_id: Option<i32>) -> Result<(), AppError> {
+    let recipients: Vec<&str> = recipients.iter()
+        .map(String::as_str)
+        .filter(|recipient| *recipient != ctx.actor)
+        .collect();
+
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    let statement = prepare(client, "insert into notification (recipient, kind, actor, list_id, item_id, comment_id)
+            select unnest($1::text[]), $2, $3, $4, $5, $6").await?;
+
+    client.execute(&statement, &[&recipients, &kind, &ctx.actor, &list_id, &item_id, &comment_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    Ok(())
+}
+
+// GET /me/notifications. 최근 알림부터
+pub async fn get_notifications(client: &Client, recipient: &str, query: &NotificationsQuery) -> Result<Vec<Notification>, AppError> {
+    let statement = prepare(client, "select * from notification
+            where recipient = $1 and (not $2 or read_at is null)
+            order by id desc
+            limit $3").await?;
+
+    let notifications = client.query(&statement, &[&recipient, &query.unread, &query.limit()])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Notification::from_row_ref(row).unwrap())
+        .collect::<Vec<Notification>>();
+
+    Ok(notifications)
+}
+
+// POST /me/notifications/{id}/read. 이미 읽은 알림은 처음 읽은 시간을 그대로 둔다
+pub async fn read_notification(client: &Client, recipient: &str, notification_id: i64) -> Result<bool, AppError> {
+    let statement = prepare(client, "update notification set read_at = coalesce(read_at, now())
+            where id = $1 and recipient = $2").await?;
+
+    let updated = client.execute(&statement, &[&notification_id, &recipient])
+        .await
+        .map_err(AppError::db_error)?;
+
+    Ok(updated > 0)
+}
+
+// POST /me/notifications/read. 읽지 않은 알림을 모두 읽음으로, 바뀐 알림 수를 돌려준다
+pub async fn read_notifications(client: &Client, recipient: &str) -> Result<u64, AppError> {
+    let statement = prepare(client, "update notification set read_at = now() where recipient = $1 and read_at is null").await?;
+
+    client.execute(&statement, &[&recipient])
+        .await
+        .map_err(AppError::db_error)
+}
+
+// GET /todos/{list_id}/items/{item_id}/attachments. 올린 순서대로
+pub async fn get_attachments(client: &Client, list_id: i32, item_id: i32) -> Result<Vec<Attachment>, AppError> {
+    get_item(client, list_id, item_id).await?;
+
+    let statement = prepare(client, "select * from attachment where item_id = $1 order by id").await?;
+
+    let attachments = client.query(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Attachment::from_row_ref(row).unwrap())
+        .collect::<Vec<Attachment>>();
+
+    Ok(attachments)
+}
+
+// 휴지통에 있는 list나 item의 첨부 파일은 없는 것으로 본다
+pub async fn get_attachment(client: &Client, list_id: i32, item_id: i32, attachment_id: i32) -> Result<Attachment, AppError> {
+    let statement = prepare(client, "select a.* from attachment a
+            join todo_item i on i.id = a.item_id and i.list_id = $1 and i.deleted_at is null
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where a.id = $3 and a.item_id = $2").await?;
+
+    client.query_opt(&statement, &[&list_id, &item_id, &attachment_id])
+        .await
+        .map_err(AppError::db_error)?
+        .map(|row| Attachment::from_row_ref(&row).unwrap())
+        .ok_or_else(AppError::not_found)
+}
+
+// POST /todos/{list_id}/items/{item_id}/attachments
+// 파일은 이미 저장소에 올라가 있다. 실패하면 핸들러가 올린 blob을 지운다
+pub async fn create_attachments(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, files: &[NewAttachment]) -> Result<Vec<Attachment>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // 올리는 동안 item이 휴지통으로 가지 않도록
+    let statement = prepare(&tx, "select i.id from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.list_id = $1 and i.id = $2 and i.deleted_at is null
+            for share of i").await?;
+
+    tx.query_opt(&statement, &[&list_id, &item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .ok_or_else(AppError::not_found)?;
+
+    let statement = prepare(&tx, "insert into attachment (item_id, storage_key, filename, content_type, size, sha256, uploaded_by)
+            values ($1, $2, $3, $4, $5, $6, $7)
+            returning *, to_jsonb(attachment.*) as snapshot").await?;
+
+    let mut attachments = Vec::with_capacity(files.len());
+    for file in files {
+        let row = tx.query_one(&statement, &[&item_id, &file.storage_key, &file.filename, &file.content_type, &file.size, &file.sha256, &ctx.actor])
+            .await
+            .map_err(AppError::db_error)?;
+
+        let attachment = Attachment::from_row_ref(&row).unwrap();
+        record_audit(&tx, ctx, "create", "attachment", Some(attachment.id), None, row.get("snapshot")).await?;
+        attachments.push(attachment);
+    }
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(attachments)
+}
+
+// DELETE /todos/{list_id}/items/{item_id}/attachments/{attachment_id}. 올린 사람이나 관리자만 (403)
+// blob은 트리거가 blob_cleanup에 넣고, 백그라운드 작업이 지운다
+pub async fn delete_attachment(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, attachment_id: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "select a.uploaded_by from attachment a
+            join todo_item i on i.id = a.item_id and i.list_id = $1 and i.deleted_at is null
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where a.id = $3 and a.item_id = $2
+            for update of a").await?;
+
+    let uploaded_by: String = match tx.query_opt(&statement, &[&list_id, &item_id, &attachment_id]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get("uploaded_by"),
+        None => return Ok(false)
+    };
+
+    if uploaded_by != ctx.actor && !ctx.is_admin {
+        return Err(AppError::forbidden());
+    }
+
+    let statement = prepare(&tx, "delete from attachment where id = $1 returning to_jsonb(attachment.*) as snapshot").await?;
+    let row = tx.query_one(&statement, &[&attachment_id])
+        .await
+        .map_err(AppError::db_error)?;
+
+    record_audit(&tx, ctx, "delete", "attachment", Some(attachment_id), row.get("snapshot"), None).await?;
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(true)
+}
+
+// 저장소에서 지울 blob. 오래된 것부터
+pub async fn get_blob_cleanups(client: &Client, limit: i64) -> Result<Vec<String>, AppError> {
+    let statement = prepare(client, "select storage_key from blob_cleanup order by created_at limit $1").await?;
+
+    let keys = client.query(&statement, &[&limit])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| row.get("storage_key"))
+        .collect::<Vec<String>>();
+
+    Ok(keys)
+}
+
+pub async fn complete_blob_cleanup(client: &Client, storage_key: &str) -> Result<(), AppError> {
+    let statement = prepare(client, "delete from blob_cleanup where storage_key = $1").await?;
+
+    client.execute(&statement, &[&storage_key])
+        .await
+        .map_err(AppError::db_error)?;
+
+    Ok(())
+}
+
+// GET /todos/{list_id}/items/{item_id}/assignees. 먼저 정해진 담당자부터
+pub async fn get_assignees<C: CachedClient>(client: &C, item_id: i32) -> Result<Vec<Assignee>, AppError> {
+    let statement = prepare(client, "select * from item_assignee where item_id = $1 order by assigned_at, assignee").await?;
+
+    let assignees = client.query(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| Assignee::from_row_ref(row).unwrap())
+        .collect::<Vec<Assignee>>();
+
+    Ok(assignees)
+}
+
+// PUT /todos/{list_id}/items/{item_id}/assignees. 담당자를 names로 바꾼다
+// 새로 담당자가 된 사용자에게는 assigned, 빠진 사용자에게는 unassigned 알림을 보낸다
+pub async fn set_assignees(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, names: &[String]) -> Result<Vec<Assignee>, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    // 동시에 바꾸면 알림이 빠지거나 겹치지 않도록 item을 잠근다
+    get_item_for_update(&tx, list_id, item_id).await?;
+    let before = get_assignees(&tx, item_id).await?;
+
+    let statement = prepare(&tx, "delete from item_assignee where item_id = $1 and assignee <> all($2)").await?;
+    tx.execute(&statement, &[&item_id, &names]).await.map_err(AppError::db_error)?;
+
+    let statement = prepare(&tx, "insert into item_assignee (item_id, assignee, assigned_by)
+            select $1, unnest($2::text[]), $3
+            on conflict do nothing").await?;
+    tx.execute(&statement, &[&item_id, &names, &ctx.actor]).await.map_err(AppError::db_error)?;
+
+    let after = get_assignees(&tx, item_id).await?;
+
+    // item 자체는 바뀌지 않았으므로 entity는 item_assignee. 이벤트와 웹훅은 보내지 않는다
+    let snapshot = |assignees: &[Assignee]| json!({ "assignees": assignees.iter().map(|assignee| assignee.assignee.as_str()).collect::<Vec<&str>>() });
+    record_audit(&tx, ctx, "update", "item_assignee", Some(item_id), Some(snapshot(&before)), Some(snapshot(&after))).await?;
+
+    let added: Vec<String> = names.iter()
+        .filter(|name| !before.iter().any(|assignee| &assignee.assignee == *name))
+        .cloned()
+        .collect();
+    let removed: Vec<String> = before.iter()
+        .filter(|assignee| !names.contains(&assignee.assignee))
+        .map(|assignee| assignee.assignee.clone())
+        .collect();
+    notify(&tx, ctx, "assigned", &added, list_id, Some(item_id), None).await?;
+    notify(&tx, ctx, "unassigned", &removed, list_id, Some(item_id), None).await?;
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(after)
+}
+
+// item i에 체크되지 않은 blocker가 있는지. 휴지통에 있는 blocker는 세지 않는다
+const HAS_UNCHECKED_BLOCKERS: &str = "exists (select 1 from item_dependency d
+                join todo_item b on b.id = d.blocked_by and b.deleted_at is null and not b.checked
+                join todo_list bl on bl.id = b.list_id and bl.deleted_at is null
+                where d.item_id = i.id)";
+
+// item보다 먼저 끝내야 하는데 아직 체크되지 않은 item의 id
+pub async fn unchecked_blockers<C: CachedClient>(client: &C, item_id: i32) -> Result<Vec<i32>, AppError> {
+    let statement = prepare(client, "select b.id from item_dependency d
+            join todo_item b on b.id = d.blocked_by and b.deleted_at is null and not b.checked
+            join todo_list l on l.id = b.list_id and l.deleted_at is null
+            where d.item_id = $1
+            order by b.id").await?;
+
+    let blockers = client.query(&statement, &[&item_id])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| row.get("id"))
+        .collect::<Vec<i32>>();
+
+    Ok(blockers)
+}
+
+// GET /todos/{list_id}/items/{item_id}/dependencies
+// 다른 list의 item은 reader가 볼 수 있는 것만
+pub async fn get_dependencies<C: CachedClient>(client: &C, list_id: i32, item_id: i32, reader: Option<&str>) -> Result<ItemDependencies, AppError> {
+    let item = get_item(client, list_id, item_id).await?;
+
+    // $2가 true면 item을 막고 있는 item, false면 item이 막고 있는 item
+    let statement = prepare(client, "select i.* from item_dependency d
+            join todo_item i on i.id = case when $2 then d.blocked_by else d.item_id end
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where (case when $2 then d.item_id else d.blocked_by end) = $1 and i.deleted_at is null
+                and list_readable(l.id, $3)
+            order by l.position, l.id, i.position, i.id").await?;
+
+    let mut related = vec![];
+    for blocked_by in [true, false] {
+        let items = client.query(&statement, &[&item_id, &blocked_by, &reader])
+            .await
+            .map_err(AppError::db_error)?
+            .iter()
+            .map(|row| TodoItem::from_row_ref(row).unwrap())
+            .collect::<Vec<TodoItem>>();
+        related.push(items);
+    }
+    let blocking = related.pop().unwrap_or_default();
+    let blocked_by = related.pop().unwrap_or_default();
+
+    Ok(ItemDependencies {
+        item_id,
+        ready: !item.checked && blocked_by.iter().all(|blocker| blocker.checked),
+        blocked_by,
+        blocking
+    })
+}
+
+// POST /todos/{list_id}/items/{item_id}/dependencies. blocker는 id만으로 찾으므로 다른 list의 item이어도 된다
+// - blocker가 없거나 휴지통에 있으면 400
+// - blocker가 이미 (여러 단계를 거쳐서라도) item을 기다리고 있으면 순환이 생긴다 (409)
+// - 이미 있는 관계를 다시 추가하면 아무것도 바뀌지 않는다
+pub async fn add_dependency(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, blocked_by: i32) -> Result<ItemDependencies, AppError> {
+    if blocked_by == item_id {
+        return Err(AppError::validation("An item cannot be blocked by itself"));
+    }
+
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    get_item(&tx, list_id, item_id).await?;
+
+    let statement = prepare(&tx, "select i.list_id from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.id = $1 and i.deleted_at is null").await?;
+    let blocker_list_id: i32 = match tx.query_opt(&statement, &[&blocked_by]).await.map_err(AppError::db_error)? {
+        Some(row) => row.get("list_id"),
+        None => return Err(AppError::validation(format!("Blocking item {} was not found", blocked_by)))
+    };
+    // 다른 list의 item을 blocker로 쓰려면 그 list를 볼 수 있어야 한다
+    check_list_access(&tx, ctx, blocker_list_id, ListAccess::Read).await?;
+
+    // 관계는 여러 list에 걸치므로, 두 요청이 동시에 서로 반대 방향을 추가해서 순환이 생기지 않도록 전체를 차례대로 처리한다
+    let statement = prepare(&tx, "select pg_advisory_xact_lock('item_dependency'::regclass::integer, 0)").await?;
+    tx.execute(&statement, &[]).await.map_err(AppError::db_error)?;
+
+    // blocker가 기다리는 item을 따라가다가 item을 만나면 순환
+    let statement = prepare(&tx, "with recursive upstream as (
+                select blocked_by as id from item_dependency where item_id = $1
+                union
+                select d.blocked_by from item_dependency d
+                join upstream u on d.item_id = u.id
+            )
+            select exists (select 1 from upstream where id = $2) as cycle").await?;
+    let row = tx.query_one(&statement, &[&blocked_by, &item_id]).await.map_err(AppError::db_error)?;
+    if row.get("cycle") {
+        return Err(AppError::conflict(format!("Item {} already waits for item {}, adding this dependency would create a cycle", blocked_by, item_id)));
+    }
+
+    let statement = prepare(&tx, "insert into item_dependency (item_id, blocked_by, created_by) values ($1, $2, $3)
+            on conflict do nothing").await?;
+    let inserted = tx.execute(&statement, &[&item_id, &blocked_by, &ctx.actor]).await.map_err(AppError::db_error)?;
+
+    // item 자체는 바뀌지 않았으므로 entity는 item_dependency. 이벤트와 웹훅은 보내지 않는다
+    if inserted > 0 {
+        record_audit(&tx, ctx, "create", "item_dependency", Some(item_id), None, Some(json!({ "blocked_by": blocked_by }))).await?;
+    }
+
+    let dependencies = get_dependencies(&tx, list_id, item_id, ctx.reader()).await?;
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(dependencies)
+}
+
+// DELETE /todos/{list_id}/items/{item_id}/dependencies/{blocker_id}. 관계가 없으면 false
+pub async fn remove_dependency(client: &mut Client, ctx: &RequestContext, list_id: i32, item_id: i32, blocked_by: i32) -> Result<bool, AppError> {
+    let tx = client.transaction().await.map_err(AppError::db_error)?;
+
+    get_item(&tx, list_id, item_id).await?;
+
+    let statement = prepare(&tx, "delete from item_dependency where item_id = $1 and blocked_by = $2").await?;
+    let deleted = tx.execute(&statement, &[&item_id, &blocked_by]).await.map_err(AppError::db_error)?;
+
+    if deleted > 0 {
+        record_audit(&tx, ctx, "delete", "item_dependency", Some(item_id), Some(json!({ "blocked_by": blocked_by })), None).await?;
+    }
+
+    tx.commit().await.map_err(AppError::db_error)?;
+
+    Ok(deleted > 0)
+}
+
+// GET /todos/{list_id}/dependencies. list의 모든 item과, list의 item과 이어진 다른 list의 item을 node로
+// edge는 한쪽이라도 list에 있는 관계. 휴지통에 있는 item과 reader가 볼 수 없는 list의 item은 뺀다
+pub async fn get_dependency_graph(client: &Client, list_id: i32, reader: Option<&str>) -> Result<DependencyGraph, AppError> {
+    get_todo(client, list_id).await?;
+
+    let statement = prepare(client, "select d.item_id, d.blocked_by from item_dependency d
+            join todo_item a on a.id = d.item_id and a.deleted_at is null
+            join todo_list al on al.id = a.list_id and al.deleted_at is null
+            join todo_item b on b.id = d.blocked_by and b.deleted_at is null
+            join todo_list bl on bl.id = b.list_id and bl.deleted_at is null
+            where (a.list_id = $1 or b.list_id = $1) and list_readable(al.id, $2) and list_readable(bl.id, $2)
+            order by d.item_id, d.blocked_by").await?;
+
+    let edges = client.query(&statement, &[&list_id, &reader])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| DependencyEdge { item_id: row.get("item_id"), blocked_by: row.get("blocked_by") })
+        .collect::<Vec<DependencyEdge>>();
+
+    let linked: Vec<i32> = edges.iter().flat_map(|edge| [edge.item_id, edge.blocked_by]).collect();
+
+    let statement = prepare(client, &format!("select i.id, i.list_id, i.title, i.checked, i.status,
+                not i.checked and not {HAS_UNCHECKED_BLOCKERS} as ready
+            from todo_item i
+            join todo_list l on l.id = i.list_id and l.deleted_at is null
+            where i.deleted_at is null and (i.list_id = $1 or i.id = any($2))
+            order by i.list_id <> $1, l.position, l.id, i.position, i.id")).await?;
+
+    let nodes = client.query(&statement, &[&list_id, &linked])
+        .await
+        .map_err(AppError::db_error)?
+        .iter()
+        .map(|row| DependencyNode {
+            id: row.get("id"),
+            list_id: row.get("list_id"),
+            title: row.get("title"),
+            checked: row.get("checked"),
+            status: row.get("status"),
+            ready: row.get("ready")
+        })
+        .collect::<Vec<DependencyNode>>();
+
+    Ok(DependencyGraph { list_id, nodes, edges })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_between_neighbours() {
+        assert_eq!(position_between(Some(0), Some(10)), Some(5));
+        assert_eq!(position_between(Some(-3), Some(-1)), Some(-2));
+        // 사이에 남은 값이 없으면 다시 번호를 매겨야 한다
+        assert_eq!(position_between(Some(4), Some(5)), None);
+        assert_eq!(position_between(Some(4), Some(4)), None);
+    }
+
+    #[test]
+    fn position_at_either_end() {
+        assert_eq!(position_between(Some(7), None), Some(7 + POSITION_GAP));
+        assert_eq!(position_between(None, Some(7)), Some(7 - POSITION_GAP));
+        assert_eq!(position_between(None, None), Some(0));
+    }
+
+    #[test]
+    fn highlight_marks_korean_prefix_terms() {
+        let terms = vec!["장보기".to_string()];
+
+        assert_eq!(highlight("주말 장보기를 하자", &terms), "주말 <mark>장보기를</mark> 하자");
+        assert_eq!(highlight("오늘장보기", &terms), "오늘장보기");
+    }
+
+    #[test]
+    fn highlight_escapes_html() {
+        let terms = vec!["milk".to_string()];
+
+        assert_eq!(highlight("<b>Milk</b> & eggs", &terms), "&lt;b&gt;<mark>Milk</mark>&lt;/b&gt; &amp; eggs");
+    }
+}
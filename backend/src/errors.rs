@@ -1,6 +1,5 @@
 use serde::Serialize;
 use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
-use tokio_postgres::error::DbError;
 use std::fmt;
 
 
@@ -8,6 +7,15 @@ use std::fmt;
 pub enum AppErrorType {
     DbError,
     NotFoundError,
+    PreconditionFailed,
+    ForbiddenError,
+    UnauthorizedError,
+    ValidationError,
+    ConflictError,
+    UnprocessableError,
+    PayloadTooLargeError,
+    UnsupportedMediaTypeError,
+    StorageError,
 }
 
 #[derive(Debug)]
@@ -19,9 +27,12 @@ pub struct AppError {
 
 impl AppError {
     pub fn message(&self) -> String {
-        match &*self {
+        match self {
             AppError {message: Some(message), cause: _, error_type: _} => message.clone(),
             AppError {message: None, cause: _, error_type: AppErrorType::NotFoundError} => "The requested item was not found".to_string(),
+            AppError {message: None, cause: _, error_type: AppErrorType::UnauthorizedError} => "Authentication is required".to_string(),
+            AppError {message: None, cause: _, error_type: AppErrorType::ForbiddenError} => "You are not allowed to access this resource".to_string(),
+            AppError {message: None, cause: _, error_type: AppErrorType::PreconditionFailed} => "The resource has been modified by another request".to_string(),
             _ => "An unexpected error has occurred".to_string()
         }
     }
@@ -29,6 +40,53 @@ impl AppError {
     pub fn db_error(error: impl ToString) -> AppError {
         AppError { message: None, cause: Some(error.to_string()), error_type: AppErrorType::DbError}
     }
+
+    pub fn not_found() -> AppError {
+        AppError { message: None, cause: None, error_type: AppErrorType::NotFoundError}
+    }
+
+    pub fn validation(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::ValidationError}
+    }
+
+    pub fn unauthorized() -> AppError {
+        AppError { message: None, cause: None, error_type: AppErrorType::UnauthorizedError}
+    }
+
+    pub fn forbidden() -> AppError {
+        AppError { message: None, cause: None, error_type: AppErrorType::ForbiddenError}
+    }
+
+    pub fn conflict(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::ConflictError}
+    }
+
+    // 체크되지 않은 blocker가 있는 item을 체크하려고 함 (409). item_ids는 아직 체크되지 않은 blocker
+    pub fn blocked(item_ids: &[i32]) -> AppError {
+        let ids = item_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(", ");
+        AppError::conflict(format!("The item is blocked by unchecked items: {}", ids))
+    }
+
+    pub fn unprocessable(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::UnprocessableError}
+    }
+
+    pub fn payload_too_large(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::PayloadTooLargeError}
+    }
+
+    pub fn unsupported_media_type(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::UnsupportedMediaTypeError}
+    }
+
+    // 첨부 파일 저장소(디스크, S3)의 오류. 원인은 로그에만 남긴다
+    pub fn storage_error(error: impl ToString) -> AppError {
+        AppError { message: None, cause: Some(error.to_string()), error_type: AppErrorType::StorageError}
+    }
+
+    pub fn precondition_failed(message: impl ToString) -> AppError {
+        AppError { message: Some(message.to_string()), cause: None, error_type: AppErrorType::PreconditionFailed}
+    }
 }
 
 // impl fmt::Display for AppError는 AppError를 출력했을 때,
@@ -58,7 +116,16 @@ impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
         match self.error_type {
             AppErrorType::DbError => StatusCode::INTERNAL_SERVER_ERROR,
-            AppErrorType::NotFoundError => StatusCode::NOT_FOUND
+            AppErrorType::NotFoundError => StatusCode::NOT_FOUND,
+            AppErrorType::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            AppErrorType::ForbiddenError => StatusCode::FORBIDDEN,
+            AppErrorType::UnauthorizedError => StatusCode::UNAUTHORIZED,
+            AppErrorType::ValidationError => StatusCode::BAD_REQUEST,
+            AppErrorType::ConflictError => StatusCode::CONFLICT,
+            AppErrorType::UnprocessableError => StatusCode::UNPROCESSABLE_ENTITY,
+            AppErrorType::PayloadTooLargeError => StatusCode::PAYLOAD_TOO_LARGE,
+            AppErrorType::UnsupportedMediaTypeError => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppErrorType::StorageError => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 
@@ -0,0 +1,195 @@
+use actix_web::web::Bytes;
+use futures_util::{stream, Stream};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval_at, Instant, Interval};
+
+// postgres의 NOTIFY 채널 이름 (init.pgsql 참고)
+// PENDING_CHANNEL: item_event가 커밋되었다. seq를 붙여야 한다 (payload 없음)
+// CHANNEL: seq를 붙였다. payload는 마지막 seq
+pub const PENDING_CHANNEL: &str = "todo_events_pending";
+pub const CHANNEL: &str = "todo_events";
+
+// item 변경 이벤트 하나.
+// id는 item_event의 seq. 커밋된 순서대로 붙기 때문에 서버가 여러 대여도 항상 증가하는 같은 값이 된다
+#[derive(Clone, Serialize)]
+pub struct ItemEvent {
+    pub id: i64,
+    // created, updated, checked, deleted, reminded, reordered
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub list_id: i32,
+    // item의 snapshot. reordered이면 {"list_id", "items": [{"id", "position", "version", "dav_name"}]}
+    pub item: serde_json::Value
+}
+
+impl ItemEvent {
+    // Server-Sent Events 형식
+    // id: 3
+    // event: checked
+    // data: {...}
+    // (빈 줄)
+    pub fn to_sse(&self) -> String {
+        format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id,
+            self.kind,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+// Last-Event-ID로 이어받을 때 필요한 과거 이벤트를 replay로 돌려준다.
+// 요청한 id 이후의 이벤트가 이미 버퍼에서 밀려났다면 Gap. 클라이언트는 목록을 다시 불러와야 한다
+pub enum Replay {
+    Events(Vec<ItemEvent>),
+    Gap
+}
+
+// LISTEN으로 받은 이벤트를 각 SSE 연결에 나눠주는 곳.
+// AppState에 Arc로 들어가서 모든 워커가 같은 hub를 사용한다
+pub struct EventHub {
+    sender: broadcast::Sender<ItemEvent>,
+    buffer: Mutex<ReplayBuffer>,
+    capacity: usize
+}
+
+struct ReplayBuffer {
+    events: VecDeque<ItemEvent>,
+    // 버퍼에서 밀려난 이벤트 중 가장 큰 id
+    evicted_up_to: i64
+}
+
+impl EventHub {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+
+        EventHub {
+            sender,
+            buffer: Mutex::new(ReplayBuffer {
+                events: VecDeque::with_capacity(capacity),
+                evicted_up_to: 0
+            }),
+            capacity
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ItemEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: ItemEvent) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            if buffer.events.len() == self.capacity {
+                if let Some(evicted) = buffer.events.pop_front() {
+                    buffer.evicted_up_to = evicted.id;
+                }
+            }
+            buffer.events.push_back(event.clone());
+        }
+
+        // 받는 쪽(SSE 연결)이 하나도 없으면 에러가 나지만 무시해도 된다
+        let _ = self.sender.send(event);
+    }
+
+    pub fn replay(&self, list_id: i32, after: i64) -> Replay {
+        let buffer = self.buffer.lock().unwrap();
+
+        // after 이후의 이벤트가 이미 밀려났다면 중간이 빠진 것
+        if after < buffer.evicted_up_to {
+            return Replay::Gap;
+        }
+
+        Replay::Events(buffer.events
+            .iter()
+            .filter(|event| event.id > after && event.list_id == list_id)
+            .cloned()
+            .collect())
+    }
+}
+
+// GET /todos/{list_id}/events 의 응답 body.
+// 먼저 Last-Event-ID 이후의 이벤트를 replay하고, 그 다음부터는 들어오는 이벤트를 그대로 보낸다
+pub fn item_event_stream(hub: Arc<EventHub>, list_id: i32, last_event_id: Option<i64>, heartbeat: Duration) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    // replay보다 먼저 구독해야 그 사이에 들어온 이벤트를 놓치지 않는다
+    let receiver = hub.subscribe();
+
+    let mut state = StreamState {
+        hub,
+        receiver,
+        list_id,
+        last_id: last_event_id.unwrap_or(0),
+        replayed: HashSet::new(),
+        pending: VecDeque::new(),
+        heartbeat: interval_at(Instant::now() + heartbeat, heartbeat)
+    };
+
+    // 연결이 끊겼을 때 브라우저(EventSource)가 3초 뒤에 다시 연결하도록
+    state.pending.push_back("retry: 3000\n\n".to_string());
+    if let Some(last_event_id) = last_event_id {
+        state.replay(last_event_id);
+    }
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(chunk) = state.pending.pop_front() {
+                return Some((Ok(Bytes::from(chunk)), state));
+            }
+
+            tokio::select! {
+                // 프록시 등이 연결을 끊지 않도록 주기적으로 주석(:) 한 줄을 보낸다
+                _ = state.heartbeat.tick() => state.pending.push_back(": keep-alive\n\n".to_string()),
+                received = state.receiver.recv() => match received {
+                    Ok(event) => state.push(event),
+                    // 너무 느려서 채널에서 이벤트가 밀려났다면 버퍼에서 다시 채운다
+                    Err(RecvError::Lagged(_)) => {
+                        let last_id = state.last_id;
+                        state.replay(last_id);
+                    },
+                    Err(RecvError::Closed) => return None
+                }
+            }
+        }
+    })
+}
+
+struct StreamState {
+    hub: Arc<EventHub>,
+    receiver: broadcast::Receiver<ItemEvent>,
+    list_id: i32,
+    last_id: i64,
+    // 마지막 replay에서 이미 보낸 이벤트. 채널로 같은 이벤트가 또 오면 건너뛴다
+    replayed: HashSet<i64>,
+    pending: VecDeque<String>,
+    heartbeat: Interval
+}
+
+impl StreamState {
+    fn push(&mut self, event: ItemEvent) {
+        if event.list_id != self.list_id || self.replayed.contains(&event.id) {
+            return;
+        }
+
+        self.last_id = self.last_id.max(event.id);
+        self.pending.push_back(event.to_sse());
+    }
+
+    fn replay(&mut self, after: i64) {
+        match self.hub.replay(self.list_id, after) {
+            Replay::Events(events) => {
+                self.replayed = events.iter().map(|event| event.id).collect();
+                for event in events {
+                    self.last_id = self.last_id.max(event.id);
+                    self.pending.push_back(event.to_sse());
+                }
+            },
+            // 이어받을 수 없으니 클라이언트가 GET /todos/{list_id}/items 로 다시 불러오도록 알린다
+            Replay::Gap => self.pending.push_back("event: reset\ndata: {}\n\n".to_string())
+        }
+    }
+}
@@ -0,0 +1,480 @@
+use crate::ical;
+use crate::models::{TodoItem, TodoList};
+use serde::{Deserialize, Serialize};
+
+// GET /todos/{list_id}/export, POST /todos/import 에서 사용하는 형식
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Markdown,
+    // http://todotxt.org
+    TodoTxt,
+    Json,
+    Csv,
+    // iCalendar의 VTODO (ical.rs)
+    Ics
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::TodoTxt => "text/plain; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Ics => "text/calendar; charset=utf-8"
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::TodoTxt => "txt",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Ics => "ics"
+        }
+    }
+}
+
+// todo_item.title 이 varchar(150)
+const MAX_TITLE_LENGTH: usize = 150;
+
+// 가져온 list. title은 markdown의 # 제목, json의 title처럼 파일 안에 있을 때만 값이 있다
+pub struct ImportedList {
+    pub title: Option<String>,
+    pub items: Vec<ImportedItem>
+}
+
+#[derive(Deserialize)]
+pub struct ImportedItem {
+    pub title: String,
+    #[serde(default)]
+    pub checked: bool
+}
+
+// 몇 번째 줄(1부터)이 왜 잘못되었는지
+#[derive(Serialize)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String
+}
+
+impl ParseError {
+    pub fn new(line: usize, message: impl ToString) -> Self {
+        ParseError { line, message: message.to_string() }
+    }
+}
+
+pub fn export(format: Format, list: &TodoList, items: &[TodoItem]) -> String {
+    match format {
+        Format::Markdown => export_markdown(list, items),
+        Format::TodoTxt => export_todotxt(items),
+        Format::Json => export_json(list, items),
+        Format::Csv => export_csv(items),
+        Format::Ics => ical::calendar(&list.title, &[(list, items.iter().collect())])
+    }
+}
+
+// 잘못된 줄이 하나라도 있으면 전부 모아서 돌려준다 (한 번에 고칠 수 있도록)
+pub fn parse(format: Format, text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    let imported = match format {
+        Format::Markdown => parse_markdown(text)?,
+        Format::TodoTxt => parse_todotxt(text)?,
+        Format::Json => parse_json(text)?,
+        Format::Csv => parse_csv(text)?,
+        Format::Ics => ical::parse(text)?
+    };
+
+    if imported.items.is_empty() && imported.title.is_none() {
+        return Err(vec![ParseError::new(1, "Nothing to import")]);
+    }
+
+    Ok(imported)
+}
+
+fn check_title(line: usize, title: &str, errors: &mut Vec<ParseError>) -> Option<String> {
+    let title = title.trim();
+    if title.is_empty() {
+        errors.push(ParseError::new(line, "Item title is empty"));
+        None
+    } else if title.chars().count() > MAX_TITLE_LENGTH {
+        errors.push(ParseError::new(line, format!("Item title is longer than {} characters", MAX_TITLE_LENGTH)));
+        None
+    } else {
+        Some(title.to_string())
+    }
+}
+
+fn finish(imported: ImportedList, errors: Vec<ParseError>) -> Result<ImportedList, Vec<ParseError>> {
+    if errors.is_empty() {
+        Ok(imported)
+    } else {
+        Err(errors)
+    }
+}
+
+// # 장보기
+//
+// - [ ] 우유
+// - [x] 계란
+fn export_markdown(list: &TodoList, items: &[TodoItem]) -> String {
+    let mut text = format!("# {}\n\n", list.title);
+    for item in items {
+        text.push_str(&format!("- [{}] {}\n", if item.checked { "x" } else { " " }, item.title));
+    }
+    text
+}
+
+// 첫 번째 # 제목을 list 제목으로, "- [ ]" / "- [x]" 줄을 item으로 읽는다
+// 체크박스가 없는 "- 우유"도 체크되지 않은 item으로 본다. 들여쓰기는 무시
+fn parse_markdown(text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    let mut imported = ImportedList { title: None, items: vec![] };
+    let mut errors = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(heading) = line.strip_prefix("# ") {
+            if imported.title.is_none() && imported.items.is_empty() {
+                imported.title = check_title(number, heading, &mut errors);
+            } else {
+                errors.push(ParseError::new(number, "Only one # heading (the list title) is allowed, before the items"));
+            }
+            continue;
+        }
+
+        let bullet = match line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")).or_else(|| line.strip_prefix("+ ")) {
+            Some(bullet) => bullet.trim_start(),
+            None => {
+                errors.push(ParseError::new(number, "Expected a list item such as \"- [ ] title\""));
+                continue;
+            }
+        };
+
+        let (checked, title) = if let Some(title) = bullet.strip_prefix("[ ]") {
+            (false, title)
+        } else if let Some(title) = bullet.strip_prefix("[x]").or_else(|| bullet.strip_prefix("[X]")) {
+            (true, title)
+        } else {
+            (false, bullet)
+        };
+
+        if let Some(title) = check_title(number, title, &mut errors) {
+            imported.items.push(ImportedItem { title, checked });
+        }
+    }
+
+    finish(imported, errors)
+}
+
+// x 2024-01-02 2024-01-01 계란
+// 2024-01-01 우유
+// 완료된 item은 x와 완료 날짜, 그 다음에 만든 날짜가 온다
+fn export_todotxt(items: &[TodoItem]) -> String {
+    let mut text = String::new();
+    for item in items {
+        let created = item.created_at.format("%Y-%m-%d");
+        if item.checked {
+            text.push_str(&format!("x {} {} {}\n", item.updated_at.format("%Y-%m-%d"), created, item.title));
+        } else {
+            text.push_str(&format!("{} {}\n", created, item.title));
+        }
+    }
+    text
+}
+
+// 우선순위 "(A)"와 날짜는 이 서버에 저장할 곳이 없어서 버린다. +project, @context는 제목에 그대로 남김
+fn parse_todotxt(text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    let mut imported = ImportedList { title: None, items: vec![] };
+    let mut errors = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let mut rest = line.trim();
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let checked = match rest.strip_prefix("x ") {
+            Some(after) => {
+                rest = after.trim_start();
+                true
+            },
+            None => false
+        };
+
+        if !checked {
+            rest = strip_priority(rest);
+        }
+
+        // 완료된 item은 날짜가 두 개(완료, 만든 날짜)까지, 아니면 한 개(만든 날짜)까지
+        for _ in 0..if checked { 2 } else { 1 } {
+            rest = strip_date(rest);
+        }
+
+        if let Some(title) = check_title(number, rest, &mut errors) {
+            imported.items.push(ImportedItem { title, checked });
+        }
+    }
+
+    finish(imported, errors)
+}
+
+fn strip_priority(text: &str) -> &str {
+    let bytes = text.as_bytes();
+    if bytes.len() >= 4 && bytes[0] == b'(' && bytes[1].is_ascii_uppercase() && bytes[2] == b')' && bytes[3] == b' ' {
+        text[4..].trim_start()
+    } else {
+        text
+    }
+}
+
+fn strip_date(text: &str) -> &str {
+    match text.split_once(' ') {
+        Some((date, rest)) if chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok() => rest.trim_start(),
+        _ => text
+    }
+}
+
+// ?include=items 와 같은 모양 ({"id": .., "title": .., "items": [..]})
+fn export_json(list: &TodoList, items: &[TodoItem]) -> String {
+    serde_json::to_string_pretty(&serde_json::json!({
+        "id": list.id,
+        "title": list.title,
+        "items": items
+    })).unwrap_or_default()
+}
+
+// {"title": "장보기", "items": [{"title": "우유", "checked": false}]}
+// export한 json을 그대로 넣어도 되도록 다른 필드(id, version 등)는 무시한다
+fn parse_json(text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    #[derive(Deserialize)]
+    struct JsonList {
+        title: Option<String>,
+        #[serde(default)]
+        items: Vec<ImportedItem>
+    }
+
+    let list = serde_json::from_str::<JsonList>(text)
+        .map_err(|err| vec![ParseError::new(err.line().max(1), err)])?;
+
+    // json은 item마다 줄 번호를 알 수 없으므로 item 순서(1부터)를 대신 쓴다
+    let mut errors = vec![];
+    let title = match list.title {
+        Some(title) => check_title(1, &title, &mut errors),
+        None => None
+    };
+    let items = list.items
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, item)| check_title(index + 1, &item.title, &mut errors).map(|title| ImportedItem { title, checked: item.checked }))
+        .collect();
+
+    finish(ImportedList { title, items }, errors)
+}
+
+fn export_csv(items: &[TodoItem]) -> String {
+    let mut text = "id,title,checked,created_at,updated_at\r\n".to_string();
+    for item in items {
+        text.push_str(&format!(
+            "{},{},{},{},{}\r\n",
+            item.id,
+            csv_field(&item.title),
+            item.checked,
+            item.created_at.to_rfc3339(),
+            item.updated_at.to_rfc3339()
+        ));
+    }
+    text
+}
+
+// 쉼표, 따옴표, 줄바꿈이 있으면 따옴표로 감싸고 안의 따옴표는 두 번 쓴다 (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// 첫 줄은 헤더. title 열은 꼭 있어야 하고, checked 열(true/false, 1/0, x, yes/no)은 없어도 된다
+// export한 csv의 다른 열(id 등)은 무시
+fn parse_csv(text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    let rows = split_csv(text).map_err(|err| vec![err])?;
+    let mut rows = rows.into_iter().filter(|(_, fields)| !(fields.len() == 1 && fields[0].trim().is_empty()));
+
+    let (header_line, header) = rows.next().ok_or_else(|| vec![ParseError::new(1, "Header row is missing")])?;
+    let column = |name: &str| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+    let title_column = column("title").ok_or_else(|| vec![ParseError::new(header_line, "Header must contain a title column")])?;
+    let checked_column = column("checked");
+
+    let mut imported = ImportedList { title: None, items: vec![] };
+    let mut errors = vec![];
+
+    for (number, fields) in rows {
+        let title = match fields.get(title_column) {
+            Some(title) => title,
+            None => {
+                errors.push(ParseError::new(number, format!("Expected {} columns, found {}", header.len(), fields.len())));
+                continue;
+            }
+        };
+
+        let checked = match checked_column.and_then(|column| fields.get(column)).map(|value| value.trim().to_lowercase()) {
+            None => false,
+            Some(value) => match value.as_str() {
+                "" | "false" | "0" | "no" | "n" => false,
+                "true" | "1" | "x" | "yes" | "y" => true,
+                _ => {
+                    errors.push(ParseError::new(number, format!("checked must be true or false, found \"{}\"", value)));
+                    continue;
+                }
+            }
+        };
+
+        if let Some(title) = check_title(number, title, &mut errors) {
+            imported.items.push(ImportedItem { title, checked });
+        }
+    }
+
+    finish(imported, errors)
+}
+
+// (시작 줄 번호, 필드들) 목록. 따옴표 안의 줄바꿈은 같은 필드로 이어진다
+fn split_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, ParseError> {
+    let mut rows = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            },
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {},
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                rows.push((row_line, std::mem::take(&mut fields)));
+                line += 1;
+                row_line = line;
+            },
+            '\n' => {
+                field.push(c);
+                line += 1;
+            },
+            _ => field.push(c)
+        }
+    }
+
+    if quoted {
+        return Err(ParseError::new(row_line, "Quoted field is not closed"));
+    }
+
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        rows.push((row_line, fields));
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(format: Format, text: &str) -> ImportedList {
+        match parse(format, text) {
+            Ok(imported) => imported,
+            Err(errors) => panic!("unexpected errors at lines {:?}", errors.iter().map(|error| error.line).collect::<Vec<usize>>())
+        }
+    }
+
+    fn error_lines(format: Format, text: &str) -> Vec<usize> {
+        match parse(format, text) {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => errors.iter().map(|error| error.line).collect()
+        }
+    }
+
+    fn titles(imported: &ImportedList) -> Vec<(&str, bool)> {
+        imported.items.iter().map(|item| (item.title.as_str(), item.checked)).collect()
+    }
+
+    #[test]
+    fn markdown_reads_title_and_checkboxes() {
+        let imported = parsed(Format::Markdown, "# 장보기\n\n- [ ] 우유\n  - [x] 계란\n* 빵\n");
+
+        assert_eq!(imported.title.as_deref(), Some("장보기"));
+        assert_eq!(titles(&imported), vec![("우유", false), ("계란", true), ("빵", false)]);
+    }
+
+    #[test]
+    fn markdown_reports_every_bad_line() {
+        // 두 번째 제목, 목록이 아닌 줄, 빈 제목
+        assert_eq!(error_lines(Format::Markdown, "# a\n- [ ] b\n# c\nplain\n- [ ]  \n"), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn todotxt_strips_priority_and_dates() {
+        let imported = parsed(Format::TodoTxt, "(A) 2024-01-01 우유 +home @store\nx 2024-01-03 2024-01-01 계란\n");
+
+        assert_eq!(titles(&imported), vec![("우유 +home @store", false), ("계란", true)]);
+    }
+
+    #[test]
+    fn json_ignores_other_fields() {
+        let imported = parsed(Format::Json, r#"{"id": 3, "title": "장보기", "items": [{"id": 1, "title": "우유", "checked": true, "version": 2}]}"#);
+
+        assert_eq!(imported.title.as_deref(), Some("장보기"));
+        assert_eq!(titles(&imported), vec![("우유", true)]);
+    }
+
+    #[test]
+    fn json_syntax_error_has_line() {
+        assert_eq!(error_lines(Format::Json, "{\n\"title\": \n}"), vec![3]);
+    }
+
+    #[test]
+    fn csv_reads_quoted_fields_and_checked_values() {
+        let imported = parsed(Format::Csv, "id,title,checked\r\n1,\"우유, 2개\",false\r\n2,\"say \"\"hi\"\"\",x\r\n3,\"두\n줄\",\r\n");
+
+        assert_eq!(titles(&imported), vec![("우유, 2개", false), ("say \"hi\"", true), ("두\n줄", false)]);
+    }
+
+    #[test]
+    fn csv_errors_use_row_start_line() {
+        // 3번 줄에서 시작한 row는 따옴표 안의 줄바꿈 때문에 두 줄을 차지한다
+        assert_eq!(error_lines(Format::Csv, "title,checked\n\"a\",maybe\n\"b\nc\",nope\n"), vec![2, 3]);
+        assert_eq!(error_lines(Format::Csv, "name\nfoo\n"), vec![1]);
+        assert_eq!(error_lines(Format::Csv, "title\n\"open\n"), vec![2]);
+    }
+
+    #[test]
+    fn empty_file_is_rejected() {
+        assert_eq!(error_lines(Format::Markdown, "\n\n"), vec![1]);
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("우유"), "우유");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -1,11 +1,22 @@
-use std::io;
-
 use crate::config::AppState;
-use crate::models::{Status, CreateTodoList, ResultResponse};
-use crate::db;
-use crate::errors::{AppError, AppErrorType};
+use crate::models::{Status, CreateTodoList, ResultResponse, TodosQuery, ItemsQuery, ItemsView, ItemNode, ItemParent, MoveRequest, TransferItems, DuplicateTodo, TodoTemplate, TagRequest, SetTags, Workflow, ItemStatus, ItemPriority, ItemNotes, CommentNode, CommentRequest, NotificationsQuery, ReadNotifications, NewAttachment, SetAssignees, SetMemberRole, AddDependency, ItemSchedule, ItemRecurrence, AgendaQuery, TodoList, TodoListWithItems, TodoItem, AuditQuery, SearchQuery, BatchRequest, ExportQuery, ImportQuery, CreateCalendarFeed, ImportErrorResponse, IssuedToken, CreateWebhook, CreatedWebhook, DeliveriesQuery};
+use crate::webhooks;
+use crate::formats;
+use crate::ical;
+use crate::attachments::{self, ByteRange};
+use std::collections::HashMap;
+use crate::db::{self, ListAccess, TagTarget};
+use crate::context::RequestContext;
+use crate::auth::{self, Credential};
+use crate::events::item_event_stream;
+use crate::conditional::{cached_json, optional_version, required_version, version_etag};
+use crate::errors::AppError;
 use deadpool_postgres::{Pool, Client};
-use actix_web::{Responder, HttpResponse, web};
+use actix_web::{Responder, HttpRequest, HttpResponse, web, web::BytesMut, http::StatusCode, http::header::{self, ContentDisposition, EntityTag, ETag, CacheControl, CacheDirective}};
+use actix_multipart::Multipart;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use std::io;
 use slog::{o, crit, Logger, error};
 
 pub async fn get_client(pool: Pool, log: Logger) -> Result<Client, AppError> {
@@ -54,7 +65,31 @@ pub async fn status() -> impl Responder {
         .json(Status {status: "UP".to_string()})
 }
 
-pub async fn get_todos(state: web::Data<AppState>) -> Result<impl Responder, AppError> {
+// 이미 확인된 사용자(다른 토큰, 인증 프록시)에게 새 토큰을 만들어준다
+pub async fn issue_token(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "issue_token"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    let ttl_secs = state.auth.token_ttl_secs;
+    let token = state.auth.issue_token(&ctx.actor, ttl_secs)
+        .ok_or_else(|| AppError::unprocessable("AUTH.SECRET is not configured"))
+        .map_err(log_error(log))?;
+
+    Ok(HttpResponse::Created().json(IssuedToken {
+        user: ctx.actor,
+        token,
+        expires_at: chrono::Utc::now() + chrono::Duration::seconds(ttl_secs)
+    }))
+}
+
+// prepare 캐시가 실제로 적중하고 있는지 부하 테스트 중에 확인하기 위한 용도
+pub async fn statement_cache_stats() -> impl Responder {
+    HttpResponse::Ok()
+        .json(db::statement_cache_stats())
+}
+
+pub async fn get_todos(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, query: web::Query<TodosQuery>) -> Result<impl Responder, AppError> {
     
     // log 위치 설정등
     // 여기서 handler는 마음대로 정해도 되는 양식
@@ -83,13 +118,26 @@ pub async fn get_todos(state: web::Data<AppState>) -> Result<impl Responder, App
     // &의 경우 참조를 넘기는 것.
     // 읽기 전용. 이렇게 넘겨 받은 변수의 경우 수정을 하거나 소유권을 가져갈 순 없음
     // 원본 데이터를 가르키는 포인터 이나, 수정이나 소유권을 가질 순 없음
-    let result = db::get_todos(&client).await;
+    let result = db::get_todos(&client, query.template, query.tag.as_deref(), &ctx.actor, ctx.reader()).await;
+
+    // ?include=items 면 list 목록 + 해당 list들의 item을 한 번에 가져와서 묶어준다
+    if query.includes("items") {
+        let todos = result.map_err(log_error(log.clone()))?;
+
+        let last_modified = todos.iter().map(|todo| todo.updated_at).max();
+
+        return get_todos_with_items(&client, todos)
+            .await
+            .and_then(|todos| cached_json(&req, &todos, None, last_modified))
+            .map_err(log_error(log));
+    }
 
     // result는 현재 Result<Vec<TodoList>, AppError>의 타입을 가지고 있다.
     // 이것을 Result<json 값을 가지고있는 Vec<TodoList>>로 바꾸는 형 변환 과정이다
     // rust에서는 map을 사용하면 some이나 ok 상태의 값을 안전하게 다룰 수 있다. 
     result
-        .map(|todos| HttpResponse::Ok().json(todos))
+        // ETag를 붙이고 If-None-Match가 같으면 304를 반환
+        .and_then(|todos| cached_json(&req, &todos, None, todos.iter().map(|todo| todo.updated_at).max()))
         // .map_err(|err| {
         //     let sublog = log.new(o!("cause" => err.cause.clone()));
         //     error!(sublog, "{}", err.message());
@@ -100,7 +148,24 @@ pub async fn get_todos(state: web::Data<AppState>) -> Result<impl Responder, App
         .map_err(log_error(log))
 }
 
-pub async fn get_itmes(state: web::Data<AppState>, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+async fn get_todos_with_items(client: &Client, todos: Vec<TodoList>) -> Result<Vec<TodoListWithItems>, AppError> {
+    let list_ids = todos.iter().map(|todo| todo.id).collect::<Vec<i32>>();
+
+    let mut items_by_list: HashMap<i32, Vec<TodoItem>> = HashMap::new();
+    for item in db::get_items_for_lists(client, &list_ids).await? {
+        items_by_list.entry(item.list_id).or_default().push(item);
+    }
+
+    Ok(todos
+        .into_iter()
+        .map(|list| {
+            let items = items_by_list.remove(&list.id).unwrap_or_default();
+            TodoListWithItems { list, items }
+        })
+        .collect())
+}
+
+pub async fn get_itmes(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, query: web::Query<ItemsQuery>) -> Result<impl Responder, AppError> {
 
     // let client: Client = state.pool.get()
     // .await
@@ -108,28 +173,97 @@ pub async fn get_itmes(state: web::Data<AppState>, path: web::Path<(i32,)>) -> R
     // .map_err(AppError::db_error)?;
 
     let log = state.log.new(o!("handler" => "get_itmes"));
+    // 태그는 사용자마다 따로이므로 태그로 거를 때는 사용자가 필요하다
+    if query.has_tag_filters() {
+        ctx.require_user().map_err(log_error(log.clone()))?;
+    }
     let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
     
 
-    let result = db::get_itmes(&client, path.0).await;
+    // ?due_before=, ?overdue=true, 태그 조건이 있을 때만 조건으로 거른다
+    let result = if query.has_filters() {
+        db::find_items(&client, Some(path.0), &ctx.actor, &query, query.assignee.as_deref(), ctx.reader()).await
+    } else {
+        db::get_itmes(&client, path.0).await
+    };
 
     result
-        .map(|items| HttpResponse::Ok().json(items))
+        .and_then(|items| items_json(&req, &query, items))
+        .map_err(log_error(log))
+}
+
+// GET /items. get_itmes와 같은 조건으로 모든 list에서 찾는다. 조건이 하나는 있어야 한다
+pub async fn find_items(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, query: web::Query<ItemsQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "find_items"));
+    if !query.has_filters() {
+        return Err(log_error(log)(AppError::validation("At least one of due_before, overdue, status, checked, assignee, ready, list_id, tag, any_tag or all_tags is required")));
+    }
+    if query.has_tag_filters() {
+        ctx.require_user().map_err(log_error(log.clone()))?;
+    }
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::find_items(&client, query.list_id, &ctx.actor, &query, query.assignee.as_deref(), ctx.reader()).await;
+
+    result
+        .and_then(|items| items_json(&req, &query, items))
+        .map_err(log_error(log))
+}
+
+// GET /me/items. 요청한 사용자가 담당자인 item을 모든 list에서 (템플릿 list 제외)
+// ex) /me/items?checked=false&due_before=2024-02-01T00:00:00Z, /me/items?list_id=3&status=doing
+pub async fn get_my_items(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, query: web::Query<ItemsQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_my_items"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::find_items(&client, query.list_id, &ctx.actor, &query, Some(&ctx.actor), ctx.reader()).await;
+
+    result
+        .and_then(|items| items_json(&req, &query, items))
+        .map_err(log_error(log))
+}
+
+// ?view=tree 이면 하위 item을 children으로 묶고 진행률(progress)을 붙인다
+fn items_json(req: &HttpRequest, query: &ItemsQuery, items: Vec<TodoItem>) -> Result<HttpResponse, AppError> {
+    let last_modified = items.iter().map(|item| item.updated_at).max();
+    match query.view {
+        ItemsView::Flat => cached_json(req, &items, None, last_modified),
+        ItemsView::Tree => cached_json(req, &ItemNode::tree(items), None, last_modified)
+    }
+}
+
+pub async fn get_item(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_item"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_item(&client, path.0, path.1).await;
+
+    // item 하나는 version을 그대로 ETag로 사용. 이 값을 PUT 요청의 If-Match에 넣으면 된다
+    result
+        .and_then(|item| cached_json(&req, &item, Some(version_etag(item.version)), Some(item.updated_at)))
         .map_err(log_error(log))
 }
 
 // CreateTodoList에 #[derive(Serialize, Deserialize)]가 설정되어 있고, web::json으로 가져온다면
 // 자동으로 clone 기능 같은것이 따라오는것 같다.
-pub async fn create_todo(state: web::Data<AppState>, json: web::Json<CreateTodoList>) -> Result<impl Responder, AppError> {
+pub async fn create_todo(state: web::Data<AppState>, ctx: RequestContext, json: web::Json<CreateTodoList>) -> Result<impl Responder, AppError> {
 
     // let client: Client = state.pool.get()
     //     .await
     //     .map_err(AppError::db_error)?;
 
     let log = state.log.new(o!("handler" => "create_todo"));
-    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
 
-    let result = db::create_todo(&client, json.title.clone()).await;
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::create_todo(&mut client, &ctx, json.title.clone()).await;
 
     // match result {
     //     Ok(todo) => HttpResponse::Ok().json(todo),
@@ -140,16 +274,26 @@ pub async fn create_todo(state: web::Data<AppState>, json: web::Json<CreateTodoL
         .map_err(log_error(log))
 }
 
-pub async fn check_itme(state: web::Data<AppState>, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+pub async fn check_itme(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
 
     // let client: Client = state.pool.get()
     //     .await
     //     .map_err(AppError::db_error)?;
 
     let log = state.log.new(o!("handler" => "check_item"));
-    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
 
-    let result = db::check_item(&client, path.0, path.1).await;
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::check_item(&mut client, &ctx, path.0, path.1, expected_version).await;
+
+    // ITEMS.ALLOW_BLOCKED_CHECK 이면 막혀 있어도 체크되므로, 남은 blocker를 Warning 헤더로 알려준다
+    let blockers = match result {
+        Ok(Some(_)) if db::allow_blocked_check() => db::unchecked_blockers(&client, path.1).await.map_err(log_error(log.clone()))?,
+        _ => vec![]
+    };
 
     // match result {
     //     Ok(()) => HttpResponse::Ok().json(ResultResponse{success: true}),
@@ -157,6 +301,1211 @@ pub async fn check_itme(state: web::Data<AppState>, path: web::Path<(i32, i32)>)
     //     Err(_) => HttpResponse::InternalServerError().into()
     // }
     result
-        .map(|updated| HttpResponse::Ok().json(ResultResponse{success: updated}))
+        .map(|updated| match updated {
+            // 바뀐 버전을 ETag로 돌려줘서 다음 요청의 If-Match에 바로 쓸 수 있게 함
+            Some(version) => {
+                let mut response = HttpResponse::Ok();
+                response.insert_header(ETag(version_etag(version)));
+                if !blockers.is_empty() {
+                    response.insert_header(("Warning", format!("199 - \"{}\"", AppError::blocked(&blockers).message())));
+                }
+                response.json(ResultResponse{success: true})
+            },
+            None => HttpResponse::Ok().json(ResultResponse{success: false})
+        })
+        .map_err(log_error(log))
+}
+
+// 마감 시간과 알림 시간을 바꾼다. check_itme과 같이 If-Match가 필요
+pub async fn schedule_item(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemSchedule>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "schedule_item"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_schedule(&mut client, &ctx, path.0, path.1, &json, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+// 반복 규칙을 정하거나(rule) 지운다(null). If-Match 필요
+pub async fn set_item_recurrence(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemRecurrence>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_recurrence"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_recurrence(&mut client, &ctx, path.0, path.1, &json, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+// 순서 바꾸기 (drag and drop). 순서만 바뀌므로 If-Match는 보내지 않아도 된다
+pub async fn move_item(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<MoveRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "move_item"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = optional_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::move_item(&mut client, &ctx, path.0, path.1, &json, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+pub async fn move_todo(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<MoveRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "move_todo"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = optional_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::move_todo(&mut client, &ctx, path.0, &json, expected_version).await;
+
+    result
+        .map(|todo| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(todo.version)))
+            .json(todo))
+        .map_err(log_error(log))
+}
+
+// 다른 list로 옮기기. 두 list를 모두 바꾸므로 두 list 모두에 쓸 수 있어야 한다
+pub async fn move_items(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<TransferItems>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "move_items"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+    db::check_list_access(&client, &ctx, json.to_list_id, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::move_items(&mut client, &ctx, path.0, &json).await;
+
+    result
+        .map(|items| HttpResponse::Ok().json(items))
+        .map_err(log_error(log))
+}
+
+pub async fn copy_items(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<TransferItems>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "copy_items"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+    db::check_list_access(&client, &ctx, json.to_list_id, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::copy_items(&mut client, &ctx, path.0, &json).await;
+
+    result
+        .map(|items| HttpResponse::Created().json(items))
+        .map_err(log_error(log))
+}
+
+pub async fn duplicate_todo(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<DuplicateTodo>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "duplicate_todo"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::duplicate_todo(&mut client, &ctx, path.0, &json).await;
+
+    result
+        .map(|todo| HttpResponse::Created().json(todo))
+        .map_err(log_error(log))
+}
+
+// 템플릿으로 지정하거나 해제한다. If-Match 필요
+pub async fn set_todo_template(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<TodoTemplate>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_todo_template"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_todo_template(&mut client, &ctx, path.0, json.is_template, expected_version).await;
+
+    result
+        .map(|todo| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(todo.version)))
+            .json(todo))
+        .map_err(log_error(log))
+}
+
+pub async fn get_workflow(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_workflow"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    let result = db::get_workflow(&client, path.0).await;
+
+    result
+        .map(|statuses| HttpResponse::Ok().json(statuses))
+        .map_err(log_error(log))
+}
+
+pub async fn set_workflow(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<Workflow>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_workflow"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_workflow(&mut client, &ctx, path.0, &json.statuses).await;
+
+    result
+        .map(|statuses| HttpResponse::Ok().json(statuses))
+        .map_err(log_error(log))
+}
+
+// list의 멤버. 보는 것은 list를 볼 수 있으면, 바꾸는 것은 owner만 (멤버가 없는 list는 확인된 사용자 누구나)
+// 자신을 빼는 것(list에서 나가기)은 owner가 아니어도 된다
+pub async fn get_members(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_members"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_members(&client, path.0).await;
+
+    result
+        .map(|members| HttpResponse::Ok().json(members))
+        .map_err(log_error(log))
+}
+
+pub async fn set_member(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, String)>, json: web::Json<SetMemberRole>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_member"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let member = SetMemberRole::member(&path.1).map_err(log_error(log.clone()))?;
+    let role = json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Manage).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_member(&mut client, &ctx, path.0, member, role).await;
+
+    result
+        .map(|members| HttpResponse::Ok().json(members))
+        .map_err(log_error(log))
+}
+
+pub async fn remove_member(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, String)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "remove_member"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let member = SetMemberRole::member(&path.1).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    let access = if member == ctx.actor { ListAccess::Read } else { ListAccess::Manage };
+    db::check_list_access(&client, &ctx, path.0, access).await.map_err(log_error(log.clone()))?;
+
+    let result = db::remove_member(&mut client, &ctx, path.0, member).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+// 상태마다 열 하나. 열에는 WIP 제한(wip_limit)과 지금 item 수(count)가 함께 있다
+pub async fn get_board(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_board"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_board(&client, path.0).await;
+
+    result
+        .and_then(|board| {
+            let last_modified = board.columns.iter().flat_map(|column| column.items.iter()).map(|item| item.updated_at).max();
+            cached_json(&req, &board, None, last_modified)
+        })
+        .map_err(log_error(log))
+}
+
+// 상태 바꾸기 (보드에서 다른 열로). WIP 제한을 넘으면 409. If-Match 필요
+pub async fn set_item_status(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemStatus>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_status"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_status(&mut client, &ctx, path.0, path.1, &json.status, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+pub async fn set_item_priority(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemPriority>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_priority"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_priority(&mut client, &ctx, path.0, path.1, json.priority, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+// notes를 바꾼다. 새로 @이름 으로 부른 사용자에게 알림이 간다. If-Match 필요
+pub async fn set_item_notes(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemNotes>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_notes"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let notes = json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_notes(&mut client, &ctx, path.0, path.1, notes, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+// 하위 item으로 옮기거나(parent_id) 최상위로 꺼낸다(null). If-Match 필요
+pub async fn set_item_parent(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<ItemParent>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_parent"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let expected_version = required_version(&req).map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_parent(&mut client, &ctx, path.0, path.1, json.parent_id, expected_version).await;
+
+    result
+        .map(|item| HttpResponse::Ok()
+            .insert_header(ETag(version_etag(item.version)))
+            .json(item))
+        .map_err(log_error(log))
+}
+
+// 반복 item의 완료 기록
+pub async fn get_item_history(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_item_history"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_item_history(&client, path.0, path.1).await;
+
+    result
+        .map(|completions| HttpResponse::Ok().json(completions))
+        .map_err(log_error(log))
+}
+
+// 모든 list에서 마감 시간이 있는 item을 날짜별로
+pub async fn get_agenda(state: web::Data<AppState>, ctx: RequestContext, query: web::Query<AgendaQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_agenda"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_agenda(&client, &query, ctx.reader()).await;
+
+    result
+        .map(|agenda| HttpResponse::Ok().json(agenda))
+        .map_err(log_error(log))
+}
+
+// 파일로 내려받을 수 있도록 Content-Disposition을 붙인다
+pub async fn export_todo(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, query: web::Query<ExportQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "export_todo"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let list = db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    let items = db::get_itmes(&client, path.0).await.map_err(log_error(log))?;
+
+    let format = query.format;
+    let filename = format!("todo-{}.{}", list.id, format.extension());
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(filename))
+        .body(formats::export(format, &list, &items)))
+}
+
+// body는 파일 내용 그대로. 잘못된 줄이 있으면 아무것도 만들지 않고 줄 번호와 이유를 돌려준다
+pub async fn import_todo(state: web::Data<AppState>, ctx: RequestContext, query: web::Query<ImportQuery>, body: String) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "import_todo"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    let imported = match formats::parse(query.format, &body) {
+        Ok(imported) => imported,
+        Err(errors) => return Ok(HttpResponse::BadRequest().json(ImportErrorResponse {
+            error: format!("Could not import, {} line(s) have errors", errors.len()),
+            errors
+        }))
+    };
+
+    let title = query.title.clone()
+        .or(imported.title)
+        .ok_or_else(|| AppError::validation("title is required when the file has no title"))
+        .and_then(|title| db::valid_title(&title).map(str::to_string))
+        .map_err(log_error(log.clone()))?;
+
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::import_todo(&mut client, &ctx, &title, &imported.items).await;
+
+    result
+        .map(|todo| HttpResponse::Created().json(todo))
+        .map_err(log_error(log))
+}
+
+pub async fn delete_todo(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_todo"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Manage).await.map_err(log_error(log.clone()))?;
+
+    let result = db::delete_todo(&mut client, &ctx, path.0).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+// 여러 item을 한 번에 추가/수정/체크/삭제한다.
+// 결과는 연산마다 상태 코드와 함께 돌려주고, atomic 모드에서 실패하면 실패한 연산의 상태 코드로 응답
+pub async fn batch_items(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<BatchRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "batch_items"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::apply_batch(&mut client, &ctx, path.0, &json).await;
+
+    result
+        .map(|response| {
+            for failed in response.results.iter().filter(|result| result.status >= 500) {
+                error!(log, "Batch operation failed"; "index" => failed.index, "cause" => failed.cause.clone());
+            }
+
+            let status = match response.results.last() {
+                Some(failed) if !response.committed => StatusCode::from_u16(failed.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                _ => StatusCode::OK
+            };
+            HttpResponse::build(status).json(response)
+        })
+        .map_err(log_error(log))
+}
+
+pub async fn delete_item(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_item"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::delete_item(&mut client, &ctx, path.0, path.1).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
         .map_err(log_error(log))
+}
+
+pub async fn get_trash(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_trash"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_trash(&client, ctx.reader()).await;
+
+    result
+        .map(|trash| HttpResponse::Ok().json(trash))
+        .map_err(log_error(log))
+}
+
+pub async fn restore_from_trash(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(String, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "restore_from_trash"));
+
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let (kind, id) = path.into_inner();
+
+    // 복구된 list/item을 그대로 돌려준다
+    let result = match kind.as_str() {
+        "lists" => db::restore_todo(&mut client, &ctx, id).await.map(|todo| HttpResponse::Ok().json(todo)),
+        "items" => db::restore_item(&mut client, &ctx, id).await.map(|item| HttpResponse::Ok().json(item)),
+        _ => Err(AppError::not_found())
+    };
+
+    result.map_err(log_error(log))
+}
+
+// 요청한 사용자가 볼 수 있는 list와 item만 찾는다 (휴지통에 있는 것은 제외)
+pub async fn search(state: web::Data<AppState>, ctx: RequestContext, query: web::Query<SearchQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "search"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::search(&client, &query, ctx.reader()).await;
+
+    result
+        .map(|results| HttpResponse::Ok().json(results))
+        .map_err(log_error(log))
+}
+
+pub async fn get_audit_log(state: web::Data<AppState>, ctx: RequestContext, query: web::Query<AuditQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_audit_log"));
+    ctx.require_admin().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_audit_log(&client, &query).await;
+
+    result
+        .map(|entries| HttpResponse::Ok().json(entries))
+        .map_err(log_error(log))
+}
+
+pub async fn item_events(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "item_events"));
+    let list_id = path.0;
+
+    // 없는 list면 404. 스트림이 열려 있는 동안 커넥션을 잡고 있지 않도록 확인만 하고 바로 돌려준다
+    {
+        let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+        db::check_list_access(&client, &ctx, list_id, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+        db::get_todo(&client, list_id).await.map_err(log_error(log))?;
+    }
+
+    let last_event_id = req.headers()
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(item_event_stream(state.events.clone(), list_id, last_event_id, state.events_heartbeat)))
+}
+
+// WebSocket 연결. 메시지 형식은 ws.rs 참고
+pub async fn list_socket(req: HttpRequest, body: web::Payload, state: web::Data<AppState>, path: web::Path<(i32,)>) -> Result<HttpResponse, actix_web::Error> {
+
+    let log = state.log.new(o!("handler" => "list_socket"));
+    let list_id = path.0;
+
+    // 연결할 때 한 번 사용자를 확인하고, 이후의 모든 메시지는 이 사용자로 처리한다
+    // 브라우저는 헤더를 붙일 수 없으므로 Sec-WebSocket-Protocol: bearer, <token> 이나 ?access_token= 도 받는다
+    let (ctx, credential) = RequestContext::authenticate(&req, true);
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    {
+        let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+        db::check_list_access(&client, &ctx, list_id, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+        db::get_todo(&client, list_id).await.map_err(log_error(log))?;
+    }
+
+    let (mut response, session, stream) = actix_ws::handle(&req, body)?;
+    // 하위 프로토콜을 보냈으면 그 중 하나를 골라서 돌려줘야 브라우저가 연결을 받아들인다
+    if credential == Some(Credential::SocketProtocol) {
+        response.headers_mut().insert(header::SEC_WEBSOCKET_PROTOCOL, header::HeaderValue::from_static(auth::SOCKET_PROTOCOL));
+    }
+    let stream = stream.max_frame_size(64 * 1024);
+
+    // 연결은 응답을 돌려준 뒤에도 계속 유지되어야 하므로 따로 띄운다
+    actix_rt::spawn(crate::ws::run_session(state, ctx, list_id, session, stream));
+
+    Ok(response)
+}
+
+// 웹훅 구독, 캘린더 피드처럼 사용자가 만든 것들.
+// 관리자는 전부, 그 외에는 자신이 만든 것에만 접근할 수 있다
+// 태그는 모두 요청한 사용자의 것. 관리자도 다른 사용자의 태그는 보지 않는다
+pub async fn get_tags(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_tags"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_tags(&client, &ctx.actor).await;
+
+    result
+        .map(|tags| HttpResponse::Ok().json(tags))
+        .map_err(log_error(log))
+}
+
+pub async fn create_tag(state: web::Data<AppState>, ctx: RequestContext, json: web::Json<TagRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "create_tag"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let (name, color) = json.validate().map_err(log_error(log.clone()))?;
+    let name = name.ok_or_else(|| log_error(log.clone())(AppError::validation("name is required")))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::create_tag(&mut client, &ctx, &name, color.as_deref()).await;
+
+    result
+        .map(|tag| HttpResponse::Created().json(tag))
+        .map_err(log_error(log))
+}
+
+pub async fn update_tag(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<TagRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "update_tag"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let (name, color) = json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::update_tag(&mut client, &ctx, path.0, name.as_deref(), color.as_deref()).await;
+
+    result
+        .map(|tag| HttpResponse::Ok().json(tag))
+        .map_err(log_error(log))
+}
+
+pub async fn delete_tag(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_tag"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::delete_tag(&mut client, &ctx, path.0).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+pub async fn get_item_tags(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_item_tags"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    db::get_item(&client, path.0, path.1).await.map_err(log_error(log.clone()))?;
+    let result = db::get_target_tags(&client, TagTarget::Item, path.1, &ctx.actor).await;
+
+    result
+        .map(|tags| HttpResponse::Ok().json(tags))
+        .map_err(log_error(log))
+}
+
+pub async fn set_item_tags(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<SetTags>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_item_tags"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let names = json.names().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_item_tags(&mut client, &ctx, path.0, path.1, &names).await;
+
+    result
+        .map(|tags| HttpResponse::Ok().json(tags))
+        .map_err(log_error(log))
+}
+
+pub async fn get_list_tags(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_list_tags"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    db::get_todo(&client, path.0).await.map_err(log_error(log.clone()))?;
+    let result = db::get_target_tags(&client, TagTarget::List, path.0, &ctx.actor).await;
+
+    result
+        .map(|tags| HttpResponse::Ok().json(tags))
+        .map_err(log_error(log))
+}
+
+pub async fn set_list_tags(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, json: web::Json<SetTags>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_list_tags"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let names = json.names().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_list_tags(&mut client, &ctx, path.0, &names).await;
+
+    result
+        .map(|tags| HttpResponse::Ok().json(tags))
+        .map_err(log_error(log))
+}
+
+// 의존 관계. 읽기는 누구나, 바꾸려면 인증이 필요하다
+pub async fn get_dependencies(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_dependencies"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_dependencies(&client, path.0, path.1, ctx.reader()).await;
+
+    result
+        .map(|dependencies| HttpResponse::Ok().json(dependencies))
+        .map_err(log_error(log))
+}
+
+pub async fn add_dependency(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<AddDependency>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "add_dependency"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::add_dependency(&mut client, &ctx, path.0, path.1, json.blocked_by).await;
+
+    result
+        .map(|dependencies| HttpResponse::Ok().json(dependencies))
+        .map_err(log_error(log))
+}
+
+pub async fn remove_dependency(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "remove_dependency"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::remove_dependency(&mut client, &ctx, path.0, path.1, path.2).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+// list의 의존 관계 그래프. 다른 list의 item도 이어져 있으면 node에 들어간다
+pub async fn get_dependency_graph(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_dependency_graph"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_dependency_graph(&client, path.0, ctx.reader()).await;
+
+    result
+        .map(|graph| HttpResponse::Ok().json(graph))
+        .map_err(log_error(log))
+}
+
+// 담당자. 읽기는 누구나, 바꾸려면 인증이 필요하다
+pub async fn get_assignees(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_assignees"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    db::get_item(&client, path.0, path.1).await.map_err(log_error(log.clone()))?;
+    let result = db::get_assignees(&client, path.1).await;
+
+    result
+        .map(|assignees| HttpResponse::Ok().json(assignees))
+        .map_err(log_error(log))
+}
+
+pub async fn set_assignees(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<SetAssignees>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "set_assignees"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let names = json.names().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::set_assignees(&mut client, &ctx, path.0, path.1, &names).await;
+
+    result
+        .map(|assignees| HttpResponse::Ok().json(assignees))
+        .map_err(log_error(log))
+}
+
+// 댓글. list를 볼 수 있으면 읽을 수 있고, 쓰려면 list에 쓸 수 있어야 한다 (db::check_list_access)
+// 수정과 삭제는 쓴 사람이나 관리자만 (403)
+pub async fn get_comments(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_comments"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_comments(&client, path.0, path.1).await;
+
+    result
+        .map(|comments| HttpResponse::Ok().json(CommentNode::threads(comments)))
+        .map_err(log_error(log))
+}
+
+pub async fn create_comment(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, json: web::Json<CommentRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "create_comment"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let body = json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::create_comment(&mut client, &ctx, path.0, path.1, body, json.parent_id).await;
+
+    result
+        .map(|comment| HttpResponse::Created().json(comment))
+        .map_err(log_error(log))
+}
+
+pub async fn update_comment(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32, i32)>, json: web::Json<CommentRequest>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "update_comment"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let body = json.validate().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::update_comment(&mut client, &ctx, path.0, path.1, path.2, body).await;
+
+    result
+        .map(|comment| HttpResponse::Ok().json(comment))
+        .map_err(log_error(log))
+}
+
+pub async fn delete_comment(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_comment"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::delete_comment(&mut client, &ctx, path.0, path.1, path.2).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+pub async fn get_attachments(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_attachments"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+
+    let result = db::get_attachments(&client, path.0, path.1).await;
+
+    result
+        .map(|attachments| HttpResponse::Ok().json(attachments))
+        .map_err(log_error(log))
+}
+
+// 한 번에 올릴 수 있는 파일 수
+const MAX_UPLOAD_FILES: usize = 10;
+
+// 첨부 파일 올리기 (multipart/form-data). 파일 이름이 있는 part는 모두 첨부 파일이다
+// 파일이 ATTACHMENTS.MAX_SIZE_BYTES보다 크면 413, ATTACHMENTS.ALLOWED_TYPES에 없는 Content-Type이면 415
+// 하나라도 실패하면 이미 저장소에 올린 파일도 지우고 아무것도 만들지 않는다
+pub async fn upload_attachments(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32)>, mut payload: Multipart) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "upload_attachments"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    // 파일을 받는 동안 커넥션을 잡고 있지 않도록, item만 확인하고 바로 돌려준다
+    {
+        let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+        db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+        db::get_item(&client, path.0, path.1).await.map_err(log_error(log.clone()))?;
+    }
+
+    let mut files = vec![];
+    let result = match receive_attachments(&state, &mut payload, &mut files).await {
+        Ok(()) if files.is_empty() => Err(AppError::validation("No file in the request, send files as multipart/form-data parts with a filename")),
+        Ok(()) => match get_client(state.pool.clone(), log.clone()).await {
+            Ok(mut client) => db::create_attachments(&mut client, &ctx, path.0, path.1, &files).await,
+            Err(err) => Err(err)
+        },
+        Err(err) => Err(err)
+    };
+
+    if result.is_err() {
+        for file in &files {
+            if let Err(err) = state.storage.delete(&file.storage_key).await {
+                error!(log, "Error deleting uploaded blob"; "key" => file.storage_key.clone(), "cause" => err.to_string());
+            }
+        }
+    }
+
+    result
+        .map(|attachments| HttpResponse::Created().json(attachments))
+        .map_err(log_error(log))
+}
+
+// part를 하나씩 받으면서 크기를 세고, 다 받으면 저장소에 올린다. 올린 파일은 files에 쌓는다
+async fn receive_attachments(state: &AppState, payload: &mut Multipart, files: &mut Vec<NewAttachment>) -> Result<(), AppError> {
+    let limits = &state.attachment_limits;
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|err| AppError::validation(format!("Invalid multipart body: {}", err)))?;
+
+        // 파일이 아닌 part(일반 form 값)는 건너뛴다
+        let Some(filename) = field.content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(attachments::clean_filename) else {
+            continue;
+        };
+
+        if files.len() == MAX_UPLOAD_FILES {
+            return Err(AppError::validation(format!("At most {} files can be uploaded at once", MAX_UPLOAD_FILES)));
+        }
+
+        let content_type = field.content_type()
+            .map(|content_type| content_type.essence_str().to_lowercase())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        if !attachments::is_allowed(&content_type, &limits.allowed_types) {
+            return Err(AppError::unsupported_media_type(format!("{} files are not allowed", content_type)));
+        }
+
+        let mut data = BytesMut::new();
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|err| AppError::validation(format!("Invalid multipart body: {}", err)))?;
+            if (data.len() + chunk.len()) as u64 > limits.max_size_bytes {
+                return Err(AppError::payload_too_large(format!("{} is larger than {} bytes", filename, limits.max_size_bytes)));
+            }
+            hasher.update(&chunk);
+            data.extend_from_slice(&chunk);
+        }
+
+        let storage_key = attachments::storage_key();
+        let size = data.len() as i64;
+        state.storage.put(&storage_key, data.freeze(), &content_type).await.map_err(AppError::storage_error)?;
+
+        files.push(NewAttachment {
+            storage_key,
+            filename,
+            content_type,
+            size,
+            sha256: hex::encode(hasher.finalize())
+        });
+    }
+
+    Ok(())
+}
+
+// 첨부 파일 내려받기. Range(ex. bytes=0-1023)를 보내면 그 부분만 206으로 보낸다
+// 첨부 파일은 바뀌지 않으므로 ETag는 내용의 sha256
+pub async fn download_attachment(req: HttpRequest, state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32, i32)>) -> Result<HttpResponse, AppError> {
+
+    let log = state.log.new(o!("handler" => "download_attachment"));
+    let attachment = {
+        let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+        db::check_list_access(&client, &ctx, path.0, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+        db::get_attachment(&client, path.0, path.1, path.2).await.map_err(log_error(log.clone()))?
+    };
+
+    let size = attachment.size as u64;
+    let etag = EntityTag::new_strong(attachment.sha256.clone());
+
+    // If-Range가 ETag와 다르면 Range를 무시하고 전체를 보낸다
+    let if_range = req.headers().get(header::IF_RANGE).and_then(|value| value.to_str().ok());
+    let range = match if_range {
+        Some(if_range) if if_range != etag.to_string() => ByteRange::Full,
+        _ => attachments::parse_range(req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()), size)
+    };
+
+    let range = match range {
+        ByteRange::Full => None,
+        ByteRange::Partial(start, end) => Some((start, end)),
+        ByteRange::Unsatisfiable => return Ok(HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish())
+    };
+
+    let stream = state.storage.get(&attachment.storage_key, range)
+        .await
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => AppError::not_found(),
+            _ => AppError::storage_error(err)
+        })
+        .map_err(log_error(log))?;
+
+    let mut response = match range {
+        Some((start, end)) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size)));
+            response.no_chunking(end - start + 1);
+            response
+        },
+        None => {
+            let mut response = HttpResponse::Ok();
+            response.no_chunking(size);
+            response
+        }
+    };
+
+    Ok(response
+        .content_type(attachment.content_type.as_str())
+        .insert_header(attachments::content_disposition(&attachment.filename))
+        .insert_header(ETag(etag))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        // 올린 파일을 브라우저가 html 등으로 해석해서 실행하지 않도록
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .streaming(stream))
+}
+
+pub async fn delete_attachment(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i32, i32)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_attachment"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+    db::check_list_access(&client, &ctx, path.0, ListAccess::Write).await.map_err(log_error(log.clone()))?;
+
+    let result = db::delete_attachment(&mut client, &ctx, path.0, path.1, path.2).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+// 요청한 사용자에게 온 알림
+pub async fn get_notifications(state: web::Data<AppState>, ctx: RequestContext, query: web::Query<NotificationsQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_notifications"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_notifications(&client, &ctx.actor, &query).await;
+
+    result
+        .map(|notifications| HttpResponse::Ok().json(notifications))
+        .map_err(log_error(log))
+}
+
+pub async fn read_notification(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i64,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "read_notification"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::read_notification(&client, &ctx.actor, path.0).await;
+
+    result
+        .map(|updated| HttpResponse::Ok().json(ResultResponse{success: updated}))
+        .map_err(log_error(log))
+}
+
+pub async fn read_notifications(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "read_notifications"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::read_notifications(&client, &ctx.actor).await;
+
+    result
+        .map(|read| HttpResponse::Ok().json(ReadNotifications{read}))
+        .map_err(log_error(log))
+}
+
+fn owner_scope(ctx: &RequestContext) -> Option<&str> {
+    if ctx.is_admin {
+        None
+    } else {
+        Some(ctx.actor.as_str())
+    }
+}
+
+pub async fn create_webhook(state: web::Data<AppState>, ctx: RequestContext, json: web::Json<CreateWebhook>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "create_webhook"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    state.webhook_hosts.validate_url(&json.url).await.map_err(log_error(log.clone()))?;
+    webhooks::validate_events(&json.events).map_err(log_error(log.clone()))?;
+
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    if let Some(list_id) = json.list_id {
+        db::check_list_access(&client, &ctx, list_id, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+        db::get_todo(&client, list_id).await.map_err(log_error(log.clone()))?;
+    }
+
+    let secret = json.secret.clone().unwrap_or_else(webhooks::generate_secret);
+    let result = db::create_webhook(&mut client, &ctx, &json, &secret).await;
+
+    result
+        .map(|webhook| HttpResponse::Ok().json(CreatedWebhook { webhook, secret }))
+        .map_err(log_error(log))
+}
+
+pub async fn get_webhooks(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_webhooks"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_webhooks(&client, owner_scope(&ctx)).await;
+
+    result
+        .map(|webhooks| HttpResponse::Ok().json(webhooks))
+        .map_err(log_error(log))
+}
+
+pub async fn delete_webhook(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_webhook"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::delete_webhook(&mut client, &ctx, path.0, owner_scope(&ctx)).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+pub async fn get_webhook_deliveries(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>, query: web::Query<DeliveriesQuery>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_webhook_deliveries"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_webhook_deliveries(&client, path.0, owner_scope(&ctx), &query).await;
+
+    result
+        .map(|deliveries| HttpResponse::Ok().json(deliveries))
+        .map_err(log_error(log))
+}
+
+pub async fn retry_webhook_delivery(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32, i64)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "retry_webhook_delivery"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::retry_webhook_delivery(&client, path.0, path.1, owner_scope(&ctx)).await;
+
+    result
+        .map(|delivery| HttpResponse::Ok().json(delivery))
+        .map_err(log_error(log))
+}
+
+pub async fn create_calendar_feed(state: web::Data<AppState>, ctx: RequestContext, json: web::Json<CreateCalendarFeed>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "create_calendar_feed"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    if let Some(list_id) = json.list_id {
+        db::check_list_access(&client, &ctx, list_id, ListAccess::Read).await.map_err(log_error(log.clone()))?;
+        db::get_todo(&client, list_id).await.map_err(log_error(log.clone()))?;
+    }
+
+    // 주소를 아는 사람은 누구나 볼 수 있으므로 추측할 수 없는 값이어야 한다
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let result = db::create_calendar_feed(&mut client, &ctx, json.list_id, &token).await;
+
+    result
+        .map(|feed| HttpResponse::Ok().json(feed))
+        .map_err(log_error(log))
+}
+
+pub async fn get_calendar_feeds(state: web::Data<AppState>, ctx: RequestContext) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "get_calendar_feeds"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::get_calendar_feeds(&client, owner_scope(&ctx)).await;
+
+    result
+        .map(|feeds| HttpResponse::Ok().json(feeds))
+        .map_err(log_error(log))
+}
+
+pub async fn delete_calendar_feed(state: web::Data<AppState>, ctx: RequestContext, path: web::Path<(i32,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "delete_calendar_feed"));
+    ctx.require_user().map_err(log_error(log.clone()))?;
+    let mut client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let result = db::delete_calendar_feed(&mut client, &ctx, path.0, owner_scope(&ctx)).await;
+
+    result
+        .map(|deleted| HttpResponse::Ok().json(ResultResponse{success: deleted}))
+        .map_err(log_error(log))
+}
+
+// GET /calendar/{token}.ics. 캘린더 앱이 주기적으로 가져간다 (인증 헤더 없이 feed token으로만)
+pub async fn calendar_feed(state: web::Data<AppState>, path: web::Path<(String,)>) -> Result<impl Responder, AppError> {
+
+    let log = state.log.new(o!("handler" => "calendar_feed"));
+    let client: Client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let feed = db::get_calendar_feed(&client, &path.0).await.map_err(log_error(log.clone()))?;
+    // 피드를 만든 사용자가 지금 볼 수 있는 list만. 멤버에서 빠지면 피드에서도 빠진다
+    let reader = (!state.auth.admins.contains(&feed.owner)).then_some(feed.owner.as_str());
+    let lists = db::get_feed_lists(&client, feed.list_id, reader).await.map_err(log_error(log.clone()))?;
+    let list_ids = lists.iter().map(|list| list.id).collect::<Vec<i32>>();
+    let items = db::get_items_for_lists(&client, &list_ids).await.map_err(log_error(log))?;
+
+    let calendar = lists.iter()
+        .map(|list| (list, items.iter().filter(|item| item.list_id == list.id).collect()))
+        .collect::<Vec<_>>();
+    let name = match (feed.list_id, lists.first()) {
+        (Some(_), Some(list)) => list.title.clone(),
+        _ => "Todos".to_string()
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(ical::calendar(&name, &calendar)))
 }
\ No newline at end of file
@@ -0,0 +1,354 @@
+use crate::formats::{ImportedItem, ImportedList, ParseError};
+use crate::models::{TodoItem, TodoList};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+// iCalendar (RFC 5545). 캘린더 앱에서 item을 할 일(VTODO)로 보여주기 위한 형식
+// list 하나가 캘린더 하나(.ics 내보내기), 피드는 여러 list를 한 캘린더에 담는다
+const PRODID: &str = "-//actix-todo//todo//KO";
+
+// UID는 서버가 바뀌어도 같은 item이면 같아야 캘린더 앱이 중복으로 만들지 않는다
+// CalDAV로 만들어진 item은 클라이언트가 정한 UID(ical_uid)를 그대로 돌려준다
+pub fn item_uid(item_id: i32) -> String {
+    format!("todo-item-{}@actix-todo", item_id)
+}
+
+pub fn calendar(name: &str, lists: &[(&TodoList, Vec<&TodoItem>)]) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(name))
+    ];
+
+    for (list, items) in lists {
+        for item in items {
+            lines.extend(vtodo(list, item));
+        }
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+// STATUS는 checked에서 가져온다. 완료된 시간은 따로 저장하지 않으므로 마지막으로 바뀐 시간을 COMPLETED로 씀
+// SEQUENCE는 바뀔 때마다 올라가야 하므로 version을 그대로 사용 (version은 1부터)
+pub fn vtodo(list: &TodoList, item: &TodoItem) -> Vec<String> {
+    let mut lines = vec![
+        "BEGIN:VTODO".to_string(),
+        format!("UID:{}", item.ical_uid.clone().unwrap_or_else(|| item_uid(item.id))),
+        format!("DTSTAMP:{}", timestamp(&item.updated_at)),
+        format!("CREATED:{}", timestamp(&item.created_at)),
+        format!("LAST-MODIFIED:{}", timestamp(&item.updated_at)),
+        format!("SEQUENCE:{}", item.version - 1),
+        format!("SUMMARY:{}", escape(&item.title)),
+        format!("CATEGORIES:{}", escape(&list.title))
+    ];
+
+    if let Some(due_at) = &item.due_at {
+        lines.push(format!("DUE:{}", timestamp(due_at)));
+    }
+
+    if item.checked {
+        lines.push("STATUS:COMPLETED".to_string());
+        lines.push(format!("COMPLETED:{}", timestamp(&item.updated_at)));
+        lines.push("PERCENT-COMPLETE:100".to_string());
+    } else {
+        lines.push("STATUS:NEEDS-ACTION".to_string());
+    }
+
+    lines.push("END:VTODO".to_string());
+    lines
+}
+
+fn timestamp(time: &DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// 값 안의 \ ; , 줄바꿈은 escape 해야 한다
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+fn unescape(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\')
+        }
+    }
+
+    text
+}
+
+// 한 줄은 75바이트를 넘으면 안 되므로 나눠서 다음 줄을 공백으로 시작한다 (한글이 잘리지 않도록 글자 단위로)
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 8);
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+
+    folded.push_str("\r\n");
+    folded
+}
+
+// 공백으로 시작하는 줄을 앞 줄에 붙인다. (시작 줄 번호, 줄) 목록
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continued), Some((_, last))) => last.push_str(continued),
+            _ => lines.push((index + 1, line.to_string()))
+        }
+    }
+
+    lines
+}
+
+// .ics 안의 VTODO 하나
+pub struct CalendarTodo {
+    pub uid: Option<String>,
+    pub title: String,
+    pub checked: bool,
+    // DUE 시각과 TZID. 시간대가 없으면(Z, 날짜만, floating) UTC로 본다
+    pub due_at: Option<NaiveDateTime>,
+    pub due_tz: Option<String>
+}
+
+// .ics 파일을 새 list로 가져올 때 (POST /todos/import?format=ics)
+pub fn parse(text: &str) -> Result<ImportedList, Vec<ParseError>> {
+    let (title, todos) = parse_calendar(text)?;
+
+    Ok(ImportedList {
+        title,
+        items: todos.into_iter().map(|todo| ImportedItem { title: todo.title, checked: todo.checked }).collect()
+    })
+}
+
+// CalDAV의 PUT처럼 리소스 하나에 VTODO가 정확히 하나 있어야 하는 경우
+pub fn parse_single(text: &str) -> Result<CalendarTodo, Vec<ParseError>> {
+    let (_, mut todos) = parse_calendar(text)?;
+
+    match todos.len() {
+        1 => Ok(todos.remove(0)),
+        count => Err(vec![ParseError::new(1, format!("Expected exactly one VTODO, found {}", count))])
+    }
+}
+
+// VTODO만 item으로 가져온다. VEVENT 등 다른 컴포넌트는 무시
+// STATUS:COMPLETED 이거나 COMPLETED 날짜가 있으면 체크된 item
+// 반환값은 (X-WR-CALNAME, VTODO 목록)
+fn parse_calendar(text: &str) -> Result<(Option<String>, Vec<CalendarTodo>), Vec<ParseError>> {
+    let mut title = None;
+    let mut todos = vec![];
+    let mut errors = vec![];
+    let mut in_calendar = false;
+    // 지금 읽고 있는 VTODO (시작 줄, 읽은 값). title은 SUMMARY가 나오기 전까지 비어있다
+    let mut todo: Option<(usize, CalendarTodo)> = None;
+
+    for (number, line) in unfold(text) {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name, value),
+            None => {
+                errors.push(ParseError::new(number, "Expected a \"NAME:value\" line"));
+                continue;
+            }
+        };
+        // SUMMARY;LANGUAGE=ko:... 처럼 이름 뒤에 붙는 파라미터는 DUE의 TZID만 읽고 무시
+        let tzid = name.split(';').skip(1)
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("TZID"))
+            .map(|(_, tz)| tz.trim_matches('"'));
+        let name = name.split(';').next().unwrap_or_default().to_ascii_uppercase();
+        let value = value.trim_end();
+
+        match (name.as_str(), value.to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VCALENDAR") => in_calendar = true,
+            _ if !in_calendar => {
+                errors.push(ParseError::new(number, "Expected BEGIN:VCALENDAR"));
+                break;
+            },
+            ("BEGIN", "VTODO") => {
+                if let Some((start, _)) = todo {
+                    errors.push(ParseError::new(start, "VTODO is not closed with END:VTODO"));
+                }
+                todo = Some((number, CalendarTodo { uid: None, title: String::new(), checked: false, due_at: None, due_tz: None }));
+            },
+            ("END", "VTODO") => match todo.take() {
+                Some((start, current)) if current.title.is_empty() => errors.push(ParseError::new(start, "VTODO has no SUMMARY")),
+                Some((_, current)) => todos.push(current),
+                None => errors.push(ParseError::new(number, "END:VTODO without BEGIN:VTODO"))
+            },
+            ("END", "VCALENDAR") => in_calendar = false,
+            ("X-WR-CALNAME", _) if title.is_none() => {
+                title = Some(unescape(value).trim().to_string()).filter(|title| !title.is_empty());
+            },
+            ("UID", _) => if let Some((_, current)) = todo.as_mut() {
+                current.uid = Some(value.to_string()).filter(|uid| !uid.is_empty() && uid.len() <= 255);
+            },
+            ("SUMMARY", _) => if let Some((_, current)) = todo.as_mut() {
+                let summary = unescape(value);
+                let summary = summary.trim();
+                if summary.is_empty() || summary.chars().count() > 150 {
+                    errors.push(ParseError::new(number, "SUMMARY must be 1 to 150 characters"));
+                } else {
+                    current.title = summary.to_string();
+                }
+            },
+            ("STATUS", status) => if let Some((_, current)) = todo.as_mut() {
+                current.checked = current.checked || status == "COMPLETED";
+            },
+            ("COMPLETED", _) => if let Some((_, current)) = todo.as_mut() {
+                current.checked = true;
+            },
+            ("DUE", _) => if let Some((_, current)) = todo.as_mut() {
+                match parse_due(value, tzid) {
+                    Some((due_at, due_tz)) => {
+                        current.due_at = Some(due_at);
+                        current.due_tz = due_tz;
+                    },
+                    None => errors.push(ParseError::new(number, "DUE must be a date (20240101) or date-time (20240101T090000Z)"))
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if let Some((start, _)) = todo {
+        errors.push(ParseError::new(start, "VTODO is not closed with END:VTODO"));
+    }
+
+    if errors.is_empty() {
+        Ok((title, todos))
+    } else {
+        Err(errors)
+    }
+}
+
+// Z로 끝나면 UTC, 아니면 TZID 시간대의 시각 (없으면 UTC). 날짜만 있으면 그 날 0시
+// 시간대 변환은 저장할 때 postgres가 하므로 여기서는 (시각, 시간대 이름)만 읽는다
+fn parse_due(value: &str, tzid: Option<&str>) -> Option<(NaiveDateTime, Option<String>)> {
+    if let Ok(due_at) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Some((due_at, None));
+    }
+
+    let due_at = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y%m%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))?;
+
+    Some((due_at, tzid.filter(|tz| !tz.is_empty()).map(str::to_string)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_lines<T>(result: Result<T, Vec<ParseError>>) -> Vec<usize> {
+        match result {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => errors.iter().map(|error| error.line).collect()
+        }
+    }
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r\n\
+        X-WR-CALNAME:장보기\\, 주말\r\n\
+        BEGIN:VTODO\r\n\
+        UID:a@example.com\r\n\
+        SUMMARY;LANGUAGE=ko:우유 \r\n 2개\r\n\
+        END:VTODO\r\n\
+        BEGIN:VEVENT\r\n\
+        SUMMARY:무시\r\n\
+        END:VEVENT\r\n\
+        BEGIN:VTODO\r\n\
+        SUMMARY:계란\r\n\
+        STATUS:COMPLETED\r\n\
+        END:VTODO\r\n\
+        END:VCALENDAR\r\n";
+
+    #[test]
+    fn parse_reads_todos_and_calendar_name() {
+        let Ok(imported) = parse(CALENDAR) else { panic!("expected a calendar") };
+
+        assert_eq!(imported.title.as_deref(), Some("장보기, 주말"));
+        let items: Vec<(&str, bool)> = imported.items.iter().map(|item| (item.title.as_str(), item.checked)).collect();
+        assert_eq!(items, vec![("우유 2개", false), ("계란", true)]);
+    }
+
+    #[test]
+    fn parse_reports_broken_todos() {
+        // SUMMARY가 없는 VTODO, 닫히지 않은 VTODO
+        let text = "BEGIN:VCALENDAR\nBEGIN:VTODO\nUID:1\nEND:VTODO\nBEGIN:VTODO\nSUMMARY:a\nEND:VCALENDAR\n";
+        assert_eq!(error_lines(parse(text)), vec![2, 5]);
+        assert_eq!(error_lines(parse("SUMMARY:a\n")), vec![1]);
+    }
+
+    #[test]
+    fn parse_single_needs_exactly_one_todo() {
+        let Ok(todo) = parse_single("BEGIN:VCALENDAR\nBEGIN:VTODO\nUID:x1\nSUMMARY:a\\nb\nCOMPLETED:20240101T000000Z\nEND:VTODO\nEND:VCALENDAR\n") else {
+            panic!("expected one VTODO")
+        };
+        assert_eq!(todo.uid.as_deref(), Some("x1"));
+        assert_eq!(todo.title, "a\nb");
+        assert!(todo.checked);
+
+        assert_eq!(error_lines(parse_single(CALENDAR)), vec![1]);
+        assert_eq!(error_lines(parse_single("BEGIN:VCALENDAR\nEND:VCALENDAR\n")), vec![1]);
+    }
+
+    #[test]
+    fn parse_single_reads_due() {
+        let todo = |due: &str| parse_single(&format!("BEGIN:VCALENDAR\nBEGIN:VTODO\nSUMMARY:a\n{}\nEND:VTODO\nEND:VCALENDAR\n", due));
+        let at = |text: &str| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let Ok(utc) = todo("DUE:20240101T090000Z") else { panic!("expected one VTODO") };
+        assert_eq!((utc.due_at, utc.due_tz), (Some(at("2024-01-01 09:00:00")), None));
+
+        let Ok(zoned) = todo("DUE;TZID=\"Asia/Seoul\":20240101T180000") else { panic!("expected one VTODO") };
+        assert_eq!((zoned.due_at, zoned.due_tz.as_deref()), (Some(at("2024-01-01 18:00:00")), Some("Asia/Seoul")));
+
+        let Ok(date) = todo("DUE;VALUE=DATE:20240101") else { panic!("expected one VTODO") };
+        assert_eq!((date.due_at, date.due_tz), (Some(at("2024-01-01 00:00:00")), None));
+
+        assert_eq!(error_lines(todo("DUE:tomorrow")), vec![4]);
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let value = "a\\b;c,d\ne";
+        assert_eq!(unescape(&escape(value)), value);
+    }
+
+    #[test]
+    fn fold_keeps_lines_short_and_unfold_joins_them() {
+        let line = format!("SUMMARY:{}", "한글".repeat(40));
+        let folded = fold(&line);
+
+        assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+        assert_eq!(unfold(&folded), vec![(1, line)]);
+    }
+}
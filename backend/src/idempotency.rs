@@ -0,0 +1,151 @@
+use crate::config::AppState;
+use crate::context::RequestContext;
+use crate::db;
+use crate::errors::AppError;
+use crate::handlers::{get_client, log_error};
+use crate::models::IdempotencyRecord;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{Bytes, BytesMut, Data};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use slog::{o, error};
+
+// 네트워크가 불안정해서 클라이언트가 같은 POST를 다시 보내도 한 번만 처리되도록 한다.
+// 클라이언트는 요청마다 새 키(ex. uuid)를 만들고, 재시도할 때는 같은 키를 보낸다
+pub const HEADER: &str = "idempotency-key";
+// 저장된 응답을 다시 돌려준 경우 응답에 붙는 헤더
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+// 첨부 파일 업로드(multipart)에도 키를 쓸 수 있도록 body는 ATTACHMENTS.MAX_SIZE_BYTES에 multipart 헤더 여유를 더한 만큼까지 읽는다
+const MULTIPART_OVERHEAD_BYTES: u64 = 64 * 1024;
+
+// POST 요청에 Idempotency-Key가 있을 때만 동작하는 미들웨어 (main.rs에서 App 전체에 등록)
+// - 처음 보는 키: 요청을 처리하고 응답을 저장한다. 서버 오류(5xx)는 저장하지 않아서 다시 시도할 수 있음
+// - 같은 키, 같은 요청: 저장된 응답을 그대로 돌려준다
+// - 같은 키, 다른 요청: 422
+// - 같은 키로 보낸 요청이 아직 처리 중: 409
+pub async fn idempotency(mut req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, Error> {
+    let key = match req.headers().get(HEADER) {
+        Some(key) if req.method() == Method::POST => key.to_str().unwrap_or_default().trim().to_string(),
+        _ => return next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+    };
+
+    if key.is_empty() || key.len() > 255 {
+        return Err(AppError::validation("Idempotency-Key must be 1 to 255 characters").into());
+    }
+
+    let state = req.app_data::<Data<AppState>>().cloned().expect("AppState is registered");
+    let log = state.log.new(o!("middleware" => "idempotency", "key" => key.clone()));
+    let ctx = req.extract::<RequestContext>().await?;
+
+    // fingerprint를 만들기 위해 body를 먼저 읽고, 핸들러가 다시 읽을 수 있도록 되돌려 놓는다
+    let limit = state.attachment_limits.max_size_bytes + MULTIPART_OVERHEAD_BYTES;
+    let body = read_body(&mut req, limit).await?;
+    let fingerprint = fingerprint(req.method(), &req.uri().to_string(), &body);
+    req.set_payload(Payload::from(body));
+
+    // 핸들러가 실행되는 동안 커넥션을 하나 더 잡고 있지 않도록 짧게 쓰고 돌려준다
+    let client = get_client(state.pool.clone(), log.clone()).await?;
+    let ttl = state.idempotency_ttl.as_secs_f64();
+    let existing = db::claim_idempotency_key(&client, &ctx.actor, &key, &fingerprint, ttl)
+        .await
+        .map_err(log_error(log.clone()))?;
+    drop(client);
+
+    match existing {
+        None => {},
+        Some(record) if record.fingerprint != fingerprint => {
+            return Err(AppError::unprocessable("Idempotency-Key was already used with a different request").into());
+        },
+        Some(IdempotencyRecord { status_code: Some(status_code), content_type, response, .. }) => {
+            let mut replay = HttpResponse::build(StatusCode::from_u16(status_code as u16).unwrap_or(StatusCode::OK));
+            replay.insert_header((REPLAYED_HEADER, "true"));
+            if let Some(content_type) = content_type {
+                replay.insert_header((header::CONTENT_TYPE, content_type));
+            }
+            return Ok(req.into_response(replay.body(response.unwrap_or_default())));
+        },
+        Some(_) => {
+            return Err(AppError::conflict("A request with this Idempotency-Key is still being processed").into());
+        }
+    }
+
+    let result = next.call(req).await;
+    let client = get_client(state.pool.clone(), log.clone()).await?;
+
+    let response = match result {
+        Ok(response) => response,
+        Err(err) => {
+            if let Err(err) = db::release_idempotency_key(&client, &ctx.actor, &key).await {
+                error!(log, "{}", err.message(); "cause" => err.cause.clone());
+            }
+            return Err(err);
+        }
+    };
+
+    // 응답 body를 저장하기 위해 끝까지 읽은 다음, 읽은 값으로 응답을 다시 만든다
+    let status = response.status();
+    let content_type = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let (req, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|err| AppError::db_error(err.into()))?;
+
+    let saved = if status.is_server_error() {
+        db::release_idempotency_key(&client, &ctx.actor, &key).await
+    } else {
+        db::complete_idempotency_key(&client, &ctx.actor, &key, status.as_u16() as i32, content_type.as_deref(), &body).await
+    };
+
+    // 저장에 실패해도 요청 자체는 처리되었으므로 응답은 그대로 돌려준다
+    if let Err(err) = saved {
+        error!(log, "{}", err.message(); "cause" => err.cause.clone());
+    }
+
+    Ok(ServiceResponse::new(req, response.set_body(BoxBody::new(body))))
+}
+
+// Bytes extractor는 PayloadConfig의 기본 제한(256KiB)을 넘으면 413이므로 직접 읽는다
+// Content-Length가 limit보다 크면 읽지 않고, 읽는 도중에 limit을 넘어도 바로 413
+async fn read_body(req: &mut ServiceRequest, limit: u64) -> Result<Bytes, Error> {
+    let too_large = || AppError::payload_too_large(format!("Request body is larger than {} bytes", limit));
+
+    let length = req.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(too_large().into());
+    }
+
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(too_large().into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(body.freeze())
+}
+
+// 같은 요청인지 비교하기 위한 값. method, path(+query), body가 모두 같아야 같은 요청
+fn fingerprint(method: &Method, uri: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(uri.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+
+    hex::encode(hasher.finalize())
+}
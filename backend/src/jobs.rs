@@ -0,0 +1,333 @@
+use crate::config::{AttachmentsConfig, IdempotencyConfig, RemindersConfig, TrashConfig, WebhooksConfig};
+use crate::context::RequestContext;
+use crate::db;
+use crate::events::{self, EventHub, ItemEvent};
+use crate::presence::{self, PresenceHub, PresenceSignal};
+use crate::storage::Storage;
+use crate::webhooks::{self, DeliveryResult, HostPolicy, PublicResolver};
+use deadpool_postgres::Pool;
+use futures_util::{stream, StreamExt};
+use slog::{o, error, info, warn, Logger};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_postgres::{AsyncMessage, Client, NoTls};
+
+// 서버와 함께 돌아가는 백그라운드 작업들
+// actix_rt::spawn으로 띄운 작업은 서버(System)가 종료될 때 함께 종료된다
+
+// 보관 기간이 지난 휴지통 항목을 주기적으로 실제 삭제
+pub fn spawn_trash_purge(pool: Pool, log: Logger, config: &TrashConfig) {
+    let log = log.new(o!("job" => "trash_purge"));
+    let retention = chrono::Duration::days(config.retention_days);
+    let period = Duration::from_secs(config.purge_interval_secs);
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let mut client = match pool.get().await {
+                Ok(client) => client,
+                Err(err) => {
+                    error!(log, "Error creating client"; "cause" => err.to_string());
+                    continue;
+                }
+            };
+
+            match db::purge_trash(&mut client, &RequestContext::system(), chrono::Utc::now() - retention).await {
+                Ok((0, 0)) => {},
+                Ok((lists, items)) => info!(log, "Purged trash"; "lists" => lists, "items" => items),
+                Err(err) => error!(log, "{}", err.message(); "cause" => err.cause.clone())
+            }
+        }
+    });
+}
+
+// postgres의 LISTEN으로 item 변경 이벤트를 받아서 EventHub에 넣는다.
+// WebSocket의 presence 신호도 같은 커넥션으로 받아서 PresenceHub에 넣음
+// NOTIFY는 커밋된 트랜잭션만 전달되고 모든 서버에 같이 전달되므로 서버가 여러 대여도 동작한다
+// LISTEN은 커넥션에 묶여 있기 때문에 풀이 아닌 전용 커넥션을 사용
+pub fn spawn_event_listener(pg_config: tokio_postgres::Config, hub: Arc<EventHub>, presence: Arc<PresenceHub>, log: Logger) {
+    let log = log.new(o!("job" => "event_listener"));
+
+    actix_rt::spawn(async move {
+        // hub에 넣은 마지막 이벤트의 seq. 다시 연결하면 그 다음부터 읽어서 끊긴 동안의 이벤트도 넣는다
+        let mut last_seq: Option<i64> = None;
+
+        loop {
+            match listen(&pg_config, &hub, &presence, &mut last_seq, &log).await {
+                Ok(()) => warn!(log, "Event listener connection closed"),
+                Err(err) => error!(log, "Error listening for events"; "cause" => err.to_string())
+            }
+
+            // 연결이 끊기면 잠시 후 다시 연결
+            actix_rt::time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+async fn listen(pg_config: &tokio_postgres::Config, hub: &EventHub, presence: &PresenceHub, last_seq: &mut Option<i64>, log: &Logger) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = pg_config.connect(NoTls).await?;
+
+    // connection은 계속 poll 해줘야 쿼리도 실행되고 알림도 받을 수 있기 때문에 따로 띄워서 채널로 받는다
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+    let connection_log = log.clone();
+    actix_rt::spawn(async move {
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(message) => if sender.send(message).is_err() {
+                    break;
+                },
+                Err(err) => {
+                    error!(connection_log, "Event listener connection error"; "cause" => err.to_string());
+                    break;
+                }
+            }
+        }
+    });
+
+    client.batch_execute(&format!("listen {}; listen {}; listen {}", events::PENDING_CHANNEL, events::CHANNEL, presence::CHANNEL)).await?;
+    info!(log, "Listening for item events");
+
+    // 연결되지 않은 동안 커밋된 이벤트에도 seq를 붙이고, 놓친 이벤트를 읽는다
+    client.execute("select stamp_item_events()", &[]).await?;
+    read_events(&client, hub, last_seq).await?;
+
+    while let Some(message) = receiver.recv().await {
+        let notification = match message {
+            AsyncMessage::Notification(notification) => notification,
+            _ => continue
+        };
+
+        match notification.channel() {
+            presence::CHANNEL => match serde_json::from_str::<PresenceSignal>(notification.payload()) {
+                Ok(signal) => presence.apply(signal),
+                Err(err) => warn!(log, "Invalid event payload"; "channel" => notification.channel().to_string(), "cause" => err.to_string())
+            },
+            // 서버가 여러 대면 모두 호출하지만, seq는 먼저 잠근 쪽에서 한 번만 붙는다
+            events::PENDING_CHANNEL => {
+                client.execute("select stamp_item_events()", &[]).await?;
+            },
+            // NOTIFY에는 seq만 오므로 item은 DB에서 읽는다
+            _ => read_events(&client, hub, last_seq).await?
+        }
+    }
+
+    Ok(())
+}
+
+// last_seq 이후에 seq가 붙은 이벤트를 차례대로 hub에 넣는다
+// 처음 연결할 때는 지난 이벤트를 넣지 않고 지금의 마지막 seq부터 시작
+async fn read_events(client: &Client, hub: &EventHub, last_seq: &mut Option<i64>) -> Result<(), tokio_postgres::Error> {
+    const PAGE_SIZE: i64 = 500;
+
+    let mut after = match *last_seq {
+        Some(after) => after,
+        None => {
+            let row = client.query_one("select coalesce(max(seq), 0) as seq from item_event", &[]).await?;
+            *last_seq = Some(row.get("seq"));
+            return Ok(());
+        }
+    };
+
+    loop {
+        let rows = client.query("select e.seq, e.type, e.list_id, coalesce(a.after, a.before) as item
+                from item_event e
+                join audit_log a on a.id = e.audit_id
+                where e.seq > $1
+                order by e.seq
+                limit $2", &[&after, &PAGE_SIZE]).await?;
+
+        for row in &rows {
+            after = row.get("seq");
+            hub.publish(ItemEvent { id: after, kind: row.get("type"), list_id: row.get("list_id"), item: row.get("item") });
+        }
+        *last_seq = Some(after);
+
+        if (rows.len() as i64) < PAGE_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+// 서버가 갑자기 죽으면 leave 신호가 오지 않으므로, 오래된 presence를 주기적으로 정리한다
+pub fn spawn_presence_expiry(presence: Arc<PresenceHub>, period: Duration) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+            presence.expire();
+        }
+    });
+}
+
+// outbox(webhook_delivery)에 쌓인 웹훅을 보낸다.
+// outbox는 데이터 변경과 같은 트랜잭션에서 쌓이므로(init.pgsql의 트리거), 커밋된 변경만 전송된다
+pub fn spawn_webhook_delivery(pool: Pool, log: Logger, config: &WebhooksConfig) {
+    let log = log.new(o!("job" => "webhook_delivery"));
+    let period = Duration::from_secs(config.poll_interval_secs);
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let backoff = Duration::from_secs(config.backoff_secs);
+    let max_attempts = config.max_attempts;
+    let batch_size = config.batch_size;
+
+    // 연결할 IP를 PublicResolver가 확인한다. redirect는 따라가지 않는다 (IP로 된 주소로 보내면 resolver를 거치지 않음)
+    let policy = Arc::new(HostPolicy::new(config));
+    let http = reqwest::Client::builder()
+        .timeout(timeout)
+        .dns_resolver(Arc::new(PublicResolver(policy.clone())))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Error creating webhook HTTP client");
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let client = match pool.get().await {
+                Ok(client) => client,
+                Err(err) => {
+                    error!(log, "Error creating client"; "cause" => err.to_string());
+                    continue;
+                }
+            };
+
+            // 보내는 동안 다른 서버가 가져가지 않도록 timeout보다 조금 길게 잡아둔다
+            let lease = (timeout + Duration::from_secs(30)).as_secs_f64();
+            let deliveries = match db::claim_webhook_deliveries(&client, batch_size, lease).await {
+                Ok(deliveries) => deliveries,
+                Err(err) => {
+                    error!(log, "{}", err.message(); "cause" => err.cause.clone());
+                    continue;
+                }
+            };
+
+            for delivery in deliveries {
+                let result = match webhooks::deliver(&http, &policy, &delivery).await {
+                    DeliveryResult::Delivered(status) => db::complete_webhook_delivery(&client, delivery.id, status as i32).await,
+                    DeliveryResult::Failed(status, cause) => {
+                        warn!(log, "Webhook delivery failed"; "delivery" => delivery.id, "attempts" => delivery.attempts, "cause" => cause.clone());
+                        let retry_after = webhooks::backoff(backoff, delivery.attempts).as_secs_f64();
+                        db::fail_webhook_delivery(&client, delivery.id, status.map(|status| status as i32), &cause, max_attempts, retry_after).await
+                    }
+                };
+
+                if let Err(err) = result {
+                    error!(log, "{}", err.message(); "cause" => err.cause.clone());
+                }
+            }
+        }
+    });
+}
+
+// 만료된 Idempotency-Key를 지운다. 만료된 키는 이미 재사용할 수 있으므로 테이블 크기를 줄이기 위한 것
+pub fn spawn_idempotency_cleanup(pool: Pool, log: Logger, config: &IdempotencyConfig) {
+    let log = log.new(o!("job" => "idempotency_cleanup"));
+    let period = Duration::from_secs(config.cleanup_interval_secs);
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let client = match pool.get().await {
+                Ok(client) => client,
+                Err(err) => {
+                    error!(log, "Error creating client"; "cause" => err.to_string());
+                    continue;
+                }
+            };
+
+            match db::purge_idempotency_keys(&client).await {
+                Ok(0) => {},
+                Ok(keys) => info!(log, "Purged expired idempotency keys"; "keys" => keys),
+                Err(err) => error!(log, "{}", err.message(); "cause" => err.cause.clone())
+            }
+        }
+    });
+}
+
+// 알림 시간이 된 item의 알림을 보낸다. 알림은 로그로 남기고,
+// 감사 로그(remind)를 통해 SSE(reminded)와 웹훅(item.reminded)으로도 나간다
+pub fn spawn_reminders(pool: Pool, log: Logger, config: &RemindersConfig) {
+    let log = log.new(o!("job" => "reminders"));
+    let period = Duration::from_secs(config.poll_interval_secs);
+    let batch_size = config.batch_size;
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let mut client = match pool.get().await {
+                Ok(client) => client,
+                Err(err) => {
+                    error!(log, "Error creating client"; "cause" => err.to_string());
+                    continue;
+                }
+            };
+
+            match db::send_reminders(&mut client, &RequestContext::system(), batch_size).await {
+                Ok(items) => for item in items {
+                    info!(log, "Reminder";
+                        "item_id" => item.id,
+                        "list_id" => item.list_id,
+                        "title" => item.title,
+                        "due_at" => item.due_at.map(|due_at| due_at.to_rfc3339()));
+                },
+                Err(err) => error!(log, "{}", err.message(); "cause" => err.cause.clone())
+            }
+        }
+    });
+}
+
+// 지워진 첨부 파일의 blob을 저장소에서 지운다 (init.pgsql의 blob_cleanup)
+// 실패한 blob은 그대로 남아서 다음 주기에 다시 시도한다
+pub fn spawn_blob_cleanup(pool: Pool, storage: Arc<dyn Storage>, log: Logger, config: &AttachmentsConfig) {
+    let log = log.new(o!("job" => "blob_cleanup"));
+    let period = Duration::from_secs(config.cleanup_interval_secs);
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let client = match pool.get().await {
+                Ok(client) => client,
+                Err(err) => {
+                    error!(log, "Error creating client"; "cause" => err.to_string());
+                    continue;
+                }
+            };
+
+            let keys = match db::get_blob_cleanups(&client, 100).await {
+                Ok(keys) => keys,
+                Err(err) => {
+                    error!(log, "{}", err.message(); "cause" => err.cause.clone());
+                    continue;
+                }
+            };
+
+            for key in keys {
+                if let Err(err) = storage.delete(&key).await {
+                    warn!(log, "Error deleting blob"; "key" => key, "cause" => err.to_string());
+                    continue;
+                }
+
+                match db::complete_blob_cleanup(&client, &key).await {
+                    Ok(()) => info!(log, "Deleted blob"; "key" => key),
+                    Err(err) => error!(log, "{}", err.message(); "cause" => err.cause.clone())
+                }
+            }
+        }
+    });
+}
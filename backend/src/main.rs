@@ -1,19 +1,38 @@
 mod config;
+mod auth;
 mod models; // 해당 파일을 사용
 mod handlers;
 mod db;
 mod errors;
+mod conditional;
+mod jobs;
+mod context;
+mod events;
+mod presence;
+mod ws;
+mod webhooks;
+mod idempotency;
+mod formats;
+mod ical;
+mod caldav;
+mod recurrence;
+mod templates;
+mod mentions;
+mod storage;
+mod attachments;
 // mod의 경우 최상위에서 한 번 사용하면,
 // 하위 파일에서는 굳이 mod로 불러올 필요 없이
 // use crate로 가져와서 쓰면 된다.
 
-use actix_web::{HttpServer, App, web::{self, Data}};
-use slog::{Logger, Drain, o, info};
+use actix_web::{HttpServer, App, dev::Service, middleware::from_fn, web::{self, Data}, http::{Method, header::{HeaderName, HeaderValue}}};
+use slog::{Logger, Drain, o, info, warn};
 use std::io;
 use dotenv::dotenv;
 use tokio_postgres::NoTls;
 use deadpool_postgres::{Runtime};
-use crate::{handlers::*, config::AppState}; // 그렇게 정의된 모듈, 타입, 함수 등을 현재 범위로 가져와 사용가능하게 함
+use crate::{handlers::*, config::{AppState, AttachmentLimits}, events::EventHub, presence::PresenceHub, webhooks::HostPolicy};
+use std::sync::Arc;
+use std::time::Duration; // 그렇게 정의된 모듈, 타입, 함수 등을 현재 범위로 가져와 사용가능하게 함
 
 
 // log 설정.
@@ -40,12 +59,62 @@ async fn main() -> io::Result<()>{
     // unwrap을 할 때, ok이면 그대로 실행, error면 error 발생하고 프로그램 정지
     let config = crate::config::ConfigSetting::from_env().unwrap();
 
+    let auth = Arc::new(crate::auth::Auth::new(&config.auth));
+
+    // 처음에는 토큰을 받을 방법이 없으므로, 서버에서 직접 만든다
+    // ex) cargo run -- token alice 또는 ./app token alice 86400
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("token") {
+        let user = args.get(2).expect("usage: app token <user> [ttl_secs]");
+        let ttl_secs = args.get(3).map(|ttl| ttl.parse().expect("ttl_secs must be a number")).unwrap_or(auth.token_ttl_secs);
+        println!("{}", auth.issue_token(user, ttl_secs).expect("AUTH.SECRET is required to issue tokens"));
+        return Ok(());
+    }
+
+    crate::db::set_statement_cache(config.db.statement_cache);
+    crate::db::set_subtask_rules(config.items.max_depth, config.items.auto_complete_parents);
+    crate::db::set_allow_blocked_check(config.items.allow_blocked_check);
+
     // postgres 데이터베이스 설정 파일로 부터 해당 데이터베이스 컨트롤러를 가져오기
     let pool = config.pg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
 
     // 최상위 파일에서 log 설정
     let log = configure_log();
 
+    if config.auth.secret.is_empty() && config.auth.proxy_secret.is_empty() {
+        warn!(log, "Neither AUTH.SECRET nor AUTH.PROXY_SECRET is set, every request is anonymous");
+    }
+
+    // item 변경 이벤트(SSE) 수신 시작
+    let events = Arc::new(EventHub::new(config.events.replay_buffer));
+    let events_heartbeat = Duration::from_secs(config.events.heartbeat_secs);
+    // presence는 heartbeat마다 갱신되므로, 3번 연속 갱신이 없으면 나간 것으로 본다
+    let presence = Arc::new(PresenceHub::new(events_heartbeat * 3));
+    crate::jobs::spawn_event_listener(config.pg.get_pg_config().unwrap(), events.clone(), presence.clone(), log.clone());
+    crate::jobs::spawn_presence_expiry(presence.clone(), events_heartbeat);
+
+    // 웹훅 전송 시작
+    crate::jobs::spawn_webhook_delivery(pool.clone(), log.clone(), &config.webhooks);
+    let webhook_hosts = Arc::new(HostPolicy::new(&config.webhooks));
+
+    // 만료된 Idempotency-Key 정리
+    let idempotency_ttl = Duration::from_secs(config.idempotency.ttl_secs);
+    crate::jobs::spawn_idempotency_cleanup(pool.clone(), log.clone(), &config.idempotency);
+
+    // 알림 시간이 된 item의 알림 보내기
+    crate::jobs::spawn_reminders(pool.clone(), log.clone(), &config.reminders);
+
+    // 첨부 파일 저장소. 지워진 첨부 파일의 blob 정리도 시작
+    let storage = crate::storage::from_config(&config.attachments).unwrap();
+    let attachment_limits = Arc::new(AttachmentLimits {
+        max_size_bytes: config.attachments.max_size_bytes,
+        allowed_types: config.attachments.allowed_types()
+    });
+    crate::jobs::spawn_blob_cleanup(pool.clone(), storage.clone(), log.clone(), &config.attachments);
+
+    // 휴지통 정리 작업 시작
+    crate::jobs::spawn_trash_purge(pool.clone(), log.clone(), &config.trash);
+
     info!(log, "Starting server at http://{}:{}/", config.server.host, config.server.port);
 
     // ::는 모듈 접근. 다른 언어서는 보통 .으로 표현. 예를 들어 std::io의 경우,
@@ -68,18 +137,156 @@ async fn main() -> io::Result<()>{
             // 각각의 요청에 독립적으로 접근할 수 있게 해줌
             // .app_data(Data::new(pool.clone()))
             // logger까지 모든 라우터에서 사용할 수 있도록 설정
-            .app_data(AppState {
+            // 핸들러에서 web::Data<AppState>로 꺼내 쓰기 때문에 Data로 감싸서 등록해야 한다
+            .app_data(Data::new(AppState {
                 pool: pool.clone(),
-                log: log.clone()
+                log: log.clone(),
+                auth: auth.clone(),
+                events: events.clone(),
+                events_heartbeat,
+                presence: presence.clone(),
+                idempotency_ttl,
+                storage: storage.clone(),
+                attachment_limits: attachment_limits.clone(),
+                webhook_hosts: webhook_hosts.clone()
+            }))
+            // POST 요청의 Idempotency-Key 처리. 아래 wrap_fn보다 먼저 등록해야 안쪽에서 실행되어
+            // 저장된 응답을 돌려줄 때도 X-Request-Id가 붙는다
+            .wrap(from_fn(crate::idempotency::idempotency))
+            // 모든 요청에 X-Request-Id를 붙인다. 감사 로그에 함께 기록되고, 응답 헤더로도 돌려줌
+            .wrap_fn(|req, srv| {
+                let request_id = crate::context::assign_request_id(&req);
+                let response = srv.call(req);
+
+                async move {
+                    let mut response = response.await?;
+                    if let Ok(value) = HeaderValue::from_str(&request_id) {
+                        response.headers_mut().insert(HeaderName::from_static(crate::context::REQUEST_ID_HEADER), value);
+                    }
+                    Ok(response)
+                }
             })
             .route("/", web::get().to(status))
+            // 요청한 사용자의 토큰. WebSocket, CalDAV 클라이언트에 쓴다
+            .route("/auth/token{_:/?}", web::post().to(issue_token))
+            .route("/metrics/statements{_:/?}", web::get().to(statement_cache_stats))
             // {_:/?} 는 맨 마지막에 / 뒤에 오는 값들은 무시를 하겠다
             // get_todos의 경우 db_pool: web::Data<Pool>의 파라미터가 필요하지만
             // actix의 경우 app_data안에 있는 값에서 찾아서 자동으로 넣어줌
             .route("/todos{_:/?}", web::get().to(get_todos))
             .route("/todos{_:/?}", web::post().to(create_todo))
+            // markdown, todo.txt, json, csv, ics로 가져오기/내보내기
+            .route("/todos/import{_:/?}", web::post().to(import_todo))
+            .route("/todos/{list_id}/export{_:/?}", web::get().to(export_todo))
+            .route("/todos/{list_id}{_:/?}", web::delete().to(delete_todo))
+            // 순서 바꾸기. ex) {"before": 3} 또는 {"after": 3}
+            .route("/todos/{list_id}/move{_:/?}", web::post().to(move_todo))
+            // list 복제. 템플릿 list면 title의 {{name}}을 variables로 바꾼다
+            .route("/todos/{list_id}/duplicate{_:/?}", web::post().to(duplicate_todo))
+            .route("/todos/{list_id}/template{_:/?}", web::put().to(set_todo_template))
+            // 상태 흐름(칸반 보드의 열)과 보드
+            .route("/todos/{list_id}/workflow{_:/?}", web::get().to(get_workflow))
+            .route("/todos/{list_id}/workflow{_:/?}", web::put().to(set_workflow))
+            .route("/todos/{list_id}/board{_:/?}", web::get().to(get_board))
+            // list의 멤버. 멤버가 있는 list는 멤버와 관리자만 볼 수 있다. ex) PUT .../members/alice {"role": "editor"}
+            .route("/todos/{list_id}/members{_:/?}", web::get().to(get_members))
+            .route("/todos/{list_id}/members/{member}{_:/?}", web::put().to(set_member))
+            .route("/todos/{list_id}/members/{member}{_:/?}", web::delete().to(remove_member))
+            // 요청한 사용자의 태그. ex) {"tags": ["backend", "infra"]}
+            .route("/todos/{list_id}/tags{_:/?}", web::get().to(get_list_tags))
+            .route("/todos/{list_id}/tags{_:/?}", web::put().to(set_list_tags))
             .route("/todos/{list_id}/items{_:/?}", web::get().to(get_itmes))
+            // 여러 item을 한 번에 바꾸기 (create/update/check/uncheck/delete/check_all/clear_completed)
+            .route("/todos/{list_id}/items:batch", web::post().to(batch_items))
+            // 다른 list로 옮기기/복사하기. ex) {"item_ids": [1, 2], "to_list_id": 3}
+            .route("/todos/{list_id}/items:move", web::post().to(move_items))
+            .route("/todos/{list_id}/items:copy", web::post().to(copy_items))
+            // item 변경을 Server-Sent Events로 받는다. Last-Event-ID 헤더로 이어받기 가능
+            .route("/todos/{list_id}/events{_:/?}", web::get().to(item_events))
+            // 같은 list를 함께 편집하기 위한 WebSocket. 토큰은 Sec-WebSocket-Protocol: bearer, <token>으로
+            .route("/todos/{list_id}/ws{_:/?}", web::get().to(list_socket))
+            .route("/todos/{list_id}/items/{item_id}{_:/?}", web::get().to(get_item))
+            // 수정 요청은 If-Match 헤더가 필요하다. ex) If-Match: "1"
             .route("/todos/{list_id}/items/{item_id}{_:/?}", web::put().to(check_itme))
+            // 삭제는 휴지통으로 이동(soft delete). 휴지통에서 복구 가능
+            .route("/todos/{list_id}/items/{item_id}{_:/?}", web::delete().to(delete_item))
+            // 마감 시간, 알림 시간. If-Match 필요
+            .route("/todos/{list_id}/items/{item_id}/schedule{_:/?}", web::put().to(schedule_item))
+            // 반복 규칙 (ex. {"rule": "FREQ=WEEKLY;BYDAY=MO", "tz": "Asia/Seoul"}). If-Match 필요
+            .route("/todos/{list_id}/items/{item_id}/recurrence{_:/?}", web::put().to(set_item_recurrence))
+            // 하위 item으로 옮기기 (ex. {"parent_id": 3}). If-Match 필요
+            .route("/todos/{list_id}/items/{item_id}/parent{_:/?}", web::put().to(set_item_parent))
+            .route("/todos/{list_id}/items/{item_id}/move{_:/?}", web::post().to(move_item))
+            // 상태(ex. {"status": "doing"})와 우선순위(ex. {"priority": 3}). If-Match 필요
+            .route("/todos/{list_id}/items/{item_id}/status{_:/?}", web::put().to(set_item_status))
+            .route("/todos/{list_id}/items/{item_id}/priority{_:/?}", web::put().to(set_item_priority))
+            .route("/todos/{list_id}/items/{item_id}/tags{_:/?}", web::get().to(get_item_tags))
+            .route("/todos/{list_id}/items/{item_id}/tags{_:/?}", web::put().to(set_item_tags))
+            // 의존 관계 (ex. {"blocked_by": 12}). blocker는 다른 list의 item이어도 된다
+            .route("/todos/{list_id}/items/{item_id}/dependencies{_:/?}", web::get().to(get_dependencies))
+            .route("/todos/{list_id}/items/{item_id}/dependencies{_:/?}", web::post().to(add_dependency))
+            .route("/todos/{list_id}/items/{item_id}/dependencies/{blocker_id}{_:/?}", web::delete().to(remove_dependency))
+            .route("/todos/{list_id}/dependencies{_:/?}", web::get().to(get_dependency_graph))
+            // 담당자 (ex. {"assignees": ["alice", "bob"]})
+            .route("/todos/{list_id}/items/{item_id}/assignees{_:/?}", web::get().to(get_assignees))
+            .route("/todos/{list_id}/items/{item_id}/assignees{_:/?}", web::put().to(set_assignees))
+            // 자세한 내용(Markdown, ex. {"notes": "..."}). If-Match 필요
+            .route("/todos/{list_id}/items/{item_id}/notes{_:/?}", web::put().to(set_item_notes))
+            // 댓글과 답글(ex. {"body": "@kim 확인 부탁", "parent_id": 3})
+            .route("/todos/{list_id}/items/{item_id}/comments{_:/?}", web::get().to(get_comments))
+            .route("/todos/{list_id}/items/{item_id}/comments{_:/?}", web::post().to(create_comment))
+            .route("/todos/{list_id}/items/{item_id}/comments/{comment_id}{_:/?}", web::put().to(update_comment))
+            .route("/todos/{list_id}/items/{item_id}/comments/{comment_id}{_:/?}", web::delete().to(delete_comment))
+            // 첨부 파일. multipart/form-data로 올리고, 내려받을 때는 Range를 쓸 수 있다
+            .route("/todos/{list_id}/items/{item_id}/attachments{_:/?}", web::get().to(get_attachments))
+            .route("/todos/{list_id}/items/{item_id}/attachments{_:/?}", web::post().to(upload_attachments))
+            .route("/todos/{list_id}/items/{item_id}/attachments/{attachment_id}{_:/?}", web::get().to(download_attachment))
+            .route("/todos/{list_id}/items/{item_id}/attachments/{attachment_id}{_:/?}", web::delete().to(delete_attachment))
+            // 요청한 사용자가 담당자인 item. ex) /me/items?checked=false&list_id=3
+            .route("/me/items{_:/?}", web::get().to(get_my_items))
+            // 요청한 사용자에게 온 알림. ex) /me/notifications?unread=true
+            .route("/me/notifications{_:/?}", web::get().to(get_notifications))
+            .route("/me/notifications/read{_:/?}", web::post().to(read_notifications))
+            .route("/me/notifications/{notification_id}/read{_:/?}", web::post().to(read_notification))
+            // 모든 list에서 item 찾기. ex) /items?all_tags=backend,infra&overdue=true
+            .route("/items{_:/?}", web::get().to(find_items))
+            .route("/tags{_:/?}", web::get().to(get_tags))
+            .route("/tags{_:/?}", web::post().to(create_tag))
+            .route("/tags/{tag_id}{_:/?}", web::put().to(update_tag))
+            .route("/tags/{tag_id}{_:/?}", web::delete().to(delete_tag))
+            // 반복 item의 완료 기록
+            .route("/todos/{list_id}/items/{item_id}/history{_:/?}", web::get().to(get_item_history))
+            // 모든 list의 마감 일정을 날짜별로. ex) /agenda?tz=Asia/Seoul&days=7
+            .route("/agenda{_:/?}", web::get().to(get_agenda))
+            .route("/trash{_:/?}", web::get().to(get_trash))
+            // list와 item 전체 텍스트 검색. ex) /search?q=장보기&type=item&checked=false
+            .route("/search{_:/?}", web::get().to(search))
+            // 관리자(AUTH.ADMINS)만 조회 가능
+            .route("/audit{_:/?}", web::get().to(get_audit_log))
+            // CalDAV. 캘린더 앱과 양방향으로 동기화 (caldav.rs 참고)
+            .route("/.well-known/caldav", web::route().to(caldav::well_known))
+            .route("/dav{_:(/.*)?}", web::method(Method::OPTIONS).to(caldav::options))
+            .route("/dav{_:/?}", caldav::propfind().to(caldav::propfind_principal))
+            .route("/dav/principals/{user}{_:/?}", caldav::propfind().to(caldav::propfind_principal))
+            .route("/dav/calendars{_:/?}", caldav::propfind().to(caldav::propfind_home))
+            .route("/dav/calendars/{list_id}{_:/?}", caldav::propfind().to(caldav::propfind_calendar))
+            .route("/dav/calendars/{list_id}{_:/?}", caldav::report().to(caldav::report_calendar))
+            .route("/dav/calendars/{list_id}/{name}", web::get().to(caldav::get_resource))
+            .route("/dav/calendars/{list_id}/{name}", web::put().to(caldav::put_resource))
+            .route("/dav/calendars/{list_id}/{name}", web::delete().to(caldav::delete_resource))
+            // 캘린더 앱에서 구독하는 .ics 피드 (iCalendar VTODO)
+            .route("/calendar/feeds{_:/?}", web::get().to(get_calendar_feeds))
+            .route("/calendar/feeds{_:/?}", web::post().to(create_calendar_feed))
+            .route("/calendar/feeds/{feed_id}{_:/?}", web::delete().to(delete_calendar_feed))
+            .route("/calendar/{token}.ics", web::get().to(calendar_feed))
+            // 웹훅 구독과 전송 기록. 자신이 만든 구독만 볼 수 있다 (관리자는 전부)
+            .route("/webhooks{_:/?}", web::get().to(get_webhooks))
+            .route("/webhooks{_:/?}", web::post().to(create_webhook))
+            .route("/webhooks/{webhook_id}{_:/?}", web::delete().to(delete_webhook))
+            .route("/webhooks/{webhook_id}/deliveries{_:/?}", web::get().to(get_webhook_deliveries))
+            .route("/webhooks/{webhook_id}/deliveries/{delivery_id}/retry{_:/?}", web::post().to(retry_webhook_delivery))
+            // kind는 lists 또는 items
+            .route("/trash/{kind}/{id}/restore{_:/?}", web::post().to(restore_from_trash))
 
     })
     // 만약 bind에 성공하면 그대로 넘어가고 아니면 error 발생
@@ -0,0 +1,74 @@
+// 댓글과 notes에서 @이름 으로 부른 사용자를 찾는다
+// - 이름은 사용자 이름(auth.rs에서 확인한 값). 영문, 숫자, 한글 등의 글자와 . _ - 를 쓸 수 있다 (끝의 . 은 문장 부호로 보고 뺀다)
+// - @ 앞이 글자나 숫자이면 이메일 주소(ex. me@example.com)로 보고 무시
+// - 같은 이름은 한 번만, 나온 순서대로. 너무 많이 부르면 앞의 MAX_MENTIONS 명만
+const MAX_MENTIONS: usize = 20;
+
+pub fn parse(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let mut previous: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let after_word = previous.is_some_and(|previous| previous.is_alphanumeric() || previous == '_');
+        previous = Some(c);
+
+        if c != '@' || after_word {
+            continue;
+        }
+
+        let start = index + 1;
+        let mut end = start;
+        while let Some(&(next_index, next)) = chars.peek() {
+            if !is_name_char(next) {
+                break;
+            }
+            end = next_index + next.len_utf8();
+            previous = Some(next);
+            chars.next();
+        }
+
+        let name = text[start..end].trim_end_matches('.');
+        if !name.is_empty() && !names.iter().any(|existing| existing == name) {
+            names.push(name.to_string());
+            if names.len() == MAX_MENTIONS {
+                break;
+            }
+        }
+    }
+
+    names
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_names_in_order_without_duplicates() {
+        assert_eq!(parse("@bob 확인 부탁, @김철수 도 @bob."), vec!["bob", "김철수"]);
+    }
+
+    #[test]
+    fn ignores_emails_and_bare_at() {
+        assert_eq!(parse("bob@example.com 으로 보내기 @ 끝 (@carol)"), vec!["carol"]);
+    }
+
+    #[test]
+    fn keeps_dots_inside_names() {
+        assert_eq!(parse("@first.last. 와 @a_b-c"), vec!["first.last", "a_b-c"]);
+    }
+
+    #[test]
+    fn caps_number_of_mentions() {
+        let text: Vec<String> = (0..30).map(|n| format!("@user{}", n)).collect();
+        let names = parse(&text.join(" "));
+
+        assert_eq!(names.len(), MAX_MENTIONS);
+        assert_eq!(names[0], "user0");
+    }
+}
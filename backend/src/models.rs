@@ -1,5 +1,9 @@
 use serde::{Serialize, Deserialize};
+use chrono::{DateTime, NaiveDate, Utc};
 use tokio_pg_mapper_derive::PostgresMapper;
+use crate::errors::AppError;
+use crate::formats::{Format, ParseError};
+use std::collections::{HashMap, HashSet};
 
 // attribute
 // 보통 그 아래에 있는 함수를 attribute의 설정에 맞춰 구현을 자동 생성해 줌
@@ -22,7 +26,19 @@ pub struct Status {
 #[pg_mapper(table="todo_list")]
 pub struct TodoList {
     pub id: i32,
-    pub title: String
+    pub title: String,
+    // count()의 결과는 postgres에서 bigint이므로 i64
+    pub item_count: i64,
+    pub checked_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: i32,
+    // 직접 정한 순서. 작을수록 앞
+    pub position: i64,
+    pub is_template: bool,
+    // 휴지통에 있을 때만 값이 있으므로, 없으면 json에서 생략
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>
 }
 
 #[derive(Serialize, Deserialize, PostgresMapper)]
@@ -31,7 +47,741 @@ pub struct TodoItem {
     pub id: i32,
     pub title: String,
     pub checked: bool,
-    pub list_id: i32
+    pub list_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: i32,
+    pub position: i64,
+    // 휴지통에 있을 때만 값이 있으므로, 없으면 json에서 생략
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // 마감 시간, 알림 시간. 없으면 null
+    pub due_at: Option<DateTime<Utc>>,
+    pub remind_at: Option<DateTime<Utc>>,
+    // 알림 작업이 알림을 보낸 시간
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reminded_at: Option<DateTime<Utc>>,
+    // 반복 규칙. 체크하면 다음 item이 만들어지고 규칙은 다음 item으로 옮겨간다
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurrence_tz: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<i32>,
+    // 상태(list의 상태 흐름 중 하나)와 우선순위(0 없음 ~ 4 긴급). checked는 상태가 완료 상태인지
+    pub status: String,
+    pub priority: i16,
+    // 자세한 내용 (Markdown). 없으면 json에서 생략
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    // 상위 item. 최상위 item이면 null
+    pub parent_id: Option<i32>,
+    // CalDAV 클라이언트가 만든 item의 UID와 리소스 이름(ex. 3F2A….ics). API 응답에는 포함하지 않음
+    #[serde(skip)]
+    pub ical_uid: Option<String>,
+    #[serde(skip)]
+    pub dav_name: Option<String>
+}
+
+// ?include=items 일 때의 응답
+// #[serde(flatten)]은 list의 필드들을 한 단계 위로 펼쳐서 {"id": .., "title": .., "items": [..]} 형태로 만들어 줌
+#[derive(Serialize)]
+pub struct TodoListWithItems {
+    #[serde(flatten)]
+    pub list: TodoList,
+    pub items: Vec<TodoItem>
+}
+
+// GET /todos?include=items
+// 쉼표로 여러 값을 받을 수 있도록 문자열로 받음
+#[derive(Deserialize)]
+pub struct TodosQuery {
+    pub include: Option<String>,
+    // ?template=true 이면 템플릿 list만
+    #[serde(default)]
+    pub template: bool,
+    // ?tag=backend 이면 요청한 사용자의 backend 태그가 붙은 list만
+    pub tag: Option<String>
+}
+
+impl TodosQuery {
+    pub fn includes(&self, name: &str) -> bool {
+        self.include
+            .as_deref()
+            .map(|include| include.split(',').any(|value| value.trim() == name))
+            .unwrap_or(false)
+    }
+}
+
+// GET /todos/{list_id}/items?due_before=2024-01-01T00:00:00Z&overdue=true
+// overdue: 마감 시간이 지났는데 체크되지 않은 item만
+// view=tree: 하위 item을 상위 item의 children으로 묶어서 돌려준다
+// tag=backend, any_tag=backend,infra (하나라도), all_tags=backend,infra (모두). 태그는 요청한 사용자의 것
+// status=doing: 그 상태의 item만, checked=false: 완료되지 않은 item만
+// assignee=bob: bob이 담당자인 item만
+// ready=true: 체크되지 않았고, 체크되지 않은 blocker도 없어서 지금 할 수 있는 item만
+// GET /items 는 같은 조건으로 모든 list에서 찾는다 (list_id=3 이면 그 list에서만)
+// GET /me/items 는 요청한 사용자가 담당자인 item을 같은 조건으로 찾는다
+#[derive(Deserialize)]
+pub struct ItemsQuery {
+    pub due_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub overdue: bool,
+    #[serde(default)]
+    pub view: ItemsView,
+    pub tag: Option<String>,
+    pub any_tag: Option<String>,
+    pub all_tags: Option<String>,
+    // status=doing
+    pub status: Option<String>,
+    pub checked: Option<bool>,
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub ready: bool,
+    // GET /items, GET /me/items 에서만. GET /todos/{list_id}/items 는 경로의 list_id를 쓴다
+    pub list_id: Option<i32>
+}
+
+impl ItemsQuery {
+    pub fn has_filters(&self) -> bool {
+        self.due_before.is_some() || self.overdue || self.status.is_some() || self.checked.is_some()
+            || self.assignee.is_some() || self.ready || self.list_id.is_some() || self.has_tag_filters()
+    }
+
+    pub fn has_tag_filters(&self) -> bool {
+        self.tag.is_some() || self.any_tag.is_some() || self.all_tags.is_some()
+    }
+
+    // (모두 있어야 하는 태그, 하나라도 있으면 되는 태그). DB와 같이 소문자로 비교
+    pub fn tag_filters(&self) -> (Vec<String>, Vec<String>) {
+        let split = |value: &Option<String>| -> Vec<String> {
+            value.iter()
+                .flat_map(|value| value.split(','))
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect()
+        };
+
+        let mut all = split(&self.all_tags);
+        all.extend(split(&self.tag));
+        all.sort();
+        all.dedup();
+
+        (all, split(&self.any_tag))
+    }
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemsView {
+    #[default]
+    Flat,
+    Tree
+}
+
+// ?view=tree 의 item 하나. progress는 하위 item이 있을 때만 (모든 단계의 하위 item 수)
+#[derive(Serialize)]
+pub struct ItemNode {
+    #[serde(flatten)]
+    pub item: TodoItem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    pub children: Vec<ItemNode>
+}
+
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct Progress {
+    pub checked: i64,
+    pub total: i64
+}
+
+impl ItemNode {
+    // id 순으로 정렬된 item 목록을 트리로 만든다.
+    // 상위 item이 목록에 없으면(휴지통에 있거나 조건으로 걸러진 경우) 최상위로 보여준다
+    pub fn tree(items: Vec<TodoItem>) -> Vec<ItemNode> {
+        let ids: HashSet<i32> = items.iter().map(|item| item.id).collect();
+        let mut children: HashMap<i32, Vec<TodoItem>> = HashMap::new();
+        let mut roots = vec![];
+
+        for item in items {
+            match item.parent_id.filter(|parent_id| ids.contains(parent_id)) {
+                Some(parent_id) => children.entry(parent_id).or_default().push(item),
+                None => roots.push(item)
+            }
+        }
+
+        roots.into_iter().map(|item| ItemNode::build(item, &mut children)).collect()
+    }
+
+    fn build(item: TodoItem, children: &mut HashMap<i32, Vec<TodoItem>>) -> ItemNode {
+        let nodes: Vec<ItemNode> = children.remove(&item.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| ItemNode::build(child, children))
+            .collect();
+
+        let progress = if nodes.is_empty() {
+            None
+        } else {
+            Some(nodes.iter().fold(Progress::default(), |progress, node| {
+                let below = node.progress.unwrap_or_default();
+                Progress {
+                    checked: progress.checked + below.checked + node.item.checked as i64,
+                    total: progress.total + below.total + 1
+                }
+            }))
+        };
+
+        ItemNode { item, progress, children: nodes }
+    }
+}
+
+// POST /todos/{list_id}/move, POST /todos/{list_id}/items/{item_id}/move
+// {"before": 3}이면 3의 바로 앞으로, {"after": 3}이면 바로 뒤로 옮긴다. 둘 중 하나만
+#[derive(Deserialize)]
+pub struct MoveRequest {
+    pub before: Option<i32>,
+    pub after: Option<i32>
+}
+
+// list의 상태 하나 (보드의 열). PUT /todos/{list_id}/workflow 에서는 순서대로 보낸다
+#[derive(Serialize, Deserialize, PostgresMapper, Clone)]
+#[pg_mapper(table="list_status")]
+pub struct ListStatus {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub done: bool,
+    pub wip_limit: Option<i32>
+}
+
+impl ListStatus {
+    // 상태 흐름을 정하지 않은 list. init.pgsql의 todo_item_sync_status와 같아야 한다
+    pub fn defaults() -> Vec<ListStatus> {
+        vec![
+            ListStatus { key: "todo".to_string(), name: "To do".to_string(), done: false, wip_limit: None },
+            ListStatus { key: "done".to_string(), name: "Done".to_string(), done: true, wip_limit: None }
+        ]
+    }
+}
+
+// PUT /todos/{list_id}/workflow
+// {"statuses": [{"key": "todo", "name": "할 일"}, {"key": "doing", "name": "진행 중", "wip_limit": 3}, {"key": "done", "name": "완료", "done": true}]}
+#[derive(Deserialize)]
+pub struct Workflow {
+    pub statuses: Vec<ListStatus>
+}
+
+impl Workflow {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.statuses.is_empty() || self.statuses.len() > 20 {
+            return Err(AppError::validation("A workflow must have 1 to 20 statuses"));
+        }
+
+        let mut keys = HashSet::new();
+        for status in &self.statuses {
+            let valid_key = !status.key.is_empty() && status.key.len() <= 30
+                && status.key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+            if !valid_key {
+                return Err(AppError::validation(format!("Invalid status key {}, use up to 30 of a-z, 0-9, _ and -", status.key)));
+            }
+            if !keys.insert(status.key.as_str()) {
+                return Err(AppError::validation(format!("Duplicate status key {}", status.key)));
+            }
+            if status.name.trim().is_empty() || status.name.chars().count() > 50 {
+                return Err(AppError::validation(format!("Status {} must have a name of 1 to 50 characters", status.key)));
+            }
+            if status.wip_limit.is_some_and(|limit| limit < 1) {
+                return Err(AppError::validation(format!("wip_limit of {} must be at least 1", status.key)));
+            }
+        }
+
+        // 체크/체크 해제할 때 옮겨갈 상태가 있어야 한다
+        if !self.statuses.iter().any(|status| status.done) || self.statuses.iter().all(|status| status.done) {
+            return Err(AppError::validation("A workflow needs at least one done and one not done status"));
+        }
+
+        Ok(())
+    }
+}
+
+// PUT /todos/{list_id}/items/{item_id}/status
+#[derive(Deserialize)]
+pub struct ItemStatus {
+    pub status: String
+}
+
+// PUT /todos/{list_id}/items/{item_id}/priority. 0 없음, 1 낮음, 2 보통, 3 높음, 4 긴급
+#[derive(Deserialize)]
+pub struct ItemPriority {
+    pub priority: i16
+}
+
+// GET /todos/{list_id}/board. 상태마다 한 열
+#[derive(Serialize)]
+pub struct Board {
+    pub list_id: i32,
+    pub columns: Vec<BoardColumn>
+}
+
+#[derive(Serialize)]
+pub struct BoardColumn {
+    #[serde(flatten)]
+    pub status: ListStatus,
+    pub count: usize,
+    pub items: Vec<TodoItem>
+}
+
+// PUT /todos/{list_id}/items/{item_id}/notes. null이나 빈 문자열이면 지운다
+#[derive(Deserialize)]
+pub struct ItemNotes {
+    pub notes: Option<String>
+}
+
+impl ItemNotes {
+    pub fn validate(&self) -> Result<Option<&str>, AppError> {
+        match self.notes.as_deref() {
+            Some(notes) if notes.chars().count() > 10000 => Err(AppError::validation("notes must be at most 10000 characters")),
+            Some(notes) if !notes.trim().is_empty() => Ok(Some(notes)),
+            _ => Ok(None)
+        }
+    }
+}
+
+// item의 댓글. 삭제된 댓글은 답글이 있을 때만 body 없이 스레드에 남는다
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="item_comment")]
+pub struct Comment {
+    pub id: i32,
+    pub item_id: i32,
+    pub parent_id: Option<i32>,
+    pub author: String,
+    pub body: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>
+}
+
+// GET /todos/{list_id}/items/{item_id}/comments 의 댓글 하나와 그 답글들
+#[derive(Serialize)]
+pub struct CommentNode {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentNode>
+}
+
+impl CommentNode {
+    // id(작성) 순으로 정렬된 댓글 목록을 스레드로 만든다
+    // 답글이 하나도 남지 않은 삭제된 댓글은 보여주지 않는다
+    pub fn threads(comments: Vec<Comment>) -> Vec<CommentNode> {
+        let mut replies: HashMap<i32, Vec<Comment>> = HashMap::new();
+        let mut roots = vec![];
+
+        for comment in comments {
+            match comment.parent_id {
+                Some(parent_id) => replies.entry(parent_id).or_default().push(comment),
+                None => roots.push(comment)
+            }
+        }
+
+        roots.into_iter().filter_map(|comment| CommentNode::build(comment, &mut replies)).collect()
+    }
+
+    fn build(comment: Comment, replies: &mut HashMap<i32, Vec<Comment>>) -> Option<CommentNode> {
+        let nodes: Vec<CommentNode> = replies.remove(&comment.id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|reply| CommentNode::build(reply, replies))
+            .collect();
+
+        if comment.deleted_at.is_some() && nodes.is_empty() {
+            return None;
+        }
+
+        Some(CommentNode { comment, replies: nodes })
+    }
+}
+
+// POST /todos/{list_id}/items/{item_id}/comments. parent_id가 있으면 그 댓글의 답글
+// PUT .../comments/{comment_id} 에서는 body만 바꿀 수 있다
+#[derive(Deserialize)]
+pub struct CommentRequest {
+    pub body: String,
+    pub parent_id: Option<i32>
+}
+
+impl CommentRequest {
+    pub fn validate(&self) -> Result<&str, AppError> {
+        let body = self.body.trim();
+        if body.is_empty() || body.chars().count() > 5000 {
+            return Err(AppError::validation("body must be 1 to 5000 characters"));
+        }
+
+        Ok(body)
+    }
+}
+
+// GET /me/notifications 의 알림 하나
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="notification")]
+pub struct Notification {
+    pub id: i64,
+    pub kind: String,
+    pub actor: String,
+    pub list_id: Option<i32>,
+    pub item_id: Option<i32>,
+    pub comment_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>
+}
+
+// GET /me/notifications?unread=true&limit=50. 최근 알림부터
+#[derive(Deserialize)]
+pub struct NotificationsQuery {
+    #[serde(default)]
+    pub unread: bool,
+    pub limit: Option<i64>
+}
+
+impl NotificationsQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 200)
+    }
+}
+
+// POST /me/notifications/read. 읽음으로 바뀐 알림 수
+#[derive(Serialize)]
+pub struct ReadNotifications {
+    pub read: u64
+}
+
+// GET /todos/{list_id}/items/{item_id}/dependencies
+// blocked_by: 이 item보다 먼저 끝내야 하는 item, blocking: 이 item을 기다리는 item. 다른 list의 item일 수 있다
+#[derive(Serialize)]
+pub struct ItemDependencies {
+    pub item_id: i32,
+    pub ready: bool,
+    pub blocked_by: Vec<TodoItem>,
+    pub blocking: Vec<TodoItem>
+}
+
+// POST /todos/{list_id}/items/{item_id}/dependencies. {"blocked_by": 12}
+#[derive(Deserialize)]
+pub struct AddDependency {
+    pub blocked_by: i32
+}
+
+// GET /todos/{list_id}/dependencies. list의 item과, 그 item과 이어진 다른 list의 item
+#[derive(Serialize)]
+pub struct DependencyGraph {
+    pub list_id: i32,
+    pub nodes: Vec<DependencyNode>,
+    pub edges: Vec<DependencyEdge>
+}
+
+#[derive(Serialize)]
+pub struct DependencyNode {
+    pub id: i32,
+    pub list_id: i32,
+    pub title: String,
+    pub checked: bool,
+    pub status: String,
+    pub ready: bool
+}
+
+// item_id는 blocked_by가 체크될 때까지 기다린다
+#[derive(Serialize)]
+pub struct DependencyEdge {
+    pub item_id: i32,
+    pub blocked_by: i32
+}
+
+// POST /auth/token. Authorization: Bearer <token>으로 쓴다
+#[derive(Serialize)]
+pub struct IssuedToken {
+    pub user: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>
+}
+
+// item의 담당자. 담당자는 사용자 이름
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="item_assignee")]
+pub struct Assignee {
+    pub assignee: String,
+    pub assigned_by: String,
+    pub assigned_at: DateTime<Utc>
+}
+
+// PUT /todos/{list_id}/items/{item_id}/assignees. {"assignees": ["alice", "bob"]}로 담당자를 바꾼다 (빈 배열이면 모두 뺀다)
+#[derive(Deserialize)]
+pub struct SetAssignees {
+    pub assignees: Vec<String>
+}
+
+impl SetAssignees {
+    // 앞뒤 공백을 빼고, 중복 없이 나온 순서대로
+    pub fn names(&self) -> Result<Vec<String>, AppError> {
+        if self.assignees.len() > 20 {
+            return Err(AppError::validation("An item can have at most 20 assignees"));
+        }
+
+        let mut names: Vec<String> = vec![];
+        for name in &self.assignees {
+            let name = name.trim();
+            if name.is_empty() || name.chars().count() > 150 || name.chars().any(char::is_control) {
+                return Err(AppError::validation("assignees must be 1 to 150 characters"));
+            }
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+
+        Ok(names)
+    }
+}
+
+// list의 멤버. 멤버가 없는 list는 모두에게 공유된다
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="list_member")]
+pub struct ListMember {
+    pub list_id: i32,
+    pub member: String,
+    pub role: String,
+    pub added_by: String,
+    pub added_at: DateTime<Utc>
+}
+
+// PUT /todos/{list_id}/members/{member}. {"role": "editor"}
+#[derive(Deserialize)]
+pub struct SetMemberRole {
+    pub role: String
+}
+
+impl SetMemberRole {
+    pub fn validate(&self) -> Result<&str, AppError> {
+        match self.role.as_str() {
+            "owner" | "editor" | "viewer" => Ok(&self.role),
+            role => Err(AppError::validation(format!("Unknown role {}, use owner, editor or viewer", role)))
+        }
+    }
+
+    // 주소에 들어온 멤버 이름. 담당자 이름과 같은 규칙
+    pub fn member(name: &str) -> Result<&str, AppError> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 150 || name.chars().any(char::is_control) {
+            return Err(AppError::validation("member must be 1 to 150 characters"));
+        }
+        Ok(name)
+    }
+}
+
+// item의 첨부 파일. 내용은 GET .../attachments/{attachment_id} 로 내려받는다
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="attachment")]
+pub struct Attachment {
+    pub id: i32,
+    pub item_id: i32,
+    // 저장소의 key. API 응답에는 포함하지 않음
+    #[serde(skip)]
+    pub storage_key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String,
+    pub uploaded_by: String,
+    pub created_at: DateTime<Utc>
+}
+
+// 저장소에 올린 뒤 DB에 기록할 첨부 파일
+pub struct NewAttachment {
+    pub storage_key: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String
+}
+
+// PUT /todos/{list_id}/items/{item_id}/parent
+// {"parent_id": 3}이면 3의 하위 item으로, null이면 최상위로 옮긴다
+#[derive(Deserialize)]
+pub struct ItemParent {
+    pub parent_id: Option<i32>
+}
+
+// PUT /todos/{list_id}/items/{item_id}/schedule
+// 시간은 시간대가 있는 RFC 3339 형식 (ex. 2024-01-01T09:00:00+09:00). null이면 지운다
+#[derive(Deserialize, Default)]
+pub struct ItemSchedule {
+    pub due_at: Option<DateTime<Utc>>,
+    pub remind_at: Option<DateTime<Utc>>
+}
+
+// PUT /todos/{list_id}/items/{item_id}/recurrence
+// {"rule": "FREQ=WEEKLY;BYDAY=MO,WE", "tz": "Asia/Seoul"}. rule이 null이면 반복하지 않음
+// tz는 요일과 날짜를 정하는 기준 (기본 UTC)
+#[derive(Deserialize)]
+pub struct ItemRecurrence {
+    pub rule: Option<String>,
+    pub tz: Option<String>
+}
+
+// 반복 item을 완료한 기록 한 줄
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="item_completion")]
+pub struct ItemCompletion {
+    pub id: i64,
+    pub series_id: i32,
+    pub item_id: i32,
+    pub list_id: i32,
+    pub title: String,
+    pub due_at: Option<DateTime<Utc>>,
+    pub completed_at: DateTime<Utc>,
+    pub completed_by: String
+}
+
+// GET /agenda?from=2024-01-01&days=7&tz=Asia/Seoul&list_id=3
+// 하루의 경계는 tz(IANA 시간대 이름, 기본 UTC)를 기준으로 나눈다. from을 생략하면 tz 기준 오늘
+#[derive(Deserialize)]
+pub struct AgendaQuery {
+    pub from: Option<NaiveDate>,
+    pub days: Option<i32>,
+    pub tz: Option<String>,
+    pub list_id: Option<i32>
+}
+
+impl AgendaQuery {
+    pub fn days(&self) -> i32 {
+        self.days.unwrap_or(7).clamp(1, 62)
+    }
+
+    pub fn tz(&self) -> &str {
+        self.tz.as_deref().unwrap_or("UTC")
+    }
+}
+
+// 같은 날 마감인 item들
+#[derive(Serialize)]
+pub struct AgendaDay {
+    pub date: NaiveDate,
+    pub items: Vec<TodoItem>
+}
+
+// overdue: from 전에 마감이 지났는데 아직 체크되지 않은 item들. days: item이 있는 날만, 날짜 순
+#[derive(Serialize)]
+pub struct Agenda {
+    pub timezone: String,
+    pub from: NaiveDate,
+    pub days_count: i32,
+    pub overdue: Vec<TodoItem>,
+    pub days: Vec<AgendaDay>
+}
+
+// GET /todos/{list_id}/export?format=markdown
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub format: Format
+}
+
+// POST /todos/import?format=todotxt&title=장보기
+// title은 파일 안에 제목이 없을 때(todo.txt, csv) 사용. 둘 다 있으면 title이 우선
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    pub format: Format,
+    pub title: Option<String>
+}
+
+// 가져오기 실패. errors에 줄 번호별 이유가 들어있다
+#[derive(Serialize)]
+pub struct ImportErrorResponse {
+    pub error: String,
+    pub errors: Vec<ParseError>
+}
+
+// GET /search 결과의 종류
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    List,
+    Item
+}
+
+impl SearchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchKind::List => "list",
+            SearchKind::Item => "item"
+        }
+    }
+}
+
+// GET /search?q=장보기&type=item&checked=false&list_id=3&limit=20&offset=40
+// checked를 주면 item만 찾는다 (list에는 checked가 없으므로)
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(rename = "type")]
+    pub kind: Option<SearchKind>,
+    pub checked: Option<bool>,
+    pub list_id: Option<i32>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+impl SearchQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(20).clamp(1, 100)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    // 검색어를 나눈 단어들. DB의 todo_search_terms와 같이 공백과 기호로 나누고 소문자로 바꾼다
+    // 실제 검색은 DB에서 하고, 여기서는 검색어를 확인하고 snippet에 표시할 단어를 찾는 데에만 쓴다
+    pub fn terms(&self) -> Result<Vec<String>, AppError> {
+        if self.q.chars().count() > 200 {
+            return Err(AppError::validation("q must be at most 200 characters"));
+        }
+
+        let terms: Vec<String> = self.q
+            .split(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+            .filter(|term| !term.is_empty())
+            .map(|term| term.to_lowercase())
+            .collect();
+
+        if terms.is_empty() {
+            return Err(AppError::validation("q must contain at least one letter or digit"));
+        }
+
+        Ok(terms)
+    }
+}
+
+// 검색 결과 하나. list와 item이 섞여 있으며 type으로 구분한다
+// snippet은 title에서 검색어로 시작하는 단어를 <mark>로 감싼 것 (title의 <, >, &는 escape 되어 있음)
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="todo_item")]
+pub struct SearchResult {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: i32,
+    pub list_id: i32,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checked: Option<bool>,
+    pub updated_at: DateTime<Utc>,
+    pub rank: f32,
+    pub snippet: String
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub results: Vec<SearchResult>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,7 +789,337 @@ pub struct CreateTodoList {
     pub title: String,
 }
 
+// 사용자마다 따로 만드는 태그
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="tag")]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    pub color: String,
+    pub created_at: DateTime<Utc>
+}
+
+// POST /tags, PUT /tags/{tag_id}. 수정할 때 없는 값은 그대로 둔다
+#[derive(Deserialize)]
+pub struct TagRequest {
+    pub name: Option<String>,
+    pub color: Option<String>
+}
+
+impl TagRequest {
+    // (이름, 색). 확인한 값만 돌려준다
+    pub fn validate(&self) -> Result<(Option<String>, Option<String>), AppError> {
+        let name = self.name.as_deref().map(tag_name).transpose()?;
+
+        let color = match &self.color {
+            Some(color) if color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit()) => Some(color.to_lowercase()),
+            Some(color) => return Err(AppError::validation(format!("Invalid color {}, use #rrggbb", color))),
+            None => None
+        };
+
+        Ok((name, color))
+    }
+}
+
+// PUT /todos/{list_id}/tags, PUT /todos/{list_id}/items/{item_id}/tags
+// {"tags": ["backend", "infra"]}로 요청한 사용자의 태그를 바꾼다. 없는 태그는 만든다
+#[derive(Deserialize)]
+pub struct SetTags {
+    pub tags: Vec<String>
+}
+
+impl SetTags {
+    pub fn names(&self) -> Result<Vec<String>, AppError> {
+        if self.tags.len() > 20 {
+            return Err(AppError::validation("At most 20 tags can be set at once"));
+        }
+
+        let mut names: Vec<String> = vec![];
+        for name in &self.tags {
+            let name = tag_name(name)?;
+            if !names.iter().any(|other| other.to_lowercase() == name.to_lowercase()) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+fn tag_name(name: &str) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 50 || name.contains(',') {
+        return Err(AppError::validation(format!("Invalid tag name {}, use 1 to 50 characters without commas", name)));
+    }
+    Ok(name.to_string())
+}
+
+// POST /todos/{list_id}/items:move, POST /todos/{list_id}/items:copy
+// item_ids의 item들을 (하위 item도 함께) to_list_id의 list 맨 뒤로 옮기거나 복사한다
+#[derive(Deserialize)]
+pub struct TransferItems {
+    pub item_ids: Vec<i32>,
+    pub to_list_id: i32,
+    // 복사할 때 체크를 모두 풀지
+    #[serde(default)]
+    pub reset_checked: bool
+}
+
+// POST /todos/{list_id}/duplicate
+// title이 없으면 원래 list의 title. title과 item title의 {{name}}은 variables의 값으로 바꾼다
+#[derive(Deserialize)]
+pub struct DuplicateTodo {
+    pub title: Option<String>,
+    #[serde(default)]
+    pub reset_checked: bool,
+    #[serde(default)]
+    pub variables: HashMap<String, String>
+}
+
+// PUT /todos/{list_id}/template
+#[derive(Deserialize)]
+pub struct TodoTemplate {
+    pub is_template: bool
+}
+
+// GET /trash
+#[derive(Serialize)]
+pub struct Trash {
+    pub lists: Vec<TodoList>,
+    pub items: Vec<TodoItem>
+}
+
+// 감사 로그 한 줄. before/after는 바뀌기 전/후의 row 전체를 json으로 저장한 것
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="audit_log")]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub entity: String,
+    pub entity_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub request_id: Option<String>
+}
+
+// GET /audit?actor=..&entity=todo_item&entity_id=3&since=2024-01-01T00:00:00Z
+// 다음 페이지는 마지막으로 받은 id를 before_id로 넘기면 된다
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub entity: Option<String>,
+    pub entity_id: Option<i32>,
+    pub request_id: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>
+}
+
+impl AuditQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(100).clamp(1, 1000)
+    }
+}
+
+// POST /todos/{list_id}/items:batch
+// atomic(기본): 하나라도 실패하면 전부 취소. partial: 실패한 연산만 취소하고 나머지는 반영
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    #[default]
+    Atomic,
+    Partial
+}
+
+// 연산 하나. version은 If-Match와 같다 (숫자, 또는 "*"이면 확인하지 않음)
+// ex) {"op": "check", "item_id": 3, "version": 2}
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Create {
+        title: String,
+        #[serde(default)]
+        parent_id: Option<i32>,
+        #[serde(default)]
+        due_at: Option<DateTime<Utc>>,
+        #[serde(default)]
+        remind_at: Option<DateTime<Utc>>
+    },
+    Update { item_id: i32, title: String, version: Option<serde_json::Value> },
+    Check { item_id: i32, version: Option<serde_json::Value> },
+    Uncheck { item_id: i32, version: Option<serde_json::Value> },
+    Delete { item_id: i32 },
+    // due_at, remind_at을 함께 바꾼다. 생략하거나 null이면 지움
+    Schedule {
+        item_id: i32,
+        #[serde(default)]
+        due_at: Option<DateTime<Utc>>,
+        #[serde(default)]
+        remind_at: Option<DateTime<Utc>>,
+        version: Option<serde_json::Value>
+    },
+    // list의 체크되지 않은 item을 모두 체크
+    CheckAll,
+    // 체크된 item을 모두 휴지통으로
+    ClearCompleted
+}
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub mode: BatchMode,
+    pub operations: Vec<BatchOperation>
+}
+
+impl BatchRequest {
+    // 트랜잭션이 너무 길어지지 않도록 한 번에 최대 500개
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.operations.is_empty() || self.operations.len() > 500 {
+            return Err(AppError::validation("operations must contain 1 to 500 operations"));
+        }
+        Ok(())
+    }
+}
+
+// 연산마다 하나씩. status는 같은 요청을 REST로 따로 보냈다면 받았을 HTTP 상태 코드
+// success가 false이면 바뀐 것이 없다는 뜻 (ex. 이미 체크된 item을 check)
+#[derive(Serialize)]
+pub struct BatchResult {
+    pub index: usize,
+    pub status: u16,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item: Option<TodoItem>,
+    // check_all, clear_completed로 바뀐 item 수
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // 로그에만 남기고 응답에는 포함하지 않음
+    #[serde(skip)]
+    pub cause: Option<String>
+}
+
+// committed가 false이면 (atomic에서 실패) 아무것도 반영되지 않았다
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub committed: bool,
+    pub results: Vec<BatchResult>
+}
+
+// 캘린더 피드. GET /calendar/{token}.ics 로 구독한다
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="calendar_feed")]
+pub struct CalendarFeed {
+    pub id: i32,
+    pub token: String,
+    pub owner: String,
+    pub list_id: Option<i32>,
+    pub created_at: DateTime<Utc>
+}
+
+// list_id를 생략하면 모든 list
+#[derive(Deserialize)]
+pub struct CreateCalendarFeed {
+    pub list_id: Option<i32>
+}
+
+// Idempotency-Key로 이미 받은 요청. status_code가 없으면 아직 처리 중
+#[derive(PostgresMapper)]
+#[pg_mapper(table="idempotency_key")]
+pub struct IdempotencyRecord {
+    pub fingerprint: String,
+    pub status_code: Option<i32>,
+    pub content_type: Option<String>,
+    pub response: Option<Vec<u8>>
+}
+
+// 웹훅 구독. list_id가 없으면 모든 list의 이벤트를 받는다
+// secret은 만들 때 한 번만 돌려주고 이후 조회에서는 보여주지 않는다
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="webhook_subscription")]
+pub struct Webhook {
+    pub id: i32,
+    pub owner: String,
+    pub list_id: Option<i32>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub list_id: Option<i32>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    // 없으면 서버가 만든다
+    pub secret: Option<String>
+}
+
+// 웹훅 전송 기록 (outbox). status는 pending, delivered, dead(재시도 횟수 초과)
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table="webhook_delivery")]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub subscription_id: i32,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>
+}
+
+// 전송할 차례가 된 웹훅. 구독의 url, secret과 함께 가져온다
+#[derive(PostgresMapper)]
+#[pg_mapper(table="webhook_delivery")]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String
+}
+
+// GET /webhooks/{id}/deliveries?status=dead&limit=50
+#[derive(Deserialize)]
+pub struct DeliveriesQuery {
+    pub status: Option<String>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>
+}
+
+impl DeliveriesQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(100).clamp(1, 1000)
+    }
+}
+
 #[derive(Serialize)]
 pub struct ResultResponse {
     pub success: bool
+}
+
+// statement cache 적중률 확인용. GET /metrics/statements
+#[derive(Serialize)]
+pub struct StatementCacheStats {
+    pub enabled: bool,
+    pub hits: u64,
+    pub misses: u64
 }
\ No newline at end of file
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// 누가 어떤 list를 보고 있는지(presence)를 서버끼리 주고받는 NOTIFY 채널
+pub const CHANNEL: &str = "todo_presence";
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceState {
+    Join,
+    // 연결이 살아있다는 신호. 일정 시간 동안 오지 않으면 나간 것으로 본다 (서버가 죽은 경우 대비)
+    Here,
+    Leave
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PresenceSignal {
+    pub list_id: i32,
+    pub connection_id: String,
+    pub user: String,
+    pub state: PresenceState
+}
+
+struct Viewer {
+    user: String,
+    last_seen: Instant
+}
+
+// 모든 서버에서 온 presence 신호를 모아서 list별 접속자 목록을 관리한다.
+// 목록이 바뀌면 list_id를 broadcast 해서 해당 list의 WebSocket 연결들이 새 목록을 보내도록 함
+pub struct PresenceHub {
+    // list_id -> (connection_id -> viewer)
+    viewers: Mutex<HashMap<i32, HashMap<String, Viewer>>>,
+    sender: broadcast::Sender<i32>,
+    ttl: Duration
+}
+
+impl PresenceHub {
+    pub fn new(ttl: Duration) -> Self {
+        let (sender, _) = broadcast::channel(256);
+
+        PresenceHub {
+            viewers: Mutex::new(HashMap::new()),
+            sender,
+            ttl
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<i32> {
+        self.sender.subscribe()
+    }
+
+    pub fn apply(&self, signal: PresenceSignal) {
+        let changed = {
+            let mut viewers = self.viewers.lock().unwrap();
+            let before = users_of(viewers.get(&signal.list_id));
+            let list = viewers.entry(signal.list_id).or_default();
+
+            match signal.state {
+                PresenceState::Join | PresenceState::Here => {
+                    list.insert(signal.connection_id, Viewer { user: signal.user, last_seen: Instant::now() });
+                },
+                PresenceState::Leave => {
+                    list.remove(&signal.connection_id);
+                }
+            }
+
+            if list.is_empty() {
+                viewers.remove(&signal.list_id);
+            }
+
+            before != users_of(viewers.get(&signal.list_id))
+        };
+
+        if changed {
+            let _ = self.sender.send(signal.list_id);
+        }
+    }
+
+    // ttl 동안 Here 신호가 없던 연결을 정리한다
+    pub fn expire(&self) {
+        let mut changed = vec![];
+
+        {
+            let mut viewers = self.viewers.lock().unwrap();
+            for (list_id, list) in viewers.iter_mut() {
+                let before = list.len();
+                list.retain(|_, viewer| viewer.last_seen.elapsed() < self.ttl);
+                if list.len() != before {
+                    changed.push(*list_id);
+                }
+            }
+            viewers.retain(|_, list| !list.is_empty());
+        }
+
+        for list_id in changed {
+            let _ = self.sender.send(list_id);
+        }
+    }
+
+    // 같은 사용자가 여러 탭으로 접속해도 한 번만 보여준다
+    pub fn users(&self, list_id: i32) -> Vec<String> {
+        users_of(self.viewers.lock().unwrap().get(&list_id))
+    }
+}
+
+fn users_of(list: Option<&HashMap<String, Viewer>>) -> Vec<String> {
+    let mut users = list
+        .map(|list| list.values().map(|viewer| viewer.user.clone()).collect::<Vec<String>>())
+        .unwrap_or_default();
+
+    users.sort();
+    users.dedup();
+    users
+}
@@ -0,0 +1,199 @@
+use crate::errors::AppError;
+use chrono::{Datelike, Duration, Months, NaiveDateTime, Weekday};
+
+// 반복 규칙. iCalendar의 RRULE(RFC 5545) 중 일부만 지원한다
+// - FREQ=DAILY;INTERVAL=3          3일마다
+// - FREQ=WEEKLY;BYDAY=MO,WE,FR     매주 월, 수, 금
+// - FREQ=WEEKLY;INTERVAL=2         2주마다 (같은 요일)
+// - FREQ=MONTHLY                   매달 같은 날 (31일처럼 그 달에 없는 날은 건너뜀)
+#[derive(Debug, PartialEq)]
+pub struct Recurrence {
+    pub freq: Frequency,
+    pub interval: u32,
+    // WEEKLY에서만 사용. 비어 있으면 기준 날짜의 요일
+    pub by_day: Vec<Weekday>
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly
+}
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("MO", Weekday::Mon), ("TU", Weekday::Tue), ("WE", Weekday::Wed), ("TH", Weekday::Thu),
+    ("FR", Weekday::Fri), ("SA", Weekday::Sat), ("SU", Weekday::Sun)
+];
+
+impl Recurrence {
+    // "RRULE:" 으로 시작해도 되고, 순서와 대소문자는 상관없다
+    pub fn parse(rule: &str) -> Result<Self, AppError> {
+        let rule = rule.trim();
+        let rule = rule.strip_prefix("RRULE:").or_else(|| rule.strip_prefix("rrule:")).unwrap_or(rule);
+
+        let mut freq = None;
+        let mut interval = 1;
+        let mut by_day = vec![];
+
+        for part in rule.split(';').filter(|part| !part.trim().is_empty()) {
+            let (name, value) = part.split_once('=')
+                .ok_or_else(|| AppError::validation(format!("Invalid recurrence rule part {}", part)))?;
+            let value = value.trim().to_ascii_uppercase();
+
+            match name.trim().to_ascii_uppercase().as_str() {
+                "FREQ" => freq = Some(match value.as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    _ => return Err(AppError::validation(format!("Unsupported FREQ {}, use DAILY, WEEKLY or MONTHLY", value)))
+                }),
+                "INTERVAL" => interval = value.parse::<u32>()
+                    .ok()
+                    .filter(|interval| (1..=365).contains(interval))
+                    .ok_or_else(|| AppError::validation("INTERVAL must be 1 to 365"))?,
+                "BYDAY" => for day in value.split(',') {
+                    let weekday = WEEKDAYS.iter()
+                        .find(|(name, _)| *name == day.trim())
+                        .map(|(_, weekday)| *weekday)
+                        .ok_or_else(|| AppError::validation(format!("Invalid BYDAY value {}", day)))?;
+                    if !by_day.contains(&weekday) {
+                        by_day.push(weekday);
+                    }
+                },
+                other => return Err(AppError::validation(format!("Unsupported recurrence rule part {}", other)))
+            }
+        }
+
+        let freq = freq.ok_or_else(|| AppError::validation("Recurrence rule must have FREQ"))?;
+        if !by_day.is_empty() && freq != Frequency::Weekly {
+            return Err(AppError::validation("BYDAY is only supported with FREQ=WEEKLY"));
+        }
+        by_day.sort_by_key(|weekday| weekday.num_days_from_monday());
+
+        Ok(Recurrence { freq, interval, by_day })
+    }
+
+    // 저장, 응답에 쓰는 형태. 같은 규칙이면 항상 같은 문자열
+    pub fn to_rule(&self) -> String {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY"
+        };
+
+        let mut rule = format!("FREQ={}", freq);
+        if self.interval > 1 {
+            rule.push_str(&format!(";INTERVAL={}", self.interval));
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter()
+                .filter_map(|weekday| WEEKDAYS.iter().find(|(_, day)| day == weekday).map(|(name, _)| *name))
+                .collect();
+            rule.push_str(&format!(";BYDAY={}", days.join(",")));
+        }
+
+        rule
+    }
+
+    // base 다음의 발생 시간. 시간(시:분)은 그대로 두고 날짜만 옮긴다
+    // 시간은 모두 item의 시간대 기준 시각 (UTC로의 변환은 db에서)
+    pub fn next_after(&self, base: NaiveDateTime) -> NaiveDateTime {
+        let interval = self.interval as i64;
+
+        match self.freq {
+            Frequency::Daily => base + Duration::days(interval),
+            Frequency::Weekly if self.by_day.is_empty() => base + Duration::weeks(interval),
+            Frequency::Weekly => {
+                // 기준 날짜가 속한 주(월요일 시작)부터 INTERVAL 주마다 돌아오는 주의 요일들만 해당
+                let week_start = |time: NaiveDateTime| time.date() - Duration::days(time.weekday().num_days_from_monday() as i64);
+                let base_week = week_start(base);
+
+                (1..=7 * (interval + 1))
+                    .map(|days| base + Duration::days(days))
+                    .find(|next| {
+                        let weeks = (week_start(*next) - base_week).num_days() / 7;
+                        weeks % interval == 0 && self.by_day.contains(&next.weekday())
+                    })
+                    .unwrap_or(base + Duration::weeks(interval))
+            },
+            Frequency::Monthly => {
+                // 그 날이 없는 달(ex. 2월 30일)은 건너뛴다. 12번 안에는 항상 찾을 수 있음
+                (1..=12)
+                    .filter_map(|count| base.checked_add_months(Months::new(self.interval * count)))
+                    .find(|next| next.day() == base.day())
+                    .unwrap_or(base + Duration::days(30 * interval))
+            }
+        }
+    }
+
+    // 마감이 많이 지난 뒤에 체크해도 다음 발생 시간은 now 이후가 되도록 건너뛴다
+    pub fn next_after_now(&self, base: NaiveDateTime, now: NaiveDateTime) -> NaiveDateTime {
+        let mut next = self.next_after(base);
+        while next <= now {
+            next = self.next_after(next);
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_accepts_prefix_and_any_order() {
+        let rule = Recurrence::parse("rrule:byday=fr,mo,FR;Interval=2;FREQ=WEEKLY").unwrap();
+
+        assert_eq!(rule, Recurrence { freq: Frequency::Weekly, interval: 2, by_day: vec![Weekday::Mon, Weekday::Fri] });
+        assert_eq!(Recurrence::parse(&rule.to_rule()).unwrap(), rule);
+    }
+
+    #[test]
+    fn parse_rejects_bad_rules() {
+        for rule in ["INTERVAL=2", "FREQ=YEARLY", "FREQ=DAILY;INTERVAL=0", "FREQ=DAILY;INTERVAL=366", "FREQ=DAILY;BYDAY=MO", "FREQ=WEEKLY;BYDAY=XX", "FREQ=DAILY;COUNT=3"] {
+            assert!(Recurrence::parse(rule).is_err(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn daily_and_weekly_step_by_interval() {
+        assert_eq!(Recurrence::parse("FREQ=DAILY;INTERVAL=3").unwrap().next_after(at(2024, 2, 28)), at(2024, 3, 2));
+        assert_eq!(Recurrence::parse("FREQ=WEEKLY").unwrap().next_after(at(2024, 1, 3)), at(2024, 1, 10));
+    }
+
+    #[test]
+    fn weekly_by_day_picks_next_listed_day() {
+        // 2024-01-03은 수요일
+        let rule = Recurrence::parse("FREQ=WEEKLY;BYDAY=MO,FR").unwrap();
+        assert_eq!(rule.next_after(at(2024, 1, 3)), at(2024, 1, 5));
+        assert_eq!(rule.next_after(at(2024, 1, 5)), at(2024, 1, 8));
+
+        // 격주면 마지막 요일 다음은 2주 뒤 첫 요일
+        let rule = Recurrence::parse("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR").unwrap();
+        assert_eq!(rule.next_after(at(2024, 1, 5)), at(2024, 1, 15));
+    }
+
+    #[test]
+    fn monthly_skips_months_without_the_day() {
+        let rule = Recurrence::parse("FREQ=MONTHLY").unwrap();
+
+        // 2월 30일은 없으니 3월 30일
+        assert_eq!(rule.next_after(at(2024, 1, 30)), at(2024, 3, 30));
+        assert_eq!(rule.next_after(at(2024, 1, 31)), at(2024, 3, 31));
+        assert_eq!(rule.next_after(at(2024, 1, 15)), at(2024, 2, 15));
+    }
+
+    #[test]
+    fn next_after_now_skips_past_occurrences() {
+        let rule = Recurrence::parse("FREQ=DAILY").unwrap();
+
+        assert_eq!(rule.next_after_now(at(2024, 1, 1), at(2024, 1, 10)), at(2024, 1, 11));
+        assert_eq!(rule.next_after_now(at(2024, 1, 20), at(2024, 1, 10)), at(2024, 1, 21));
+    }
+}
@@ -0,0 +1,292 @@
+use crate::config::AttachmentsConfig;
+use actix_web::web::Bytes;
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{FutureExt, StreamExt, TryStreamExt};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+// 첨부 파일의 내용(blob)을 저장하는 곳. ATTACHMENTS.STORAGE로 고른다
+// - local: 서버의 디렉터리 (기본)
+// - s3: S3 호환 저장소 (AWS S3, MinIO 등)
+// 파일 이름, 크기 같은 정보는 DB(attachment)에 있고, 여기에는 key로 내용만 저장한다
+pub type BlobStream = BoxStream<'static, io::Result<Bytes>>;
+
+// dyn Storage로 쓰기 위해 async fn 대신 BoxFuture를 돌려준다
+pub trait Storage: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, data: Bytes, content_type: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    // range는 (처음, 끝) byte 위치. 끝도 포함한다. None이면 전체
+    fn get<'a>(&'a self, key: &'a str, range: Option<(u64, u64)>) -> BoxFuture<'a, io::Result<BlobStream>>;
+
+    // 이미 없는 blob을 지우는 것도 성공
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+pub fn from_config(config: &AttachmentsConfig) -> Result<Arc<dyn Storage>, String> {
+    match config.storage.as_str() {
+        "local" => Ok(Arc::new(LocalStorage { root: PathBuf::from(&config.dir) })),
+        "s3" => S3Storage::new(config).map(|storage| Arc::new(storage) as Arc<dyn Storage>),
+        other => Err(format!("Unknown ATTACHMENTS.STORAGE {}, use local or s3", other))
+    }
+}
+
+// 한 번에 읽어서 보내는 크기
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// key를 그대로 경로로 쓴다. key는 서버가 만든 값이므로(attachments::storage_key) 디렉터리 밖을 가리키지 않는다
+pub struct LocalStorage {
+    root: PathBuf
+}
+
+impl Storage for LocalStorage {
+    // 임시 파일에 다 쓴 뒤 이름을 바꿔서, 쓰는 도중의 파일을 읽는 일이 없도록 한다
+    fn put<'a>(&'a self, key: &'a str, data: Bytes, _content_type: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let path = self.root.join(key);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let temp = path.with_extension("part");
+            let mut file = tokio::fs::File::create(&temp).await?;
+            file.write_all(&data).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp, &path).await
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, key: &'a str, range: Option<(u64, u64)>) -> BoxFuture<'a, io::Result<BlobStream>> {
+        async move {
+            let mut file = tokio::fs::File::open(self.root.join(key)).await?;
+            let (start, length) = match range {
+                Some((start, end)) => (start, end - start + 1),
+                None => (0, file.metadata().await?.len())
+            };
+            file.seek(SeekFrom::Start(start)).await?;
+
+            // 남은 길이만큼 CHUNK_SIZE씩 읽어서 보낸다
+            let chunks = stream::try_unfold((file.take(length), vec![0u8; CHUNK_SIZE]), |(mut file, mut buffer)| async move {
+                let read = file.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                let chunk = Bytes::copy_from_slice(&buffer[..read]);
+                Ok(Some((chunk, (file, buffer))))
+            });
+
+            Ok(chunks.boxed())
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            match tokio::fs::remove_file(self.root.join(key)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(())
+            }
+        }.boxed()
+    }
+}
+
+// S3 호환 저장소. MinIO처럼 bucket 이름을 경로에 넣는 방식(path-style)으로 요청한다
+// ex) ATTACHMENTS.S3_ENDPOINT=http://localhost:9000 이면 http://localhost:9000/{bucket}/{key}
+// 요청은 AWS Signature Version 4로 서명한다
+pub struct S3Storage {
+    http: reqwest::Client,
+    endpoint: reqwest::Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String
+}
+
+// 빈 body의 sha256
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+impl S3Storage {
+    fn new(config: &AttachmentsConfig) -> Result<S3Storage, String> {
+        let endpoint = reqwest::Url::parse(&config.s3_endpoint)
+            .map_err(|err| format!("Invalid ATTACHMENTS.S3_ENDPOINT: {}", err))?;
+
+        if config.s3_bucket.is_empty() || config.s3_access_key.is_empty() || config.s3_secret_key.is_empty() {
+            return Err("ATTACHMENTS.S3_BUCKET, S3_ACCESS_KEY and S3_SECRET_KEY are required for s3 storage".to_string());
+        }
+
+        let http = reqwest::Client::builder()
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        Ok(S3Storage {
+            http,
+            endpoint,
+            bucket: config.s3_bucket.clone(),
+            region: config.s3_region.clone(),
+            access_key: config.s3_access_key.clone(),
+            secret_key: config.s3_secret_key.clone()
+        })
+    }
+
+    fn object_url(&self, key: &str) -> reqwest::Url {
+        let mut url = self.endpoint.clone();
+        let path = format!("{}/{}/{}", url.path().trim_end_matches('/'), uri_encode(&self.bucket), uri_encode(key));
+        url.set_path(&path);
+        url
+    }
+
+    // range는 서명에 포함되는 헤더이므로 여기서 함께 넣는다
+    fn request(&self, method: reqwest::Method, key: &str, payload_hash: &str, range: Option<(u64, u64)>) -> reqwest::RequestBuilder {
+        let url = self.object_url(key);
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string()
+        };
+        let range = range.map(|(start, end)| format!("bytes={}-{}", start, end));
+
+        let mut headers = vec![("host", host.as_str())];
+        if let Some(range) = &range {
+            headers.push(("range", range));
+        }
+        headers.push(("x-amz-content-sha256", payload_hash));
+        headers.push(("x-amz-date", &amz_date));
+
+        let authorization = sign_v4(&SigningRequest {
+            method: method.as_str(),
+            path: url.path(),
+            headers: &headers,
+            payload_hash,
+            amz_date: &amz_date,
+            region: &self.region,
+            access_key: &self.access_key,
+            secret_key: &self.secret_key
+        });
+
+        let mut request = self.http.request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization);
+        if let Some(range) = range {
+            request = request.header("range", range);
+        }
+        request
+    }
+}
+
+impl Storage for S3Storage {
+    fn put<'a>(&'a self, key: &'a str, data: Bytes, content_type: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let payload_hash = hex::encode(Sha256::digest(&data));
+            let response = self.request(reqwest::Method::PUT, key, &payload_hash, None)
+                .header("content-type", content_type)
+                .body(data)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            check_status(response).await.map(|_| ())
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, key: &'a str, range: Option<(u64, u64)>) -> BoxFuture<'a, io::Result<BlobStream>> {
+        async move {
+            let response = self.request(reqwest::Method::GET, key, EMPTY_SHA256, range)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            let response = check_status(response).await?;
+            Ok(response.bytes_stream().map_err(io::Error::other).boxed())
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let response = self.request(reqwest::Method::DELETE, key, EMPTY_SHA256, None)
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            match check_status(response).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(())
+            }
+        }.boxed()
+    }
+}
+
+// 실패하면 S3가 보낸 오류(xml)를 그대로 원인으로 남긴다
+async fn check_status(response: reqwest::Response) -> io::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let kind = if status == reqwest::StatusCode::NOT_FOUND { io::ErrorKind::NotFound } else { io::ErrorKind::Other };
+    Err(io::Error::new(kind, format!("S3 responded {}: {}", status, body)))
+}
+
+struct SigningRequest<'a> {
+    method: &'a str,
+    // 이미 인코딩된 경로
+    path: &'a str,
+    // 이름은 소문자, 이름순으로 정렬되어 있어야 한다
+    headers: &'a [(&'a str, &'a str)],
+    payload_hash: &'a str,
+    amz_date: &'a str,
+    region: &'a str,
+    access_key: &'a str,
+    secret_key: &'a str
+}
+
+// https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+// 쿼리 문자열은 쓰지 않으므로 비워둔다
+fn sign_v4(request: &SigningRequest) -> String {
+    let date = &request.amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, request.region);
+
+    let canonical_headers: String = request.headers.iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = request.headers.iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&str>>()
+        .join(";");
+
+    let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}",
+        request.method, request.path, canonical_headers, signed_headers, request.payload_hash);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        request.amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+    let key = hmac(format!("AWS4{}", request.secret_key).as_bytes(), date.as_bytes());
+    let key = hmac(&key, request.region.as_bytes());
+    let key = hmac(&key, b"s3");
+    let key = hmac(&key, b"aws4_request");
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+    format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        request.access_key, scope, signed_headers, signature)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// 영문, 숫자, - _ . ~ 와 경로의 / 를 뺀 나머지는 %XX로
+fn uri_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
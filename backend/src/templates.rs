@@ -0,0 +1,81 @@
+use chrono::Utc;
+use std::collections::HashMap;
+
+// list 템플릿의 title에 들어가는 변수. {{name}} 을 값으로 바꾼다
+// - 이름은 영문, 숫자, _ 만 쓸 수 있다. {{ name }} 처럼 안쪽의 공백은 무시
+// - date는 따로 주지 않으면 오늘 날짜(UTC, ex. 2024-01-31)
+pub struct Variables {
+    values: HashMap<String, String>,
+    // 값이 없어서 바꾸지 못한 변수 이름 (중복 없이, 나온 순서대로)
+    pub missing: Vec<String>
+}
+
+impl Variables {
+    pub fn new(mut values: HashMap<String, String>) -> Self {
+        values.entry("date".to_string()).or_insert_with(|| Utc::now().date_naive().to_string());
+        Variables { values, missing: vec![] }
+    }
+
+    // 값이 없는 변수는 {{name}} 그대로 남겨두고 missing에 기록한다
+    pub fn render(&mut self, text: &str) -> String {
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start + 2..].find("}}").map(|end| start + 2 + end) else {
+                break;
+            };
+
+            rendered.push_str(&rest[..start]);
+            let name = rest[start + 2..end].trim();
+            let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            match self.values.get(name) {
+                Some(value) if valid => rendered.push_str(value),
+                _ => {
+                    if valid && !self.missing.iter().any(|missing| missing == name) {
+                        self.missing.push(name.to_string());
+                    }
+                    rendered.push_str(&rest[start..end + 2]);
+                }
+            }
+
+            rest = &rest[end + 2..];
+        }
+
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> Variables {
+        Variables::new(pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+    }
+
+    #[test]
+    fn render_replaces_known_names() {
+        let mut variables = variables(&[("who", "철수"), ("date", "2024-01-31")]);
+
+        assert_eq!(variables.render("{{ who }}의 장보기 {{date}}"), "철수의 장보기 2024-01-31");
+        assert!(variables.missing.is_empty());
+    }
+
+    #[test]
+    fn render_keeps_missing_and_invalid_names() {
+        let mut variables = variables(&[]);
+
+        assert_eq!(variables.render("{{a}} {{ a }} {{b-c}} {{}} {{open"), "{{a}} {{ a }} {{b-c}} {{}} {{open");
+        assert_eq!(variables.missing, vec!["a"]);
+    }
+
+    #[test]
+    fn date_defaults_to_today() {
+        let mut variables = variables(&[]);
+
+        assert_eq!(variables.render("{{date}}"), Utc::now().date_naive().to_string());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::config::WebhooksConfig;
+use crate::errors::AppError;
+use crate::models::PendingDelivery;
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+// 구독할 수 있는 이벤트. 구독할 때 events를 비워두면 전부 받는다
+// init.pgsql의 enqueue_webhook_deliveries 트리거가 audit_log로부터 이 이름을 만든다
+pub const EVENTS: [&str; 9] = [
+    "list.created", "list.updated", "list.deleted", "list.restored",
+    "item.created", "item.updated", "item.checked", "item.deleted", "item.reminded"
+];
+
+pub fn validate_events(events: &[String]) -> Result<(), AppError> {
+    match events.iter().find(|event| !EVENTS.contains(&event.as_str())) {
+        Some(event) => Err(AppError::validation(format!("Unknown webhook event {}", event))),
+        None => Ok(())
+    }
+}
+
+// 웹훅 주소로 서버 안쪽(localhost, 사설망, 클라우드 메타데이터 169.254.169.254 등)에 요청을 보내게 만들 수 없도록
+// 주소의 host가 가리키는 IP를 모두 확인한다. 만들 때 확인하고, 보낼 때도 다시 확인한다 (그 사이 DNS가 바뀔 수 있음)
+// 보낼 때는 PublicResolver가 실제로 연결할 IP를 확인하므로 확인한 뒤 연결하기 전에 바뀌는 일도 없다
+// WEBHOOKS.ALLOWED_HOSTS에 있는 host는 확인하지 않는다 (로컬 개발용)
+pub struct HostPolicy {
+    allowed_hosts: Vec<String>
+}
+
+impl HostPolicy {
+    pub fn new(config: &WebhooksConfig) -> HostPolicy {
+        HostPolicy { allowed_hosts: config.allowed_hosts() }
+    }
+
+    fn allows(&self, host: &str) -> bool {
+        self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    pub async fn validate_url(&self, url: &str) -> Result<(), AppError> {
+        let parsed = match Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "https" || parsed.scheme() == "http" => parsed,
+            _ => return Err(AppError::validation("url must be an absolute http(s) URL"))
+        };
+
+        // IPv6는 [::1] 처럼 대괄호가 붙어 있다
+        let host = parsed.host_str()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .ok_or_else(|| AppError::validation("url must have a host"))?;
+        let port = parsed.port_or_known_default().unwrap_or(80);
+
+        self.resolve(host, port).await.map(|_| ())
+    }
+
+    // host의 IP 목록. 하나라도 공개된 주소가 아니면 거부한다
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, AppError> {
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map(|addrs| addrs.collect::<Vec<SocketAddr>>())
+            .unwrap_or_default();
+
+        if addrs.is_empty() {
+            return Err(AppError::validation(format!("url host {} could not be resolved", host)));
+        }
+        if !self.allows(host) && addrs.iter().any(|addr| !is_public(addr.ip())) {
+            return Err(AppError::validation(format!("url host {} resolves to a private or loopback address", host)));
+        }
+
+        Ok(addrs)
+    }
+}
+
+// 웹훅 HTTP client가 연결할 때 쓰는 DNS resolver
+pub struct PublicResolver(pub Arc<HostPolicy>);
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.0.clone();
+        Box::pin(async move {
+            // port는 연결할 때 주소의 port로 바뀐다
+            match policy.resolve(name.as_str(), 0).await {
+                Ok(addrs) => Ok(Box::new(addrs.into_iter()) as Addrs),
+                Err(err) => Err(err.message().into())
+            }
+        })
+    }
+}
+
+// loopback, 사설(10/8, 172.16/12, 192.168/16, fc00::/7), link-local(169.254/16, fe80::/10),
+// 0.0.0.0, CGNAT(100.64/10), broadcast, multicast는 공개된 주소가 아니다
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+            || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation()
+            || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            // ::ffff:127.0.0.1
+            Some(mapped) => is_public(IpAddr::V4(mapped)),
+            None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local()
+                || ip.is_unicast_link_local() || ip.is_multicast())
+        }
+    }
+}
+
+pub fn generate_secret() -> String {
+    format!("whsec_{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+// 받는 쪽에서 검증하는 방법:
+// X-Webhook-Signature: t=1700000000,v1=<hex>
+// v1 == HMAC-SHA256(secret, "{t}.{body}") 이고 t가 너무 오래되지 않았는지 확인
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+// 실패한 횟수가 늘어날 때마다 대기 시간을 두 배로 (최대 하루)
+pub fn backoff(base: Duration, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    base.saturating_mul(2u32.pow(exponent)).min(Duration::from_secs(24 * 60 * 60))
+}
+
+pub enum DeliveryResult {
+    Delivered(u16),
+    Failed(Option<u16>, String)
+}
+
+// 2xx 응답만 성공으로 본다
+// 만든 뒤에 WEBHOOKS.ALLOWED_HOSTS가 바뀌었거나 IP로 된 주소일 수 있으므로 보내기 전에 다시 확인한다
+pub async fn deliver(client: &reqwest::Client, policy: &HostPolicy, delivery: &PendingDelivery) -> DeliveryResult {
+    if let Err(err) = policy.validate_url(&delivery.url).await {
+        return DeliveryResult::Failed(None, err.message());
+    }
+
+    let body = serde_json::to_vec(&delivery.payload).unwrap_or_default();
+    let timestamp = chrono::Utc::now().timestamp();
+
+    let response = client.post(&delivery.url)
+        .header("content-type", "application/json")
+        .header("x-webhook-id", delivery.id.to_string())
+        .header("x-webhook-event", &delivery.event)
+        .header("x-webhook-signature", sign(&delivery.secret, timestamp, &body))
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => DeliveryResult::Delivered(response.status().as_u16()),
+        Ok(response) => DeliveryResult::Failed(
+            Some(response.status().as_u16()),
+            format!("Unexpected response status {}", response.status())
+        ),
+        Err(err) => DeliveryResult::Failed(None, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_matches_known_signature() {
+        // python -c 'import hmac,hashlib; print(hmac.new(b"whsec_test", b"1700000000.{\"id\":1}", hashlib.sha256).hexdigest())'
+        assert_eq!(
+            sign("whsec_test", 1700000000, br#"{"id":1}"#),
+            "t=1700000000,v1=2f441ba4b3b2d50d28a9ab9d9fd8880376ecd1eb5d0435401553f5d8d0a5dcf8"
+        );
+    }
+
+    #[test]
+    fn sign_depends_on_timestamp() {
+        let v1 = |timestamp| sign("whsec_test", timestamp, b"{}").split_once(",v1=").unwrap().1.to_string();
+        assert_ne!(v1(1), v1(2));
+    }
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "169.254.169.254", "100.64.0.1", "::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::AppState;
+use crate::conditional::body_version;
+use crate::context::RequestContext;
+use crate::db::{self, ListAccess};
+use crate::errors::AppError;
+use crate::events::ItemEvent;
+use crate::presence::{PresenceSignal, PresenceState};
+use actix_web::{web, ResponseError};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use serde::{Deserialize, Serialize};
+use slog::{o, info, warn, Logger};
+use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+
+// 메시지 형식이 바뀌면 올린다. 클라이언트는 모든 메시지에 "v"를 함께 보내야 함
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// 클라이언트 -> 서버
+// {"v": 1, "type": "check", "id": "c1", "item_id": 3, "version": 2}
+// version은 REST의 If-Match와 같다. "*"이면 버전을 확인하지 않음
+#[derive(Deserialize)]
+struct ClientEnvelope {
+    v: u32,
+    #[serde(flatten)]
+    message: ClientMessage
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Check { id: String, item_id: i32, version: serde_json::Value },
+    Delete { id: String, item_id: i32 },
+    Ping
+}
+
+// 서버 -> 클라이언트
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage<'a> {
+    Hello { list_id: i32, user: &'a str, connection_id: &'a str },
+    // 요청(id)이 성공했을 때. result는 REST 응답과 같은 값
+    Ack { id: &'a str, result: serde_json::Value },
+    // 요청이 실패했을 때. status는 REST였다면 받았을 HTTP 상태 코드
+    Error { id: Option<&'a str>, status: u16, error: String },
+    // 다른 사람(자신 포함)의 변경. SSE의 이벤트와 같은 값
+    Event { event: &'a ItemEvent },
+    // 이벤트를 놓쳤으니 목록을 다시 불러와야 함
+    Reset,
+    Presence { list_id: i32, users: Vec<String> },
+    Pong
+}
+
+#[derive(Serialize)]
+struct ServerEnvelope<'a> {
+    v: u32,
+    #[serde(flatten)]
+    message: ServerMessage<'a>
+}
+
+async fn send(session: &mut Session, message: ServerMessage<'_>) -> Result<(), actix_ws::Closed> {
+    let text = serde_json::to_string(&ServerEnvelope { v: PROTOCOL_VERSION, message }).unwrap_or_default();
+    session.text(text).await
+}
+
+// WebSocket 연결 하나가 끝날 때까지 도는 루프.
+// 클라이언트가 heartbeat의 3배 동안 아무것도 보내지 않으면(pong 포함) 연결을 끊는다
+pub async fn run_session(state: web::Data<AppState>, ctx: RequestContext, list_id: i32, mut session: Session, mut stream: MessageStream) {
+    let connection_id = uuid::Uuid::new_v4().to_string();
+    let log = state.log.new(o!("ws" => connection_id.clone(), "list_id" => list_id, "user" => ctx.actor.clone()));
+    let timeout = state.events_heartbeat * 3;
+
+    let mut events = state.events.subscribe();
+    let mut presence = state.presence.subscribe();
+    let mut heartbeat = actix_rt::time::interval(state.events_heartbeat);
+    let mut last_seen = Instant::now();
+
+    info!(log, "WebSocket connected");
+    announce(&state, &log, list_id, &connection_id, &ctx.actor, PresenceState::Join).await;
+
+    let hello = send(&mut session, ServerMessage::Hello { list_id, user: &ctx.actor, connection_id: &connection_id }).await;
+    let mut close_reason = None;
+
+    if hello.is_ok() {
+        close_reason = loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > timeout {
+                        warn!(log, "WebSocket client timed out");
+                        break Some(CloseReason { code: CloseCode::Away, description: Some("timeout".to_string()) });
+                    }
+                    if session.ping(b"").await.is_err() {
+                        break None;
+                    }
+                    announce(&state, &log, list_id, &connection_id, &ctx.actor, PresenceState::Here).await;
+                },
+                received = stream.recv() => match received {
+                    Some(Ok(Message::Text(text))) => {
+                        last_seen = Instant::now();
+                        match handle_text(&state, &ctx, list_id, &mut session, &text).await {
+                            Ok(None) => {},
+                            Ok(Some(reason)) => break Some(reason),
+                            Err(_) => break None
+                        }
+                    },
+                    Some(Ok(Message::Ping(bytes))) => {
+                        last_seen = Instant::now();
+                        if session.pong(&bytes).await.is_err() {
+                            break None;
+                        }
+                    },
+                    Some(Ok(Message::Pong(_))) => last_seen = Instant::now(),
+                    Some(Ok(Message::Close(_))) | None => break None,
+                    Some(Ok(_)) => {
+                        let error = ServerMessage::Error { id: None, status: 400, error: "Only JSON text messages are supported".to_string() };
+                        if send(&mut session, error).await.is_err() {
+                            break None;
+                        }
+                    },
+                    Some(Err(err)) => {
+                        warn!(log, "WebSocket protocol error"; "cause" => err.to_string());
+                        break Some(CloseReason { code: CloseCode::Protocol, description: None });
+                    }
+                },
+                received = events.recv() => {
+                    let sent = match received {
+                        Ok(event) if event.list_id == list_id => send(&mut session, ServerMessage::Event { event: &event }).await,
+                        Ok(_) => Ok(()),
+                        Err(RecvError::Lagged(_)) => send(&mut session, ServerMessage::Reset).await,
+                        Err(RecvError::Closed) => break None
+                    };
+                    if sent.is_err() {
+                        break None;
+                    }
+                },
+                received = presence.recv() => match received {
+                    Ok(changed) if changed == list_id => {
+                        let users = state.presence.users(list_id);
+                        if send(&mut session, ServerMessage::Presence { list_id, users }).await.is_err() {
+                            break None;
+                        }
+                    },
+                    Ok(_) | Err(RecvError::Lagged(_)) => {},
+                    Err(RecvError::Closed) => break None
+                }
+            }
+        };
+    }
+
+    announce(&state, &log, list_id, &connection_id, &ctx.actor, PresenceState::Leave).await;
+    let _ = session.close(close_reason).await;
+    info!(log, "WebSocket disconnected");
+}
+
+// 반환값: Ok(Some(..))이면 연결을 닫아야 함, Err이면 이미 닫힌 연결
+async fn handle_text(state: &web::Data<AppState>, ctx: &RequestContext, list_id: i32, session: &mut Session, text: &str) -> Result<Option<CloseReason>, actix_ws::Closed> {
+    let envelope = match serde_json::from_str::<ClientEnvelope>(text) {
+        Ok(envelope) => envelope,
+        Err(err) => {
+            send(session, ServerMessage::Error { id: None, status: 400, error: err.to_string() }).await?;
+            return Ok(None);
+        }
+    };
+
+    if envelope.v != PROTOCOL_VERSION {
+        let error = format!("Unsupported protocol version {}, expected {}", envelope.v, PROTOCOL_VERSION);
+        send(session, ServerMessage::Error { id: None, status: 400, error: error.clone() }).await?;
+        return Ok(Some(CloseReason { code: CloseCode::Unsupported, description: Some(error) }));
+    }
+
+    let (id, result) = match envelope.message {
+        ClientMessage::Ping => {
+            send(session, ServerMessage::Pong).await?;
+            return Ok(None);
+        },
+        ClientMessage::Check { id, item_id, version } => {
+            let result = check_item(state, &ctx.with_request_id(&id), list_id, item_id, version).await;
+            (id, result)
+        },
+        ClientMessage::Delete { id, item_id } => {
+            let result = delete_item(state, &ctx.with_request_id(&id), list_id, item_id).await;
+            (id, result)
+        }
+    };
+
+    match result {
+        Ok(result) => send(session, ServerMessage::Ack { id: &id, result }).await?,
+        Err(err) => send(session, ServerMessage::Error { id: Some(&id), status: err.status_code().as_u16(), error: err.message() }).await?
+    }
+
+    Ok(None)
+}
+
+// 아래 두 함수는 handlers의 check_itme, delete_item과 같은 db 함수를 사용한다
+async fn check_item(state: &web::Data<AppState>, ctx: &RequestContext, list_id: i32, item_id: i32, version: serde_json::Value) -> Result<serde_json::Value, AppError> {
+    let expected_version = body_version(Some(&version))?;
+
+    let mut client = state.pool.get().await.map_err(AppError::db_error)?;
+    // 연결한 뒤에 멤버에서 빠지거나 role이 바뀔 수 있으므로 메시지마다 확인한다
+    db::check_list_access(&client, ctx, list_id, ListAccess::Write).await?;
+
+    db::check_item(&mut client, ctx, list_id, item_id, expected_version)
+        .await
+        .map(|updated| serde_json::json!({ "success": updated.is_some(), "version": updated }))
+}
+
+async fn delete_item(state: &web::Data<AppState>, ctx: &RequestContext, list_id: i32, item_id: i32) -> Result<serde_json::Value, AppError> {
+    let mut client = state.pool.get().await.map_err(AppError::db_error)?;
+    db::check_list_access(&client, ctx, list_id, ListAccess::Write).await?;
+
+    db::delete_item(&mut client, ctx, list_id, item_id)
+        .await
+        .map(|deleted| serde_json::json!({ "success": deleted }))
+}
+
+// presence 신호는 NOTIFY로 보내서 다른 서버에 연결된 사람들도 볼 수 있게 한다
+async fn announce(state: &web::Data<AppState>, log: &Logger, list_id: i32, connection_id: &str, user: &str, presence: PresenceState) {
+    let signal = PresenceSignal {
+        list_id,
+        connection_id: connection_id.to_string(),
+        user: user.to_string(),
+        state: presence
+    };
+
+    let result = match state.pool.get().await {
+        Ok(client) => db::notify_presence(&client, &signal).await,
+        Err(err) => Err(AppError::db_error(err))
+    };
+
+    if let Err(err) = result {
+        warn!(log, "Error announcing presence"; "cause" => err.cause.clone());
+    }
+}